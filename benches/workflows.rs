@@ -0,0 +1,53 @@
+use backend::database::*;
+use backend::workflow::WorkflowArgs;
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::json;
+use tokio::runtime::Runtime;
+
+/// How many workflows the database holds
+const WORKFLOWS: usize = 1000;
+
+/// Opens a new in-memory database holding WORKFLOWS workflows of seven steps each
+fn setup(runtime: &Runtime) -> DocID {
+    return runtime.block_on(async {
+        open_database(DatabaseLocation::InMemory).unwrap();
+        setup_database().await.unwrap();
+        let mut last_id = 0;
+        for i in 0..WORKFLOWS {
+            let args: WorkflowArgs = serde_json::from_value(json!({
+                "Title": format!("Benchmark {i}"),
+                "WorkflowSteps": [
+                    { "WorkflowStepID": 0 },
+                    { "WorkflowStepID": 1 },
+                    { "WorkflowStepID": 2 },
+                    { "WorkflowStepID": 3 },
+                    { "WorkflowStepID": 4 },
+                    { "WorkflowStepID": 5, "NumCores": 4 },
+                    { "WorkflowStepID": 6, "MachineCount": 2 },
+                ],
+            })).unwrap();
+            last_id = insert_workflow(args).await.unwrap();
+        }
+        return last_id;
+    });
+}
+
+/// Lists every workflow, both from the database and from the cache,
+/// and loads a single workflow from the database
+fn bench_workflows(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let id = setup(&runtime);
+    assert_eq!(runtime.block_on(query_workflows()).unwrap().len(), WORKFLOWS);
+
+    let mut group = c.benchmark_group(format!("{WORKFLOWS} workflows"));
+    group.bench_function("list from database", |b| b.to_async(&runtime)
+        .iter(|| async { repository().unwrap().query_workflows().await.unwrap() }));
+    group.bench_function("list from cache", |b| b.to_async(&runtime)
+        .iter(|| async { query_workflows().await.unwrap() }));
+    group.bench_function("find from database", |b| b.to_async(&runtime)
+        .iter(|| async { repository().unwrap().find_workflow(id).await.unwrap() }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_workflows);
+criterion_main!(benches);
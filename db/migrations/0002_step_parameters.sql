@@ -0,0 +1,8 @@
+-- Generic parameters of an assigned workflow step, stored as name/value pairs
+CREATE TABLE IF NOT EXISTS assigned_workflow_step_param (
+    assigned_workflow_step_id INTEGER,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (assigned_workflow_step_id, name),
+    FOREIGN KEY (assigned_workflow_step_id) REFERENCES assigned_workflow_step(id)
+);
@@ -0,0 +1,4 @@
+-- How the pages of a print job are laid out and how many copies are printed
+ALTER TABLE printjob ADD COLUMN page_size TEXT NOT NULL DEFAULT 'Letter';
+ALTER TABLE printjob ADD COLUMN duplex INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE printjob ADD COLUMN copies INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1 @@
+ALTER TABLE simulation_report ADD COLUMN overrides TEXT NOT NULL DEFAULT '[]'; -- JSON list of the what-if overrides used
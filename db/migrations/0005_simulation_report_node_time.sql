@@ -0,0 +1,11 @@
+-- Create a table to track when each node of a workflow ran in a simulation
+CREATE TABLE IF NOT EXISTS simulation_report_node_time (
+  simulation_report_id INTEGER,
+  node_index INTEGER,
+  workflow_step_id INTEGER,
+  start_time INTEGER,
+  end_time INTEGER,
+  PRIMARY KEY (simulation_report_id, node_index),
+  FOREIGN KEY (simulation_report_id) REFERENCES simulation_report(id),
+  FOREIGN KEY (workflow_step_id) REFERENCES workflow_step(id)
+);
@@ -0,0 +1,24 @@
+-- JSON copies of the print job and workflow as they were simulated, so that the
+-- report outlives them. printjobID and workflowID are therefore no longer foreign keys.
+-- SQLite can't drop a foreign key, so the table is copied without them. The checks of
+-- the tables referring to it are deferred until its rows are back
+PRAGMA defer_foreign_keys = ON;
+
+CREATE TEMP TABLE simulation_report_copy AS SELECT * FROM simulation_report;
+DROP TABLE simulation_report;
+
+CREATE TABLE simulation_report (
+    id INTEGER PRIMARY KEY,
+    title TEXT NOT NULL,
+    creation_time INTEGER,
+    total_time_taken INTEGER,
+    printjobID INTEGER NOT NULL,
+    workflowID INTEGER NOT NULL,
+    overrides TEXT NOT NULL DEFAULT '[]', -- JSON list of the what-if overrides used
+    print_job_snapshot TEXT,
+    workflow_snapshot TEXT
+);
+
+INSERT INTO simulation_report (id, title, creation_time, total_time_taken, printjobID, workflowID, overrides)
+SELECT id, title, creation_time, total_time_taken, printjobID, workflowID, overrides FROM simulation_report_copy;
+DROP TABLE simulation_report_copy;
@@ -0,0 +1 @@
+ALTER TABLE simulation_report ADD COLUMN rerun_of INTEGER; -- the report this one re-ran, if any
@@ -0,0 +1,2 @@
+-- Workflow steps are defined in workflow_steps.json, and copied here on startup
+ALTER TABLE workflow_step ADD COLUMN title TEXT;
@@ -0,0 +1,7 @@
+-- The number of RIPs is now the NumCores parameter of the rasterization step.
+-- Each assigned step keeps the number it was last given
+INSERT OR REPLACE INTO assigned_workflow_step_param (assigned_workflow_step_id, name, value)
+SELECT assigned_workflow_step_id, 'NumCores', CAST(num_of_RIPs AS TEXT) FROM rasterization_params
+WHERE id IN (SELECT MAX(id) FROM rasterization_params WHERE num_of_RIPs IS NOT NULL GROUP BY assigned_workflow_step_id);
+
+DROP TABLE IF EXISTS rasterization_params;
@@ -0,0 +1,129 @@
+-- The same schema as db/migrations/0001_create_database.sql, for PostgreSQL.
+-- IDs are BIGINT, since PostgreSQL has no unsigned integers
+
+-- Create a table to define detailed error reports
+CREATE TABLE IF NOT EXISTS errors_detailed (
+  id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  date_occured BIGINT,
+  status BIGINT,
+  domain TEXT NOT NULL,
+  request TEXT NOT NULL,
+  method TEXT NOT NULL,
+  response TEXT NOT NULL
+);
+
+-- Limit the number of records in the error table to 128
+CREATE FUNCTION ensure_max_rows() RETURNS TRIGGER AS $$
+BEGIN
+    IF (SELECT COUNT(*) FROM errors_detailed) > 128 THEN
+        DELETE FROM errors_detailed WHERE id = (SELECT id FROM errors_detailed ORDER BY id ASC LIMIT 1);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER ensure_max_rows
+AFTER INSERT ON errors_detailed
+FOR EACH ROW EXECUTE FUNCTION ensure_max_rows();
+
+-- Create a table to define rasterization profiles
+CREATE TABLE IF NOT EXISTS rasterization_profile (
+   id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+   title TEXT NOT NULL,
+   profile TEXT NOT NULL
+);
+
+-- Create a table to define print jobs
+CREATE TABLE IF NOT EXISTS printjob (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    title TEXT NOT NULL,
+    creation_time BIGINT,
+    page_count BIGINT,
+    rasterization_profile_id BIGINT,
+    FOREIGN KEY (rasterization_profile_id) REFERENCES rasterization_profile(id)
+);
+
+-- Create a table to define workflows
+CREATE TABLE IF NOT EXISTS workflow (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    title TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS workflow_step (
+    id BIGINT PRIMARY KEY
+);
+
+-- Create a table to define simulation reports
+CREATE TABLE IF NOT EXISTS simulation_report (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    title TEXT NOT NULL,
+    creation_time BIGINT,
+    total_time_taken BIGINT,
+    printjobID BIGINT NOT NULL,
+    workflowID BIGINT NOT NULL,
+    FOREIGN KEY (printjobID) REFERENCES printjob(id),
+    FOREIGN KEY (workflowID) REFERENCES workflow(id)
+);
+
+-- A workflow step which is assigned to a specific workflow
+CREATE TABLE IF NOT EXISTS assigned_workflow_step (
+   id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+   workflow_id BIGINT,
+   workflow_step_id BIGINT,
+   FOREIGN KEY (workflow_id) REFERENCES workflow(id),
+   FOREIGN KEY (workflow_step_id) REFERENCES workflow_step(id)
+);
+
+-- Create a table to track how long each simulated step took
+CREATE TABLE IF NOT EXISTS simulation_report_step_time (
+  simulation_report_id BIGINT,
+  workflow_step_id BIGINT,
+  step_time BIGINT,
+  PRIMARY KEY (simulation_report_id, workflow_step_id),
+  FOREIGN KEY (simulation_report_id) REFERENCES simulation_report(id),
+  FOREIGN KEY (workflow_step_id) REFERENCES workflow_step(id)
+);
+
+-- Create a table to track workflow steps which are part of a workflow
+CREATE TABLE IF NOT EXISTS next_workflow_step (
+    assigned_workflow_step_id BIGINT,  -- the id of the assigned workflow step this is
+    next_step_id BIGINT,                 -- which workflow step comes next.
+    PRIMARY KEY (assigned_workflow_step_id, next_step_id),
+    FOREIGN KEY (next_step_id) REFERENCES assigned_workflow_step(id),
+    FOREIGN KEY (assigned_workflow_step_id) REFERENCES assigned_workflow_step(id)
+);
+
+CREATE TABLE IF NOT EXISTS prev_workflow_step (
+    assigned_workflow_step_id BIGINT,  -- the id of the assigned workflow step this is
+    prev_step_id BIGINT,                 -- which workflow step came last.
+    PRIMARY KEY (assigned_workflow_step_id, prev_step_id),
+    FOREIGN KEY (prev_step_id) REFERENCES assigned_workflow_step(id),
+    FOREIGN KEY (assigned_workflow_step_id) REFERENCES assigned_workflow_step(id)
+);
+
+--- Create a table to track workflow step results.
+CREATE TABLE IF NOT EXISTS ran_workflow_step (
+   workflow_step_id BIGINT,
+   simulation_report_id BIGINT,
+   time_taken BIGINT,
+   PRIMARY KEY (workflow_step_id, simulation_report_id),
+   FOREIGN KEY (workflow_step_id) REFERENCES assigned_workflow_step(id),
+   FOREIGN KEY (simulation_report_id) REFERENCES simulation_report(id)
+);
+
+--- Workflow Step Paramaters
+CREATE TABLE IF NOT EXISTS rasterization_params (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    assigned_workflow_step_id BIGINT,
+    num_of_RIPs BIGINT,
+    FOREIGN KEY (assigned_workflow_step_id) REFERENCES assigned_workflow_step(id)
+);
+
+-- Create table to keep track of user login information. "user" is a reserved word
+CREATE TABLE IF NOT EXISTS "user" (
+    user_id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    password_hash BYTEA NOT NULL, -- use a secure hashing function for passwords, should be handled by backend
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- Create a table to track when each node of a workflow ran in a simulation
+CREATE TABLE IF NOT EXISTS simulation_report_node_time (
+  simulation_report_id BIGINT,
+  node_index BIGINT,
+  workflow_step_id BIGINT,
+  start_time BIGINT,
+  end_time BIGINT,
+  PRIMARY KEY (simulation_report_id, node_index),
+  FOREIGN KEY (simulation_report_id) REFERENCES simulation_report(id),
+  FOREIGN KEY (workflow_step_id) REFERENCES workflow_step(id)
+);
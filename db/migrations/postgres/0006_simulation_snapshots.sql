@@ -0,0 +1,6 @@
+-- JSON copies of the print job and workflow as they were simulated, so that the
+-- report outlives them. printjobID and workflowID are therefore no longer foreign keys.
+ALTER TABLE simulation_report DROP CONSTRAINT simulation_report_printjobid_fkey;
+ALTER TABLE simulation_report DROP CONSTRAINT simulation_report_workflowid_fkey;
+ALTER TABLE simulation_report ADD COLUMN print_job_snapshot TEXT;
+ALTER TABLE simulation_report ADD COLUMN workflow_snapshot TEXT;
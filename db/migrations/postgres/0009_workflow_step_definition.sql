@@ -0,0 +1,2 @@
+-- Custom steps created through the API are only stored here
+ALTER TABLE workflow_step ADD COLUMN definition TEXT; -- JSON catalog entry the step was last loaded with
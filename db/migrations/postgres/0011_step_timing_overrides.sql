@@ -0,0 +1,3 @@
+-- Override the catalog's timing for an assigned step when not NULL
+ALTER TABLE assigned_workflow_step ADD COLUMN setup_time BIGINT;
+ALTER TABLE assigned_workflow_step ADD COLUMN time_per_page BIGINT;
@@ -0,0 +1,6 @@
+-- Deleted items are moved to the trash, where they can be restored until they
+-- are purged. deleted_at is when the item was moved there, or NULL if it wasn't
+ALTER TABLE rasterization_profile ADD COLUMN deleted_at BIGINT;
+ALTER TABLE printjob ADD COLUMN deleted_at BIGINT;
+ALTER TABLE workflow ADD COLUMN deleted_at BIGINT;
+ALTER TABLE simulation_report ADD COLUMN deleted_at BIGINT;
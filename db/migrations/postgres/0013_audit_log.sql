@@ -0,0 +1,16 @@
+-- Every change made to the stored data, oldest first
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    time BIGINT NOT NULL,
+    entity TEXT NOT NULL, -- the type of the changed item, e.g. PrintJob
+    entity_id BIGINT NOT NULL,
+    action TEXT NOT NULL, -- Insert, Update, Delete, Restore or Purge
+    actor TEXT, -- who made the change, once requests are authenticated
+    -- JSON copies of the item before and after the change. before_json is NULL
+    -- for an insert, and after_json is NULL for a delete
+    before_json TEXT,
+    after_json TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_entity ON audit_log (entity, entity_id);
+CREATE INDEX IF NOT EXISTS audit_log_time ON audit_log (time);
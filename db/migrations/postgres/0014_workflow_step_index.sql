@@ -0,0 +1,3 @@
+-- Workflows are loaded along with their assigned steps in one query, which
+-- looks the steps up by workflow
+CREATE INDEX IF NOT EXISTS assigned_workflow_step_workflow ON assigned_workflow_step (workflow_id);
@@ -0,0 +1,6 @@
+-- whip up some of the data that can't be created from the GUI
+INSERT INTO rasterization_profile (title, profile) VALUES ('BW', 'Standard Color Profile');
+INSERT INTO rasterization_profile (title, profile) VALUES ('CMY', 'Standard Color Profile');
+INSERT INTO rasterization_profile (title, profile) VALUES ('CMYK', 'Standard Color Profile');
+INSERT INTO rasterization_profile (title, profile) VALUES ('High Quality (Best Detail)', 'Specialized Profile');
+INSERT INTO rasterization_profile (title, profile) VALUES ('Line Art (Crisp Lines, No Gradients)', 'Specialized Profile');
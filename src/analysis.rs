@@ -0,0 +1,464 @@
+use crate::database::*;
+use crate::simulation::*;
+use crate::workflow::*;
+use crate::workflow_steps::*;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+
+/// How a step of a simulation report performed
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct StepAnalysis {
+    pub NodeIndex: usize,
+    pub WorkflowStepID: DocID,
+    pub Title: String,
+    pub StartTime: u32,
+    pub EndTime: u32,
+    pub Duration: u32,
+    /// Fraction of the total time this step was busy, [0,1]
+    pub Utilization: f64,
+    /// Would finishing this step sooner finish the whole workflow sooner?
+    pub OnCriticalPath: bool,
+}
+
+/// The kinds of changes the analysis knows how to try
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum RecommendationKind {
+    AddRasterizationCores,
+    AddMachine,
+    MoveOffCriticalPath,
+}
+
+/// A change to the workflow, along with the time it would have saved
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    pub Kind: RecommendationKind,
+    pub NodeIndex: usize,
+    pub Description: String,
+    /// The override that reproduces this change in a new simulation, if it can be
+    /// expressed as one
+    pub Override: Option<StepOverride>,
+    pub EstimatedTotalTime: u32,
+    pub EstimatedTimeSaved: u32,
+}
+
+/// Bottleneck analysis of a simulation report
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationAnalysis {
+    pub SimulationReportID: DocID,
+    pub TotalTimeTaken: u32,
+    /// Node index of the longest step on the critical path
+    pub Bottleneck: Option<usize>,
+    pub Steps: Vec<StepAnalysis>,
+    /// Ranked from the most time saved to the least
+    pub Recommendations: Vec<Recommendation>,
+}
+
+/// Analyzes a simulation report, finding its bottleneck and ranking changes
+/// to the workflow by how much time they would save. Each recommendation is
+/// estimated by simulating the report's print job and workflow again, with
+/// the same overrides plus the recommended change
+pub async fn analyze_simulation_report(id: DocID) -> Result<SimulationAnalysis, CustomError> {
+    let report = find_simulation_report(id).await?;
+    let print_job = report.print_job().await?;
+    let workflow = report.workflow().await?;
+
+    let base = simulate_workflow(&print_job, &workflow, &report.Overrides).await?;
+    let critical = critical_path(&workflow, &base);
+    let steps: Vec<StepAnalysis> = base.node_times.iter()
+        .map(|time| StepAnalysis {
+            NodeIndex: time.NodeIndex,
+            WorkflowStepID: time.WorkflowStepID,
+            Title: workflow.Steps[time.NodeIndex].title(),
+            StartTime: time.StartTime,
+            EndTime: time.EndTime,
+            Duration: time.EndTime - time.StartTime,
+            Utilization: match base.total_time {
+                0 => 0.0,
+                total => (time.EndTime - time.StartTime) as f64 / total as f64,
+            },
+            OnCriticalPath: critical.contains(&time.NodeIndex),
+        })
+        .collect();
+    let bottleneck = steps.iter()
+        .filter(|step| step.OnCriticalPath)
+        .max_by_key(|step| step.Duration)
+        .map(|step| step.NodeIndex);
+
+    let mut recommendations = Vec::<Recommendation>::new();
+    for &index in &critical {
+        for candidate in candidates(&workflow, index) {
+            let result = match &candidate.change {
+                Change::Override(step_override) => {
+                    let mut overrides = report.Overrides.clone();
+                    overrides.push(step_override.clone());
+                    simulate_workflow(&print_job, &workflow, &overrides).await
+                },
+                Change::Workflow(changed) => simulate_workflow(&print_job, changed, &report.Overrides).await,
+            };
+            // A change the workflow can't take, e.g. past a parameter's maximum, isn't worth recommending
+            let total = match result {
+                Ok(result) => result.total_time,
+                Err(_) => continue,
+            };
+            if total < base.total_time {
+                recommendations.push(Recommendation {
+                    Kind: candidate.kind,
+                    NodeIndex: index,
+                    Description: candidate.description,
+                    Override: match candidate.change {
+                        Change::Override(step_override) => Some(step_override),
+                        Change::Workflow(_) => None,
+                    },
+                    EstimatedTotalTime: total,
+                    EstimatedTimeSaved: base.total_time - total,
+                });
+            }
+        }
+    }
+    recommendations.sort_by(|a, b| b.EstimatedTimeSaved.cmp(&a.EstimatedTimeSaved)
+        .then(a.NodeIndex.cmp(&b.NodeIndex)));
+
+    return Ok(SimulationAnalysis {
+        SimulationReportID: id,
+        TotalTimeTaken: base.total_time,
+        Bottleneck: bottleneck,
+        Steps: steps,
+        Recommendations: recommendations,
+    });
+}
+
+/// Finds the nodes on the critical path by walking back from the steps that
+/// finish last, through whichever previous steps they were waiting on
+fn critical_path(workflow: &Workflow, result: &SimulationResult) -> Vec<usize> {
+    let times = &result.node_times;
+    let mut critical = HashSet::<usize>::new();
+    let mut stack: Vec<usize> = times.iter()
+        .filter(|time| time.EndTime == result.total_time && result.total_time > 0)
+        .map(|time| time.NodeIndex)
+        .collect();
+
+    while let Some(index) = stack.pop() {
+        if !critical.insert(index) {
+            continue;
+        }
+        let start = times[index].StartTime;
+        for &prev in &workflow.Steps[index].prev {
+            if start > 0 && times[prev].EndTime == start {
+                stack.push(prev);
+            }
+        }
+    }
+
+    let mut critical: Vec<usize> = critical.into_iter().collect();
+    critical.sort();
+    return critical;
+}
+
+/// A change to try on a workflow
+enum Change {
+    Override(StepOverride),
+    Workflow(Workflow),
+}
+
+struct Candidate {
+    kind: RecommendationKind,
+    description: String,
+    change: Change,
+}
+
+/// Lists the changes worth trying for a node on the critical path
+fn candidates(workflow: &Workflow, index: usize) -> Vec<Candidate> {
+    let node = &workflow.Steps[index];
+    let title = node.title();
+    let mut output = Vec::<Candidate>::new();
+    let node_override = |name: &str, value: u32| StepOverride {
+        WorkflowStepID: None,
+        NodeIndex: Some(index),
+        SetupTime: None,
+        TimePerPage: None,
+        Parameters: HashMap::from([(name.to_string(), json!(value))]),
+    };
+
+    let doubled_cores = node.parameter::<u32>(NUM_CORES_PARAM)
+        .and_then(|num_cores| num_cores.checked_mul(2).map(|doubled| (num_cores, doubled)));
+    if let Some((num_cores, doubled)) = doubled_cores {
+        output.push(Candidate {
+            kind: RecommendationKind::AddRasterizationCores,
+            description: format!("Increase {title} from {num_cores} to {doubled} cores"),
+            change: Change::Override(node_override(NUM_CORES_PARAM, doubled)),
+        });
+    }
+
+    let added_machine = node.machine_count.checked_add(1);
+    if let (Some(count), true) = (added_machine, node.data.parallelism() != Parallelism::None) {
+        output.push(Candidate {
+            kind: RecommendationKind::AddMachine,
+            description: format!("Run {title} on {count} machines in parallel"),
+            change: Change::Override(node_override(MACHINE_COUNT_PARAM, count)),
+        });
+    }
+
+    if let (WFSVariant::Metrics, false) = (node.data, node.next.is_empty()) {
+        output.push(Candidate {
+            kind: RecommendationKind::MoveOffCriticalPath,
+            description: format!("Run {title} alongside the steps after it, instead of before them"),
+            change: Change::Workflow(move_off_critical_path(workflow, index)),
+        });
+    }
+
+    return output;
+}
+
+/// Turns a node into a side branch: the steps after it wait on the steps
+/// before it instead, so it no longer holds them up
+fn move_off_critical_path(workflow: &Workflow, index: usize) -> Workflow {
+    let mut changed = workflow.clone();
+    let prev = changed.Steps[index].prev.clone();
+    let next = std::mem::take(&mut changed.Steps[index].next);
+
+    for &n in &next {
+        changed.Steps[n].prev.retain(|&p| p != index);
+        for &p in &prev {
+            if p != n && !changed.Steps[n].prev.contains(&p) {
+                changed.Steps[n].prev.push(p);
+            }
+        }
+    }
+    for &p in &prev {
+        for &n in &next {
+            if p != n && !changed.Steps[p].next.contains(&n) {
+                changed.Steps[p].next.push(n);
+            }
+        }
+    }
+
+    return changed;
+}
+
+/// The change in a value between two simulation reports
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct Delta {
+    pub A: Option<u32>,
+    pub B: Option<u32>,
+    /// B - A, when both reports have a value
+    pub Delta: Option<i64>,
+    /// The delta as a percentage of A, when A is non-zero
+    pub PercentDelta: Option<f64>,
+}
+
+impl Delta {
+    fn new(a: Option<u32>, b: Option<u32>) -> Delta {
+        let delta = match (a, b) {
+            (Some(a), Some(b)) => Some(b as i64 - a as i64),
+            _ => None,
+        };
+        return Delta {
+            A: a,
+            B: b,
+            Delta: delta,
+            PercentDelta: match (a, delta) {
+                (Some(a), Some(delta)) if a > 0 => Some(delta as f64 * 100.0 / a as f64),
+                _ => None,
+            },
+        };
+    }
+}
+
+/// Time taken by one type of workflow step in each report
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct StepComparison {
+    pub WorkflowStepID: DocID,
+    pub Title: String,
+    pub Time: Delta,
+}
+
+/// Time taken by one node of the workflows in each report. Nodes are aligned
+/// by their index, and only when both reports ran the same step there
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeComparison {
+    pub NodeIndex: usize,
+    pub WorkflowStepID: DocID,
+    pub Title: String,
+    pub Time: Delta,
+}
+
+/// Side by side comparison of two simulation reports
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationComparison {
+    pub SimulationReportA: DocID,
+    pub SimulationReportB: DocID,
+    pub SamePrintJob: bool,
+    pub SameWorkflow: bool,
+    pub SameRasterizationProfile: bool,
+    pub SameOverrides: bool,
+    pub TotalTime: Delta,
+    pub Steps: Vec<StepComparison>,
+    pub Nodes: Vec<NodeComparison>,
+}
+
+/// Compares simulation report `b` against simulation report `a`
+pub async fn compare_simulation_reports(a: DocID, b: DocID) -> Result<SimulationComparison, CustomError> {
+    let report_a = find_simulation_report(a).await?;
+    let report_b = find_simulation_report(b).await?;
+
+    // The print jobs and workflows are compared as they were simulated, since a
+    // workflow's steps may have changed in between, or an ID may have been reused
+    let print_job_a = report_a.print_job().await.ok();
+    let print_job_b = report_b.print_job().await.ok();
+    let workflow_a = report_a.workflow().await.ok();
+    let workflow_b = report_b.workflow().await.ok();
+    let profile_a = print_job_a.as_ref().map(|pj| pj.RasterizationProfileID);
+    let profile_b = print_job_b.as_ref().map(|pj| pj.RasterizationProfileID);
+
+    let mut step_ids: Vec<DocID> = report_a.StepTimes.keys()
+        .chain(report_b.StepTimes.keys())
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    step_ids.sort();
+    let steps = step_ids.into_iter()
+        .map(|id| StepComparison {
+            WorkflowStepID: id,
+            Title: step_title(&[&report_a, &report_b], id),
+            Time: Delta::new(report_a.StepTimes.get(&id).copied(), report_b.StepTimes.get(&id).copied()),
+        })
+        .collect();
+
+    let mut nodes = Vec::<NodeComparison>::new();
+    for i in 0..report_a.NodeTimes.len().max(report_b.NodeTimes.len()) {
+        let node_a = report_a.NodeTimes.get(i);
+        let node_b = report_b.NodeTimes.get(i);
+        let duration = |n: &NodeTime| n.EndTime - n.StartTime;
+        match (node_a, node_b) {
+            (Some(na), Some(nb)) if na.WorkflowStepID == nb.WorkflowStepID => nodes.push(NodeComparison {
+                NodeIndex: i,
+                WorkflowStepID: na.WorkflowStepID,
+                Title: step_title(&[&report_a, &report_b], na.WorkflowStepID),
+                Time: Delta::new(Some(duration(na)), Some(duration(nb))),
+            }),
+            _ => {
+                for (node, in_a) in [(node_a, true), (node_b, false)] {
+                    if let Some(n) = node {
+                        let report = if in_a { &report_a } else { &report_b };
+                        nodes.push(NodeComparison {
+                            NodeIndex: i,
+                            WorkflowStepID: n.WorkflowStepID,
+                            Title: step_title(&[report], n.WorkflowStepID),
+                            Time: match in_a {
+                                true => Delta::new(Some(duration(n)), None),
+                                false => Delta::new(None, Some(duration(n))),
+                            },
+                        });
+                    }
+                }
+            },
+        }
+    }
+
+    return Ok(SimulationComparison {
+        SimulationReportA: a,
+        SimulationReportB: b,
+        SamePrintJob: same_simulated(print_job_a, print_job_b, &["id", "DateCreated"]),
+        SameWorkflow: same_simulated(workflow_a, workflow_b, &["id"]),
+        SameRasterizationProfile: profile_a.is_some() && profile_a == profile_b,
+        SameOverrides: report_a.Overrides == report_b.Overrides,
+        TotalTime: Delta::new(Some(report_a.TotalTimeTaken), Some(report_b.TotalTimeTaken)),
+        Steps: steps,
+        Nodes: nodes,
+    });
+}
+
+/// Whether two print jobs or workflows were simulated alike, ignoring the
+/// given fields which only tell stored items apart
+fn same_simulated<T: Serialize>(a: Option<T>, b: Option<T>, ignored: &[&str]) -> bool {
+    let simulated = |item: Option<T>| item
+        .and_then(|item| serde_json::to_value(item).ok())
+        .map(|mut value| {
+            if let Some(fields) = value.as_object_mut() {
+                fields.retain(|name, _| !ignored.contains(&name.as_str()));
+            }
+            return value;
+        });
+    let a = simulated(a);
+    return a.is_some() && a == simulated(b);
+}
+
+/// A re-run of a simulation report, compared against the original
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct RerunSummary {
+    pub id: DocID,
+    pub RerunOf: DocID,
+    pub UsedSnapshot: bool,
+    pub Comparison: SimulationComparison,
+}
+
+/// Simulates a report again with its original overrides, either on the current print job
+/// and workflow or on the snapshot stored in the report. The snapshot is also used when the
+/// print job or workflow has since been deleted. The new report is stored, linked to the
+/// original, and compared against it
+pub async fn rerun_simulation_report(id: DocID, use_snapshot: bool) -> Result<RerunSummary, CustomError> {
+    let original = find_simulation_report(id).await?;
+    let current = match use_snapshot {
+        true => None,
+        false => match (find_print_job(original.PrintJobID).await, find_workflow(original.WorkflowID).await) {
+            (Ok(print_job), Ok(workflow)) => Some((print_job, workflow)),
+            (Err(CustomError::NotFound), _)
+            | (_, Err(CustomError::NotFound)) => None,
+            (Err(err), _) | (_, Err(err)) => return Err(err),
+        },
+    };
+    let used_snapshot = current.is_none();
+    let (print_job, workflow) = match (current, &original.PrintJobSnapshot, &original.WorkflowSnapshot) {
+        (Some(current), _, _) => current,
+        (None, Some(print_job), Some(workflow)) => (print_job.clone(), workflow.clone()),
+        (None, _, _) if use_snapshot => return Err(CustomError::MissingSnapshot(id)),
+        (None, _, _) => return Err(CustomError::NotFound),
+    };
+    let mut report = simulate_inputs(original.PrintJobID, original.WorkflowID, print_job, workflow,
+        original.Overrides.clone()).await?;
+    report.RerunOf = Some(id);
+
+    let new_id = store_simulation_report(&report).await?;
+    return Ok(RerunSummary {
+        id: new_id,
+        RerunOf: id,
+        UsedSnapshot: used_snapshot,
+        Comparison: compare_simulation_reports(id, new_id).await?,
+    });
+}
+
+/// Title of a step as snapshotted in the first of the reports that used it,
+/// or else from the catalog
+fn step_title(reports: &[&SimulationReport], id: DocID) -> String {
+    return reports.iter()
+        .filter_map(|report| report.WorkflowSnapshot.as_ref())
+        .flat_map(|workflow| workflow.Steps.iter())
+        .find(|node| node.data.id() == id)
+        .map(|node| node.title())
+        .unwrap_or_else(|| get_variant_by_id(id).map(|v| v.title()).unwrap_or_default());
+}
+
+/// Key under which the times of steps without a stage are rolled up
+const UNSTAGED: &str = "Unstaged";
+
+/// Rolls the step times of a simulation report up into the stages of the steps
+pub async fn simulation_report_stage_times(id: DocID) -> Result<HashMap<String, u32>, CustomError> {
+    let report = find_simulation_report(id).await?;
+    let mut stage_times = HashMap::<String, u32>::new();
+    for (step_id, time) in report.StepTimes {
+        let stage = get_variant_by_id(step_id).ok().and_then(|variant| variant.stage());
+        let key = stage.map_or(UNSTAGED.to_string(), |stage| stage.to_string());
+        *stage_times.entry(key).or_default() += time;
+    }
+    return Ok(stage_times);
+}
@@ -1,23 +1,34 @@
-use crate::{database::*, workflow::*, workflow_steps::*};
+use crate::{analysis::*, backup::*, database::*, workflow::*, workflow_steps::*};
 use std::time::{SystemTime, UNIX_EPOCH};
 use axum::{
-    extract::Path,
+    body::Body,
+    extract::{rejection::JsonRejection, DefaultBodyLimit, Path, Query},
+    middleware::{self, Next},
     response::{Response, IntoResponse},
-    routing::{delete, get, post},
-    http::{StatusCode, Request},
+    routing::{delete, get, post, put},
+    http::{header, StatusCode, Request},
     Json, Router,
 };
 use http::Method;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
+/// Environment variable holding the token which requests to admin routes must
+/// send as `Authorization: Bearer <token>`. Admin routes are disabled without it
+pub const ADMIN_TOKEN_ENV: &str = "PRINTOS_ADMIN_TOKEN";
+
+/// Largest request body POST /Admin/Restore accepts, since a whole database
+/// is well over axum's default limit of 2 MB
+const RESTORE_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
 /// Builds the routes for the API.
 pub fn build_routes() -> Router {
     // https://dev.to/amaendeepm/api-development-in-rust-cors-tower-middleware-and-the-power-of-axum-397k
     let cors_layer = CorsLayer::new()
         .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers(Any);
 
     return Router::new()
@@ -29,6 +40,7 @@ pub fn build_routes() -> Router {
         .route("/PrintJob", post(post_print_job))
         .route("/PrintJob/{id}", get(get_print_job_by_id))
         .route("/PrintJob/{id}", delete(delete_print_job))
+        .route("/PrintJob/{id}/Restore", post(restore_print_job))
         // Rasterization Profile Routes
         .route("/RasterizationProfile", get(get_rasterization_profiles))
         .route(
@@ -40,14 +52,30 @@ pub fn build_routes() -> Router {
             "/RasterizationProfile/{id}",
             delete(delete_rasterization_profile),
         )
+        .route("/RasterizationProfile/{id}/Restore", post(restore_rasterization_profile))
         // Workflow Routes
         .route("/Workflow", get(get_workflows))
         .route("/Workflow", post(post_workflow))
         .route("/Workflow/{id}", get(get_workflow_by_id))
         .route("/Workflow/{id}", delete(delete_workflow))
+        .route("/Workflow/{id}/Restore", post(restore_workflow))
         // WorkflowStep Routes
         .route("/WorkflowStep", get(get_workflow_steps))
+        .route("/WorkflowStep", post(post_workflow_step))
         .route("/WorkflowStep/{id}", get(get_workflow_step_by_id))
+        .route(
+            "/WorkflowStep/{id}/Migrate",
+            post(post_workflow_step_migrate).route_layer(middleware::from_fn(require_admin)),
+        )
+        .route("/WorkflowStep/Catalog", get(get_workflow_step_catalog_entries))
+        .route(
+            "/WorkflowStep/Catalog",
+            put(put_workflow_step_catalog).route_layer(middleware::from_fn(require_admin)),
+        )
+        .route(
+            "/WorkflowStep/Catalog/Reload",
+            post(post_workflow_step_catalog_reload).route_layer(middleware::from_fn(require_admin)),
+        )
         // SimulationReport Routes
         .route("/SimulationReport", post(post_simulation_report))
         .route("/SimulationReport", get(get_simulation_reports))
@@ -57,12 +85,61 @@ pub fn build_routes() -> Router {
             "/SimulationReport/{id}/WorkflowStep/Time",
             get(get_simulation_report_workflow_steps_by_id),
         )
+        .route("/SimulationReport/{id}/Stage/Time", get(get_simulation_report_stage_times))
+        .route("/SimulationReport/{id}/Analysis", get(get_simulation_report_analysis))
+        .route("/SimulationReport/{a}/Compare/{b}", get(get_simulation_report_comparison))
+        .route("/SimulationReport/{id}/Rerun", post(post_simulation_report_rerun))
+        .route("/SimulationReport/{id}/Restore", post(restore_simulation_report))
+        // Analytics Routes
+        .route("/Analytics/Workflow", get(get_workflow_analytics))
+        .route("/Analytics/WorkflowStep", get(get_workflow_step_analytics))
+        .route("/Analytics/TimePerPage", get(get_time_per_page_analytics))
+        .route("/Analytics/Usage", get(get_usage_analytics))
+        // Audit Routes
+        .route("/Audit", get(get_audit_log).route_layer(middleware::from_fn(require_admin)))
+        // Trash Routes
+        .route("/Trash", get(get_trash))
+        // Admin Routes
+        .route("/Admin/Backup", get(get_backup).route_layer(middleware::from_fn(require_admin)))
+        .route(
+            "/Admin/Restore",
+            post(post_restore)
+                .layer(DefaultBodyLimit::max(RESTORE_BODY_LIMIT))
+                .route_layer(middleware::from_fn(require_admin)),
+        )
+        // Metrics Routes
+        .route("/Metrics/Database", get(get_database_metrics))
         // Fallback
         .fallback(endpoint_not_found)
         // CORS
         .layer(ServiceBuilder::new().layer(cors_layer));
 }
 
+/// Only lets a request through to an admin route if it sends the token
+/// configured in PRINTOS_ADMIN_TOKEN. Every request is refused when no token is set.
+///
+/// ### Arguments
+/// * `request` - The request to an admin route.
+/// * `next` - The route's handler.
+///
+/// ### Returns
+/// The handler's response, 401 if the token is missing or wrong, or 403 if admin routes are disabled.
+async fn require_admin(request: Request<Body>, next: Next) -> Response {
+    let token = match std::env::var(ADMIN_TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => token,
+        _ => return response(403, format!("Admin routes are disabled. Set {ADMIN_TOKEN_ENV} to enable them.")),
+    };
+    let sent = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Digests are compared, so the time taken doesn't tell how much of the token was right
+    return match sent {
+        Some(sent) if Sha256::digest(sent) == Sha256::digest(&token) => next.run(request).await,
+        _ => response(401, "A valid admin token is required.".to_string()),
+    };
+}
+
 /// Returns a sanitized tuple of the response code and message.
 ///
 /// ### Arguments
@@ -180,8 +257,151 @@ async fn get_workflows() -> Response {
     };
 }
 
-async fn get_workflow_steps() -> Response {
-    return response(200, json!(get_all_workflow_steps().await).to_string());
+/// Returns the workflow steps which can be added to a workflow.
+///
+/// ### Arguments
+/// * `filter` - Optional stage and category the steps must be in.
+///
+/// ### Returns
+/// The matching workflow steps.
+async fn get_workflow_steps(Query(filter): Query<WorkflowStepFilter>) -> Response {
+    return match get_filtered_workflow_steps(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting workflow steps.".to_string(),
+            "/WorkflowStep".to_string(),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+
+/// Creates a custom workflow step.
+///
+/// ### Arguments
+/// * `payload` - The title, timings, compatible steps and parameters of the step.
+///
+/// ### Returns
+/// The new workflow step.
+async fn post_workflow_step(Json(payload): Json<CustomWorkflowStepArgs>) -> Response {
+    return match create_custom_workflow_step(payload.clone()).await {
+        Ok(data) => response(201, json!(data).to_string()),
+        Err(err @ CustomError::InvalidCatalog(_)) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            "/WorkflowStep".to_string(),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
+        Err(err) => error_response(
+            500,
+            err.to_string(),
+            "An error occurred creating the workflow step.".to_string(),
+            "/WorkflowStep".to_string(),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
+    };
+}
+
+/// Moves every assigned step of a deprecated workflow step to a replacement step.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the deprecated Workflow Step.
+/// * `payload` - The ID of the replacement Workflow Step.
+///
+/// ### Returns
+/// How many assigned steps were moved, and how many of their parameters were removed.
+async fn post_workflow_step_migrate(Path(id_str): Path<String>, Json(payload): Json<MigrateWorkflowStepArgs>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
+        Err(e) => return error_response(
+            400,
+            e.to_string(),
+            format!("Invalid ID: {id_str}"),
+            format!("/WorkflowStep/{id_str}/Migrate"),
+            "POST".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    return match migrate_workflow_step(id, payload.ReplacementID).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(err @ CustomError::InvalidMigration(_)) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            format!("/WorkflowStep/{id_str}/Migrate"),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
+        Err(err) => error_response(
+            500,
+            err.to_string(),
+            format!("An error occurred migrating the workflow step with id {id_str}"),
+            format!("/WorkflowStep/{id_str}/Migrate"),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
+    };
+}
+
+/// Returns the workflow step catalog, as it is stored in its file.
+async fn get_workflow_step_catalog_entries() -> Response {
+    return response(200, json!(get_workflow_step_catalog()).to_string());
+}
+
+/// Replaces the workflow step catalog and saves it to its file.
+///
+/// ### Arguments
+/// * `payload` - The list of catalog entries.
+///
+/// ### Returns
+/// The workflow steps of the new catalog.
+async fn put_workflow_step_catalog(Json(payload): Json<Vec<WFSAttributes>>) -> Response {
+    let result = match replace_workflow_step_catalog(payload.clone()).await {
+        Ok(()) => get_all_workflow_steps().await,
+        Err(err) => Err(err),
+    };
+    return match result {
+        Ok(steps) => response(200, json!(steps).to_string()),
+        Err(err) => catalog_error_response(err, "PUT", serde_json::to_string(&payload).unwrap_or("".to_string())).await,
+    };
+}
+
+/// Reloads the workflow step catalog from its file.
+///
+/// ### Returns
+/// The workflow steps of the reloaded catalog.
+async fn post_workflow_step_catalog_reload() -> Response {
+    let result = match reload_workflow_step_catalog().await {
+        Ok(()) => get_all_workflow_steps().await,
+        Err(err) => Err(err),
+    };
+    return match result {
+        Ok(steps) => response(200, json!(steps).to_string()),
+        Err(err) => catalog_error_response(err, "POST", "".to_string()).await,
+    };
+}
+
+/// Returns the error response for a catalog which could not be loaded.
+/// The catalog in use is left unchanged.
+async fn catalog_error_response(err: CustomError, method: &str, request: String) -> Response {
+    let (code, message) = match err {
+        CustomError::InvalidCatalog(_) => (422, err.to_string()),
+        CustomError::WorkflowStepInUse(_) => (409, err.to_string()),
+        _ => (500, "An error occurred loading the workflow step catalog.".to_string()),
+    };
+    return error_response(
+        code,
+        err.to_string(),
+        message,
+        "/WorkflowStep/Catalog".to_string(),
+        method.to_string(),
+        request,
+    ).await;
 }
 
 async fn get_simulation_reports() -> Response {
@@ -198,6 +418,168 @@ async fn get_simulation_reports() -> Response {
     };
 }
 
+/// Returns total time statistics for each workflow's simulations.
+///
+/// ### Arguments
+/// * `filter` - Optional `From` and `To` creation times.
+async fn get_workflow_analytics(Query(filter): Query<AnalyticsFilter>) -> Response {
+    return match query_workflow_time_statistics(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting workflow analytics.".to_string(),
+            "/Analytics/Workflow".to_string(),
+            "GET".to_string(),
+            json!(filter).to_string(),
+        ).await,
+    };
+}
+
+/// Returns time statistics for each type of workflow step across simulations.
+///
+/// ### Arguments
+/// * `filter` - Optional `From` and `To` creation times.
+async fn get_workflow_step_analytics(Query(filter): Query<AnalyticsFilter>) -> Response {
+    return match query_workflow_step_time_statistics(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting workflow step analytics.".to_string(),
+            "/Analytics/WorkflowStep".to_string(),
+            "GET".to_string(),
+            json!(filter).to_string(),
+        ).await,
+    };
+}
+
+/// Returns the average time per page of simulations over time.
+///
+/// ### Arguments
+/// * `filter` - Optional `From` and `To` creation times, and the `Interval` in seconds.
+async fn get_time_per_page_analytics(Query(filter): Query<AnalyticsFilter>) -> Response {
+    return match query_time_per_page_trend(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting time per page analytics.".to_string(),
+            "/Analytics/TimePerPage".to_string(),
+            "GET".to_string(),
+            json!(filter).to_string(),
+        ).await,
+    };
+}
+
+/// Returns the state of the database connection pool.
+async fn get_database_metrics() -> Response {
+    return match pool_metrics() {
+        Ok(metrics) => response(200, json!(metrics).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting database metrics.".to_string(),
+            "/Metrics/Database".to_string(),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+
+/// Returns a backup of the database, as a versioned JSON archive.
+async fn get_backup() -> Response {
+    return match create_backup().await {
+        Ok(archive) => response(200, json!(archive).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred backing up the database.".to_string(),
+            "/Admin/Backup".to_string(),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+
+/// Restores a backup made by GET /Admin/Backup.
+///
+/// ### Arguments
+/// * `args` - Optional `Mode`, `Replace` to replace the data in the database or `Merge` to add to it.
+/// * `payload` - The archive to restore.
+///
+/// ### Returns
+/// How many rows were restored into each table.
+async fn post_restore(Query(args): Query<RestoreArgs>, payload: Result<Json<BackupArchive>, JsonRejection>) -> Response {
+    // Archives are large, so they aren't copied into the error log
+    let archive = match payload {
+        Ok(Json(archive)) => archive,
+        Err(e) => return error_response(
+            422,
+            e.body_text(),
+            e.body_text(),
+            "/Admin/Restore".to_string(),
+            "POST".to_string(),
+            json!(args).to_string(),
+        ).await,
+    };
+    return match restore_backup(archive, args.Mode).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(err @ CustomError::InvalidBackup(_)) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            "/Admin/Restore".to_string(),
+            "POST".to_string(),
+            json!(args).to_string(),
+        ).await,
+        Err(err) => error_response(
+            500,
+            err.to_string(),
+            "An error occurred restoring the backup.".to_string(),
+            "/Admin/Restore".to_string(),
+            "POST".to_string(),
+            json!(args).to_string(),
+        ).await,
+    };
+}
+
+/// Returns the most used workflows and rasterization profiles.
+///
+/// ### Arguments
+/// * `filter` - Optional `From` and `To` creation times, and the `Limit` of each list.
+async fn get_usage_analytics(Query(filter): Query<AnalyticsFilter>) -> Response {
+    return match query_usage(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting usage analytics.".to_string(),
+            "/Analytics/Usage".to_string(),
+            "GET".to_string(),
+            json!(filter).to_string(),
+        ).await,
+    };
+}
+
+/// Returns the changes recorded in the audit log, newest first.
+///
+/// ### Arguments
+/// * `filter` - Optional `Entity`, `EntityID` and `Action` of the changes, `From` and `To` times, and a `Limit`.
+async fn get_audit_log(Query(filter): Query<AuditFilter>) -> Response {
+    return match query_audit_log(&filter).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting the audit log.".to_string(),
+            "/Audit".to_string(),
+            "GET".to_string(),
+            json!(filter).to_string(),
+        ).await,
+    };
+}
+
 /// Returns a PrintJob by its ID.
 ///
 /// ### Arguments
@@ -350,6 +732,73 @@ async fn get_simulation_report_by_id(Path(id_str): Path<String>) -> Response {
     };
 }
 
+/// Returns the bottleneck analysis of a Simulation Report.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Simulation Report to analyze.
+///
+/// ### Returns
+/// Per-step timing, the critical path and ranked recommendations.
+async fn get_simulation_report_analysis(Path(id_str): Path<String>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
+        Err(e) => return error_response(
+            400,
+            e.to_string(),
+            format!("Invalid ID: {id_str}"),
+            format!("/SimulationReport/{id_str}/Analysis"),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    return match analyze_simulation_report(id).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("SimulationReport not found: {id_str}")),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            format!("An error occurred analyzing the simulation report with id {id_str}"),
+            format!("/SimulationReport/{id_str}/Analysis"),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+/// Compares two Simulation Reports side by side.
+///
+/// ### Arguments
+/// * `a_str` - The ID of the Simulation Report to compare against.
+/// * `b_str` - The ID of the Simulation Report to compare.
+///
+/// ### Returns
+/// The differences in total and step times, and whether the inputs differed.
+async fn get_simulation_report_comparison(Path((a_str, b_str)): Path<(String, String)>) -> Response {
+    let (a, b): (DocID, DocID) = match (a_str.parse(), b_str.parse()) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return error_response(
+            400,
+            format!("Invalid IDs: {a_str}, {b_str}"),
+            format!("Invalid IDs: {a_str}, {b_str}"),
+            format!("/SimulationReport/{a_str}/Compare/{b_str}"),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    return match compare_simulation_reports(a, b).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("SimulationReport not found: {a_str} or {b_str}")),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            format!("An error occurred comparing simulation reports {a_str} and {b_str}"),
+            format!("/SimulationReport/{a_str}/Compare/{b_str}"),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
 
 async fn get_simulation_report_workflow_steps_by_id(Path(id_str): Path<String>) -> impl IntoResponse {
     let id: DocID = match id_str.parse() {
@@ -362,6 +811,33 @@ async fn get_simulation_report_workflow_steps_by_id(Path(id_str): Path<String>)
     };
 }
 
+/// Returns the time a Simulation Report spent in each stage of the print process.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Simulation Report.
+///
+/// ### Returns
+/// An object with {stage->time} key-value pairs.
+async fn get_simulation_report_stage_times(Path(id_str): Path<String>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
+        Err(_) => return response(400, format!("Invalid ID: {id_str}")),
+    };
+    return match simulation_report_stage_times(id).await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("SimulationReport not found: {id_str}")),
+        Err(e) => error_response(
+            500,
+            e.to_string(),
+            format!("An error occurred getting the stage times of the simulation report with id {id_str}"),
+            format!("/SimulationReport/{id_str}/Stage/Time"),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+
 async fn post_rasterization_profile(
     Json(payload): Json<RasterizationProfile>,
 ) -> impl IntoResponse {
@@ -388,6 +864,14 @@ async fn post_rasterization_profile(
 async fn post_print_job(Json(payload): Json<PrintJob>) -> Response {
     return match insert_print_job(payload.clone()).await {
         Ok(data) => response(201, data.to_string()),
+        Err(err @ CustomError::InvalidPrintJob(_)) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            "/PrintJob".to_string(),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
         Err(e) => return error_response(
             500,
             e.to_string(),
@@ -410,14 +894,18 @@ async fn post_workflow(Json(payload): Json<WorkflowArgs>) -> impl IntoResponse {
     return match insert_workflow(payload.clone()).await {
         Ok(data) => response(201, data.to_string()),
         Err(err) => {
-            let code: u16 = match err.to_string().to_ascii_lowercase() == "invalid workflow" {
-                true =>  {println!("Error: {}", err); 422}
-                false => {println!("Error: {}", err); 500}
-            }; 
+            let code: u16 = match matches!(err, CustomError::InvalidWorkflow(_)) {
+                true => 422,
+                false => 500,
+            };
+            let message_generic = match code {
+                422 => err.to_string(),
+                _ => format!("An error occurred."),
+            };
             return error_response(
                 code,
                 err.to_string(),
-                format!("An error occurred."),
+                message_generic,
                 "/Workflow".to_string(),
                 "POST".to_string(),
                 serde_json::to_string(&payload).unwrap_or("".to_string()),
@@ -434,8 +922,16 @@ async fn post_workflow(Json(payload): Json<WorkflowArgs>) -> impl IntoResponse {
 /// ### Returns
 /// The status code of the insertion.
 async fn post_simulation_report(Json(payload): Json<SimulationReportArgs>) -> Response {
-    return match insert_simulation_report(payload.PrintJobID, payload.WorkflowID).await {
+    return match insert_simulation_report(payload.PrintJobID, payload.WorkflowID, payload.Overrides.clone()).await {
         Ok(data) => response(201, data.to_string()),
+        Err(err @ (CustomError::InvalidOverride(_) | CustomError::InvalidWorkflow(_))) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            "/SimulationReport".to_string(),
+            "POST".to_string(),
+            serde_json::to_string(&payload).unwrap_or("".to_string()),
+        ).await,
         Err(err) => { return error_response(
             500,
             err.to_string(),
@@ -447,6 +943,62 @@ async fn post_simulation_report(Json(payload): Json<SimulationReportArgs>) -> Re
     };
 }
 
+/// Re-runs a Simulation Report, storing the result as a new report.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Simulation Report to re-run.
+/// * `payload` - Optional JSON object choosing whether to simulate the stored snapshot.
+///
+/// ### Returns
+/// The ID of the new report and its comparison against the original.
+async fn post_simulation_report_rerun(Path(id_str): Path<String>, payload: Result<Json<RerunArgs>, JsonRejection>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
+        Err(e) => return error_response(
+            400,
+            e.to_string(),
+            format!("Invalid ID: {id_str}"),
+            format!("/SimulationReport/{id_str}/Rerun"),
+            "POST".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    // The body is optional
+    let args = match payload {
+        Ok(Json(args)) => args,
+        Err(JsonRejection::MissingJsonContentType(_)) => RerunArgs::default(),
+        Err(e) => return error_response(
+            400,
+            e.body_text(),
+            e.body_text(),
+            format!("/SimulationReport/{id_str}/Rerun"),
+            "POST".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    return match rerun_simulation_report(id, args.UseSnapshot).await {
+        Ok(data) => response(201, json!(data).to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("SimulationReport, or the print job and workflow it simulated, not found: {id_str}")),
+        Err(err @ (CustomError::MissingSnapshot(_) | CustomError::InvalidOverride(_) | CustomError::InvalidWorkflow(_))) => error_response(
+            422,
+            err.to_string(),
+            err.to_string(),
+            format!("/SimulationReport/{id_str}/Rerun"),
+            "POST".to_string(),
+            serde_json::to_string(&args).unwrap_or("".to_string()),
+        ).await,
+        Err(err) => error_response(
+            500,
+            err.to_string(),
+            format!("An error occurred re-running the simulation report with id {id_str}"),
+            format!("/SimulationReport/{id_str}/Rerun"),
+            "POST".to_string(),
+            serde_json::to_string(&args).unwrap_or("".to_string()),
+        ).await,
+    };
+}
+
 /// Inserts a user into the database
 ///
 /// # Arguments
@@ -461,14 +1013,15 @@ async fn post_user(Json(payload): Json<user>) -> impl IntoResponse {
     };
 }
 
-/// Deletes a Print Job from the database.
+/// Moves a Print Job to the trash.
 ///
 /// ### Arguments
 /// * `id_str` - The ID of the Print Job to delete.
+/// * `options` - Whether to also delete its dependents, and whether to only preview the deletion.
 ///
 /// ### Returns
-/// The status code of the deletion.
-async fn delete_print_job(Path(id_str): Path<String>) -> Response {
+/// The status code of the deletion, see delete_response().
+async fn delete_print_job(Path(id_str): Path<String>, Query(options): Query<DeleteOptions>) -> Response {
     let id: DocID = match id_str.parse() {
         Ok(data) => data,
         Err(err) => return error_response(
@@ -480,104 +1033,241 @@ async fn delete_print_job(Path(id_str): Path<String>) -> Response {
             "".to_string(),
         ).await, 
     };
-    return match remove_print_job(id).await {
-        Ok(_data) => response(204, "".to_string()), //TODO: Return the deleted data?
+    return delete_response(StoredItem::new(ItemType::PrintJob, id), options, "/PrintJob/{id}").await;
+}
+
+/// Moves a Rasterization Profile to the trash.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Rasterization Profile to delete.
+/// * `options` - Whether to also delete the print jobs using it, and whether to only preview the deletion.
+///
+/// ### Returns
+/// The status code of the deletion, see delete_response().
+async fn delete_rasterization_profile(Path(id_str): Path<String>, Query(options): Query<DeleteOptions>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
         Err(err) => return error_response(
-            500,
+            400,
             err.to_string(),
-            format!("An error occurred."),
-            "/PrintJob/{id}".to_string(),
+            format!("Invalid ID: {id_str}"),
+            "/RasterizationProfile/{id}".to_string(),
             "DELETE".to_string(),
             "".to_string(),
         ).await, 
     };
+    return delete_response(StoredItem::new(ItemType::RasterizationProfile, id), options, "/RasterizationProfile/{id}").await;
 }
 
-async fn delete_rasterization_profile(Path(id_str): Path<String>) -> Response {
+/// Moves a Workflow to the trash.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Workflow to delete.
+/// * `options` - Whether to also delete its dependents, and whether to only preview the deletion.
+///
+/// ### Returns
+/// The status code of the deletion, see delete_response().
+async fn delete_workflow(Path(id_str): Path<String>, Query(options): Query<DeleteOptions>) -> Response {
     let id: DocID = match id_str.parse() {
         Ok(data) => data,
         Err(err) => return error_response(
             400,
             err.to_string(),
             format!("Invalid ID: {id_str}"),
-            "/RasterizationProfile/{id}".to_string(),
+            "/Workflow/{id}".to_string(),
             "DELETE".to_string(),
             "".to_string(),
         ).await, 
     };
-    return match remove_rasterization_profile(id).await {
-        Ok(_data) => response(204, "".to_string()), //TODO: Return the deleted data?
+    return delete_response(StoredItem::new(ItemType::Workflow, id), options, "/Workflow/{id}").await;
+}
+
+/// Moves a Simulation Report to the trash.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Simulation Report to delete.
+/// * `options` - Whether to also delete the reports which re-ran it, and whether to only preview the deletion.
+///
+/// ### Returns
+/// The status code of the deletion, see delete_response().
+async fn delete_simulation_report(Path(id_str): Path<String>, Query(options): Query<DeleteOptions>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
         Err(err) => return error_response(
+            400,
+            err.to_string(),
+            format!("Invalid ID: {id_str}"),
+            "/SimulationReport/{id}".to_string(),
+            "DELETE".to_string(),
+            "".to_string(),
+        ).await, 
+    };
+    return delete_response(StoredItem::new(ItemType::SimulationReport, id), options, "/SimulationReport/{id}").await;
+}
+
+/// Deletes an item and returns the response to its DELETE request.
+///
+/// ### Arguments
+/// * `item` - The item to delete, see delete_item().
+/// * `options` - The options of the request.
+/// * `domain` - The route of the request.
+///
+/// ### Returns
+/// 204 once moved to the trash, or 200 with the items which would be for a preview.
+/// 404 if the item doesn't exist, and 409 with its dependents if it has any and
+/// the request doesn't cascade.
+async fn delete_response(item: StoredItem, options: DeleteOptions, domain: &str) -> Response {
+    return match delete_item(item.clone(), options).await {
+        Ok(removed) if options.preview => response(200, json!(removed).to_string()),
+        Ok(_) => response(204, "".to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("{item} not found")),
+        Err(CustomError::HasDependents(item, dependents)) => error_response(
+            409,
+            format!("{item} is still needed by {dependents:?}"),
+            json!({
+                "Message": format!("{item} is still needed by other items. Delete them first, or use ?cascade=true"),
+                "Dependents": dependents,
+            }).to_string(),
+            domain.to_string(),
+            "DELETE".to_string(),
+            "".to_string(),
+        ).await,
+        Err(err) => error_response(
             500,
             err.to_string(),
             format!("An error occurred."),
-            "/RasterizationProfile/{id}".to_string(),
+            domain.to_string(),
             "DELETE".to_string(),
             "".to_string(),
-        ).await, 
+        ).await,
     };
 }
 
-/// Deletes a Workflow from the database.
+/// Lists the items in the trash, most recently deleted first.
+async fn get_trash() -> Response {
+    return match query_trash().await {
+        Ok(data) => response(200, json!(data).to_string()),
+        Err(e) => return error_response(
+            500,
+            e.to_string(),
+            "An error occurred getting the trash.".to_string(),
+            "/Trash".to_string(),
+            "GET".to_string(),
+            "".to_string(),
+        ).await,
+    };
+}
+
+/// Takes a Print Job out of the trash.
 ///
 /// ### Arguments
-/// * `id_str` - The ID of the Workflow to delete.
+/// * `id_str` - The ID of the Print Job to restore.
 ///
 /// ### Returns
-/// The status code of the deletion.
-async fn delete_workflow(Path(id_str): Path<String>) -> Response {
+/// The restored items, see restore_response().
+async fn restore_print_job(Path(id_str): Path<String>) -> Response {
     let id: DocID = match id_str.parse() {
         Ok(data) => data,
         Err(err) => return error_response(
             400,
             err.to_string(),
             format!("Invalid ID: {id_str}"),
-            "/Workflow/{id}".to_string(),
-            "DELETE".to_string(),
+            "/PrintJob/{id}/Restore".to_string(),
+            "POST".to_string(),
             "".to_string(),
-        ).await, 
+        ).await,
     };
-    return match remove_workflow(id).await {
-        Ok(_data) => response(204, "".to_string()), //TODO: Return the deleted data?
+    return restore_response(StoredItem::new(ItemType::PrintJob, id), "/PrintJob/{id}/Restore").await;
+}
+
+/// Takes a Rasterization Profile out of the trash.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Rasterization Profile to restore.
+///
+/// ### Returns
+/// The restored items, see restore_response().
+async fn restore_rasterization_profile(Path(id_str): Path<String>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
         Err(err) => return error_response(
-            500,
+            400,
             err.to_string(),
-            format!("An error occurred."),
-            "/Workflow/{id}".to_string(),
-            "DELETE".to_string(),
+            format!("Invalid ID: {id_str}"),
+            "/RasterizationProfile/{id}/Restore".to_string(),
+            "POST".to_string(),
             "".to_string(),
-        ).await, 
+        ).await,
     };
+    return restore_response(StoredItem::new(ItemType::RasterizationProfile, id), "/RasterizationProfile/{id}/Restore").await;
 }
 
-/// Deletes a Simulation Report from the database.
+/// Takes a Workflow out of the trash.
 ///
 /// ### Arguments
-/// * `id_str` - The ID of the Simulation Report to delete.
+/// * `id_str` - The ID of the Workflow to restore.
 ///
 /// ### Returns
-/// The status code of the deletion.
-async fn delete_simulation_report(Path(id_str): Path<String>) -> Response {
+/// The restored items, see restore_response().
+async fn restore_workflow(Path(id_str): Path<String>) -> Response {
     let id: DocID = match id_str.parse() {
         Ok(data) => data,
         Err(err) => return error_response(
             400,
             err.to_string(),
             format!("Invalid ID: {id_str}"),
-            "/SimulationReport/{id}".to_string(),
-            "DELETE".to_string(),
+            "/Workflow/{id}/Restore".to_string(),
+            "POST".to_string(),
             "".to_string(),
-        ).await, 
+        ).await,
     };
-    return match remove_simulation_report(id).await {
-        Ok(_data) => response(204, "".to_string()), //TODO: Return the deleted data?
+    return restore_response(StoredItem::new(ItemType::Workflow, id), "/Workflow/{id}/Restore").await;
+}
+
+/// Takes a Simulation Report out of the trash.
+///
+/// ### Arguments
+/// * `id_str` - The ID of the Simulation Report to restore.
+///
+/// ### Returns
+/// The restored items, see restore_response().
+async fn restore_simulation_report(Path(id_str): Path<String>) -> Response {
+    let id: DocID = match id_str.parse() {
+        Ok(data) => data,
         Err(err) => return error_response(
+            400,
+            err.to_string(),
+            format!("Invalid ID: {id_str}"),
+            "/SimulationReport/{id}/Restore".to_string(),
+            "POST".to_string(),
+            "".to_string(),
+        ).await,
+    };
+    return restore_response(StoredItem::new(ItemType::SimulationReport, id), "/SimulationReport/{id}/Restore").await;
+}
+
+/// Restores an item and returns the response to its Restore request.
+///
+/// ### Arguments
+/// * `item` - The item to restore, see restore_item().
+/// * `domain` - The route of the request.
+///
+/// ### Returns
+/// 200 with the restored items, which include the items in the trash the item needs.
+/// 404 if the item isn't in the trash.
+async fn restore_response(item: StoredItem, domain: &str) -> Response {
+    return match restore_item(item.clone()).await {
+        Ok(restored) => response(200, json!(restored).to_string()),
+        Err(CustomError::NotFound) =>
+            response(404, format!("{item} is not in the trash")),
+        Err(err) => error_response(
             500,
             err.to_string(),
             format!("An error occurred."),
-            "/SimulationReport/{id}".to_string(),
-            "DELETE".to_string(),
+            domain.to_string(),
+            "POST".to_string(),
             "".to_string(),
-        ).await, 
+        ).await,
     };
 }
@@ -0,0 +1,205 @@
+use crate::database::*;
+use crate::workflow_steps::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use strum_macros::{Display as EnumDisplay, EnumString};
+
+/// Version of the archive format, raised whenever its layout changes
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/**
+ * The tables a backup holds, parents before the tables referring to them,
+ * so rows can be inserted in this order and deleted in the reverse order.
+ *
+ * User accounts are left out, so a backup never holds password hashes,
+ * and so is the error log, which only matters to the server it came from
+ **/
+pub const BACKUP_TABLES: &[&str] = &[
+    "rasterization_profile",
+    "printjob",
+    "workflow",
+    "workflow_step",
+    "assigned_workflow_step",
+    "assigned_workflow_step_param",
+    "next_workflow_step",
+    "prev_workflow_step",
+    "simulation_report",
+    "simulation_report_step_time",
+    "simulation_report_node_time",
+    "ran_workflow_step",
+    "audit_log",
+];
+
+/// The backed up tables which other tables refer to by ID. A merge keeps the
+/// existing row of a taken ID, so the backup's rows referring to a different row
+/// under that ID would end up attached to the wrong one. Workflow steps are left
+/// out, since their IDs come from the catalog and mean the same step everywhere
+pub const MERGE_PARENT_TABLES: &[&str] = &[
+    "rasterization_profile",
+    "printjob",
+    "workflow",
+    "assigned_workflow_step",
+    "simulation_report",
+];
+
+/// A row of a table, by column name. Values are numbers, strings, booleans or null
+pub type BackupRow = serde_json::Map<String, serde_json::Value>;
+
+/// Every row of the backed up tables, along with the schema they were read from
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupArchive {
+    pub FormatVersion: u32,
+    /// The database's schema version, see migrations.rs
+    pub SchemaVersion: u32,
+    pub CreatedAt: u32,
+    /// Rows of each table, by table name
+    pub Tables: BTreeMap<String, Vec<BackupRow>>,
+}
+
+/// What a restore does with the data already in the database
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, EnumDisplay, EnumString)]
+pub enum RestoreMode {
+    /// Deletes everything first, leaving the database just like the backup
+    #[default]
+    Replace,
+    /// Keeps everything, adding only the rows whose keys aren't taken yet.
+    /// Rows which others refer to must either be new or match the existing row
+    Merge,
+}
+
+/// Query parameters of a restore
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoreArgs {
+    #[serde(default)] pub Mode: RestoreMode,
+}
+
+/// What a restore changed
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreSummary {
+    pub Mode: RestoreMode,
+    pub SchemaVersion: u32,
+    /// How many rows were inserted into each table
+    pub Rows: BTreeMap<String, usize>,
+}
+
+
+/// Reads every backed up table. The tables are read in one transaction, so the
+/// archive is consistent even while other requests change the database
+pub async fn create_backup() -> Result<BackupArchive, CustomError> {
+    let repository = repository()?;
+    return Ok(BackupArchive {
+        FormatVersion: BACKUP_FORMAT_VERSION,
+        SchemaVersion: repository.migration_status().await?.CurrentVersion,
+        CreatedAt: unix_time(),
+        Tables: repository.export_tables(BACKUP_TABLES.to_vec()).await?,
+    });
+}
+
+
+/// Checks that an archive can be restored into a database at the given schema version.
+/// The archive must hold every backed up table, and every row must have exactly the
+/// columns of its table, so no table is emptied or filled with defaults by mistake
+///
+/// ### Arguments
+/// * `archive` - The archive to restore
+/// * `schema_version` - The schema version of the database
+/// * `columns` - The columns of each backed up table of the database
+///
+/// ### Returns
+/// An InvalidBackup error describing the first problem found, if any
+pub fn check_backup(archive: &BackupArchive, schema_version: u32, columns: &BTreeMap<String, Vec<String>>) -> Result<(), CustomError> {
+    if archive.FormatVersion != BACKUP_FORMAT_VERSION {
+        return Err(CustomError::InvalidBackup(format!("Unsupported format version {}, expected {BACKUP_FORMAT_VERSION}",
+            archive.FormatVersion)));
+    }
+    if archive.SchemaVersion != schema_version {
+        return Err(CustomError::InvalidBackup(format!("The backup has schema version {}, but the database has version {schema_version}",
+            archive.SchemaVersion)));
+    }
+    if let Some(table) = archive.Tables.keys().find(|table| !BACKUP_TABLES.contains(&table.as_str())) {
+        return Err(CustomError::InvalidBackup(format!("Unknown table {table}")));
+    }
+    if let Some(table) = BACKUP_TABLES.iter().find(|table| !archive.Tables.contains_key(**table)) {
+        return Err(CustomError::InvalidBackup(format!("The backup has no table {table}")));
+    }
+    for (table, rows) in &archive.Tables {
+        let table_columns = columns.get(table).map(Vec::as_slice).unwrap_or_default();
+        for row in rows {
+            if let Some(column) = row.keys().find(|column| !table_columns.contains(column)) {
+                return Err(CustomError::InvalidBackup(format!("Table {table} has no column {column}")));
+            }
+            if let Some(column) = table_columns.iter().find(|column| !row.contains_key(*column)) {
+                return Err(CustomError::InvalidBackup(format!("A row of table {table} has no column {column}")));
+            }
+            if let Some((column, _)) = row.iter().find(|(_, value)| value.is_array() || value.is_object()) {
+                return Err(CustomError::InvalidBackup(format!("Column {column} of table {table} holds a nested value")));
+            }
+        }
+    }
+    return Ok(());
+}
+
+
+/// Checks that merging an archive attaches its rows to the same parents as in the
+/// backup. Every row of a table which others refer to must either have an ID which
+/// isn't taken yet, or be just like the existing row with its ID
+///
+/// ### Arguments
+/// * `archive` - The archive to merge
+/// * `existing` - The rows of the MERGE_PARENT_TABLES in the database
+///
+/// ### Returns
+/// An InvalidBackup error naming the first row whose ID is taken by another row, if any
+pub fn check_merge(archive: &BackupArchive, existing: &BTreeMap<String, Vec<BackupRow>>) -> Result<(), CustomError> {
+    for table in MERGE_PARENT_TABLES {
+        let existing_rows: BTreeMap<String, &BackupRow> = existing.get(*table).into_iter().flatten()
+            .map(|row| (row["id"].to_string(), row))
+            .collect();
+        for row in archive.Tables.get(*table).into_iter().flatten() {
+            let id = row.get("id").map(|id| id.to_string()).unwrap_or_default();
+            if existing_rows.get(&id).is_some_and(|existing_row| *existing_row != row) {
+                return Err(CustomError::InvalidBackup(format!(
+                    "Table {table} already has a different row with ID {id}, so the rows referring to it can't be merged")));
+            }
+        }
+    }
+    return Ok(());
+}
+
+
+/// Validates an archive and restores it as one unit of work, so a restore which
+/// fails changes nothing. The workflow step catalog is loaded again once the
+/// restore is committed, since the backup may hold other custom steps than the database did
+///
+/// ### Arguments
+/// * `archive` - The archive to restore
+/// * `mode` - Whether to replace the data in the database or merge into it
+///
+/// ### Returns
+/// How many rows were inserted into each table
+pub async fn restore_backup(mut archive: BackupArchive, mode: RestoreMode) -> Result<RestoreSummary, CustomError> {
+    let summary = unit_of_work(|| async move {
+        let repository = repository()?;
+        let schema_version = repository.migration_status().await?.CurrentVersion;
+        let columns = repository.table_columns(BACKUP_TABLES.to_vec()).await?;
+        check_backup(&archive, schema_version, &columns)?;
+
+        match mode {
+            RestoreMode::Replace => repository.clear_tables(BACKUP_TABLES.iter().rev().copied().collect()).await?,
+            RestoreMode::Merge => check_merge(&archive, &repository.export_tables(MERGE_PARENT_TABLES.to_vec()).await?)?,
+        }
+        let mut rows = BTreeMap::new();
+        for table in BACKUP_TABLES {
+            let table_rows = archive.Tables.remove(*table)
+                .ok_or_else(|| CustomError::InvalidBackup(format!("The backup has no table {table}")))?;
+            rows.insert(table.to_string(), repository.import_table(table, table_rows).await?);
+        }
+        return Ok(RestoreSummary { Mode: mode, SchemaVersion: schema_version, Rows: rows });
+    }).await?;
+    invalidate_cached_workflows();
+    reload_workflow_step_catalog().await?;
+    return Ok(summary);
+}
@@ -1,43 +1,239 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{Debug, Display},
-    sync::{Arc, Mutex}
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use thiserror;
 use serde::{Serialize, Deserialize};
 use lazy_static::lazy_static;
-use rusqlite::{params, Connection, Error, Row, Result, Params};
-use crate::simulation::{*};
-use tokio::sync::SetError;
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use rusqlite::{Error, Result};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use strum_macros::{Display as EnumDisplay, EnumString};
 use crate::{
+    migrations::migrate_database,
+    repository::{open_repository, Repository},
     simulation::{*},
     workflow::{*},
     workflow_steps::{*},
-    EMPTY_WFS_VARIANT
 };
 use sha2::{Sha256, Digest};
 
 pub type DocID = u32;
-const DATABASE_LOCATION: &str = "./db/database.db3";
+/// Environment variable holding the database's path, `:memory:` for an in-memory
+/// database, or a `postgres://` URL for a PostgreSQL database
+pub const DATABASE_ENV: &str = "PRINTOS_DATABASE";
+const DEFAULT_DATABASE_LOCATION: &str = "./db/database.db3";
+const IN_MEMORY_LOCATION: &str = ":memory:";
+/// Environment variable holding how many days deleted items are kept in the trash
+pub const TRASH_RETENTION_ENV: &str = "PRINTOS_TRASH_RETENTION_DAYS";
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+/// How often the trash is purged of items older than the retention
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Most connections a pool keeps open at once
+pub(crate) const MAX_DB_CONNECTIONS: u32 = 8;
+/// How long a request waits for a free connection before giving up
+pub(crate) const DB_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Name of the generic parameter holding a step's machine count
+pub const MACHINE_COUNT_PARAM: &str = "MachineCount";
+/// Names of the generic parameters holding the Impose step's layout
+pub const N_UP_PARAM: &str = "NUp";
+pub const SHEET_SIZE_PARAM: &str = "SheetSize";
+/// Name of the Rasterization step's number of RIPs parameter
+pub const NUM_CORES_PARAM: &str = "NumCores";
+/// Name of the Cutting step's number of blades parameter
+pub const BLADE_COUNT_PARAM: &str = "BladeCount";
+/// Name of the Laminating step's film parameter
+pub const FILM_TYPE_PARAM: &str = "FilmType";
+
+// The repository of the current database, shared by every request.
+// The default database is opened when it's first used, unless open_database() was called.
+lazy_static! {
+    static ref REPOSITORY: RwLock<Option<Arc<dyn Repository>>> = RwLock::new(None);
+}
 
-// Wrap database in mutex so it can be used concurrently. Connection is opened lazily at first usage, then kept open.
+// Workflows assembled from the current database, see query_workflows()
 lazy_static! {
-    pub static ref DB_CONNECTION: Arc<Mutex<Connection>> = Arc::new(Mutex::new(
-        Connection::open(DATABASE_LOCATION).expect("Failed to connect to database.")
-    ));
+    static ref WORKFLOW_CACHE: RwLock<WorkflowCache> = RwLock::new(WorkflowCache::default());
+}
+
+// The unit of work the current task is running in, see unit_of_work()
+tokio::task_local! {
+    static UNIT_OF_WORK: Arc<dyn Repository>;
+}
+
+
+/// Where the database is kept, which also decides the backend storing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatabaseLocation {
+    /// A SQLite file, which is created if it doesn't exist
+    File(String),
+    /// A new, empty SQLite database which lasts until another database is opened.
+    /// It's created from the migrations, so it has the schema but no data until it's seeded
+    InMemory,
+    /// A PostgreSQL database, by its connection URL
+    Postgres(String),
+}
+
+impl DatabaseLocation {
+    /// Reads a location from a path, `:memory:` for an in-memory database,
+    /// or a `postgres://` URL
+    pub fn parse(setting: &str) -> DatabaseLocation {
+        return match setting {
+            IN_MEMORY_LOCATION => DatabaseLocation::InMemory,
+            url if url.starts_with("postgres://") || url.starts_with("postgresql://") =>
+                DatabaseLocation::Postgres(url.to_string()),
+            path => DatabaseLocation::File(path.to_string()),
+        };
+    }
+
+    /// Reads the location from the PRINTOS_DATABASE environment variable,
+    /// or the default file if it isn't set
+    pub fn from_env() -> DatabaseLocation {
+        return match std::env::var(DATABASE_ENV) {
+            Ok(setting) if !setting.is_empty() => DatabaseLocation::parse(&setting),
+            _ => DatabaseLocation::File(DEFAULT_DATABASE_LOCATION.to_string()),
+        };
+    }
+}
+
+impl Display for DatabaseLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            DatabaseLocation::File(path) => write!(f, "{path}"),
+            DatabaseLocation::InMemory => write!(f, "{IN_MEMORY_LOCATION}"),
+            // Leave out the user and password
+            DatabaseLocation::Postgres(url) => match url.split_once("://") {
+                Some((scheme, rest)) => write!(f, "{scheme}://{}", rest.rsplit_once('@').map_or(rest, |(_, host)| host)),
+                None => write!(f, "{url}"),
+            },
+        };
+    }
+}
+
+
+/// Running totals of how connections have been borrowed from a pool
+#[derive(Default)]
+pub(crate) struct PoolStats {
+    checkouts: AtomicU64,
+    timeouts: AtomicU64,
+    wait_micros: AtomicU64,
+}
+
+impl PoolStats {
+    /// Borrows a connection from the pool, waiting for one to become free if needed
+    pub(crate) fn checkout<M: ManageConnection>(&self, pool: &Pool<M>) -> Result<PooledConnection<M>, CustomError> {
+        let start = Instant::now();
+        let result = pool.get();
+        self.wait_micros.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        match &result {
+            Ok(_) => self.checkouts.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.timeouts.fetch_add(1, Ordering::Relaxed),
+        };
+        return Ok(result?);
+    }
+
+    /// Returns the current state of the pool, along with these totals
+    pub(crate) fn metrics<M: ManageConnection>(&self, pool: &Pool<M>) -> PoolMetrics {
+        let state = pool.state();
+        let checkouts = self.checkouts.load(Ordering::Relaxed);
+        let timeouts = self.timeouts.load(Ordering::Relaxed);
+        let attempts = checkouts + timeouts;
+        return PoolMetrics {
+            MaxConnections: pool.max_size(),
+            Connections: state.connections,
+            IdleConnections: state.idle_connections,
+            ActiveConnections: state.connections - state.idle_connections,
+            Checkouts: checkouts,
+            Timeouts: timeouts,
+            AverageWaitMicros: match attempts {
+                0 => 0.0,
+                _ => self.wait_micros.load(Ordering::Relaxed) as f64 / attempts as f64,
+            },
+        };
+    }
 }
 
+/// The state of the connection pool, and how long requests have waited on it
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolMetrics {
+    pub MaxConnections: u32,
+    /// Connections currently open, whether borrowed or idle
+    pub Connections: u32,
+    pub IdleConnections: u32,
+    /// Connections currently borrowed by a request
+    pub ActiveConnections: u32,
+    /// Times a connection has been borrowed since the database was opened
+    pub Checkouts: u64,
+    /// Times a request gave up waiting for a free connection
+    pub Timeouts: u64,
+    /// Average time spent waiting for a free connection, in microseconds
+    pub AverageWaitMicros: f64,
+}
 
-/// This is a wrapper for passing along a rusqlite error or a custom error string
+
+/// Workflows kept in memory, so they aren't assembled from their rows on every request.
+/// Units of work clear it when they change a workflow, see invalidate_cached_workflows()
+#[derive(Debug, Default)]
+struct WorkflowCache {
+    workflows: BTreeMap<DocID, Workflow>,
+    /// Whether every workflow is cached, so they can be listed from the cache
+    complete: bool,
+    /// Raised whenever the cache is cleared, so a load which started earlier doesn't fill it
+    generation: u64,
+}
+
+
+/// This is a wrapper for passing along a database error or a custom error string
 /// More error types could be added to this enum if needed
 #[derive(Debug, thiserror::Error)]
 pub enum CustomError {
     #[error("{0}")]
     OtherError(String),
+    /// The item looked up doesn't exist, whichever backend stores it
+    #[error("Not found")]
+    NotFound,
+    #[error("Invalid workflow: {0}")]
+    InvalidWorkflow(String),
+    #[error("Invalid override: {0}")]
+    InvalidOverride(String),
+    #[error("Invalid print job: {0}")]
+    InvalidPrintJob(String),
+    #[error("Simulation report {0} has no snapshot")]
+    MissingSnapshot(DocID),
+    #[error("Invalid workflow step catalog: {0}")]
+    InvalidCatalog(String),
+    #[error("Workflow step {0} is still used by workflows or simulation reports")]
+    WorkflowStepInUse(DocID),
+    #[error("Invalid migration: {0}")]
+    InvalidMigration(String),
+    #[error("Database schema version {0} is newer than the latest version this server supports, {1}")]
+    SchemaTooNew(u32, u32),
+    #[error("Invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("{0} is still needed by {n} other items", n = .1.len())]
+    HasDependents(StoredItem, Vec<StoredItem>),
     #[error(transparent)]
-    DatabaseError(#[from] Error),
+    DatabaseError(Error),
     #[error(transparent)]
-    TokioSetError(#[from] SetError<HashMap<u32, WFSVariant>>),
+    PostgresError(#[from] postgres::Error),
+    #[error("Database connection pool: {0}")]
+    PoolError(#[from] r2d2::Error),
+    #[error("Database task failed: {0}")]
+    TaskError(#[from] tokio::task::JoinError),
+}
+
+impl From<Error> for CustomError {
+    /// A query which returned no rows looked up an item which doesn't exist
+    fn from(err: Error) -> CustomError {
+        return match err {
+            Error::QueryReturnedNoRows => CustomError::NotFound,
+            err => CustomError::DatabaseError(err),
+        };
+    }
 }
 
 #[allow(non_snake_case)]
@@ -60,7 +256,66 @@ pub struct PrintJob {
 	#[serde(default)] pub DateCreated: Option<u32>,
 	pub Title: String,
 	pub PageCount: u32,
-	pub RasterizationProfileID: u32
+	pub RasterizationProfileID: u32,
+	#[serde(default)] pub PageSize: MediaSize,
+	#[serde(default)] pub Duplex: bool,
+	#[serde(default = "default_copies")] pub Copies: u32,
+}
+
+fn default_copies() -> u32 {
+    return 1;
+}
+
+
+/// Standard paper sizes, used for both print job pages and press sheets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, EnumString, EnumDisplay)]
+pub enum MediaSize {
+    #[default]
+    Letter,
+    Legal,
+    Tabloid,
+    A4,
+    A3,
+    SRA3,
+    B2,
+}
+
+impl MediaSize {
+    /// Width and height in millimeters
+    pub fn dimensions(&self) -> (u32, u32) {
+        use MediaSize::*;
+        return match self {
+            Letter => (216, 279),
+            Legal => (216, 356),
+            Tabloid => (279, 432),
+            A4 => (210, 297),
+            A3 => (297, 420),
+            SRA3 => (320, 450),
+            B2 => (500, 707),
+        };
+    }
+
+    /// The most pages of the given size that fit on a sheet of this size,
+    /// laid out in a grid with the pages in either orientation
+    pub fn pages_per_sheet(&self, page: MediaSize) -> u32 {
+        let (sheet_w, sheet_h) = self.dimensions();
+        let (page_w, page_h) = page.dimensions();
+        let upright = (sheet_w / page_w) * (sheet_h / page_h);
+        let rotated = (sheet_w / page_h) * (sheet_h / page_w);
+        return upright.max(rotated);
+    }
+}
+
+impl ToSql for MediaSize {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.to_string()));
+    }
+}
+
+impl FromSql for MediaSize {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
 }
 
 
@@ -75,27 +330,41 @@ pub struct RasterizationProfile {
 #[allow(non_snake_case)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationReport {
-	#[serde(default)] id: Option<DocID>,
-	PrintJobID: DocID,
-	WorkflowID: DocID,
-	CreationTime: u32,
-	TotalTimeTaken: u32,
-    StepTimes: HashMap<DocID, u32>,
+	#[serde(default)] pub id: Option<DocID>,
+	pub PrintJobID: DocID,
+	pub WorkflowID: DocID,
+	pub CreationTime: u32,
+	pub TotalTimeTaken: u32,
+    pub StepTimes: HashMap<DocID, u32>,
+    /// The what-if overrides this report was simulated with
+    pub Overrides: Vec<StepOverride>,
+    /// When each node of the workflow started and finished
+    #[serde(default)]
+    pub NodeTimes: Vec<NodeTime>,
+    /// The print job as it was when simulated
+    #[serde(default)]
+    pub PrintJobSnapshot: Option<PrintJob>,
+    /// The workflow as it was when simulated
+    #[serde(default)]
+    pub WorkflowSnapshot: Option<Workflow>,
+    /// The report this one re-ran, if any
+    #[serde(default)]
+    pub RerunOf: Option<DocID>,
 }
 
 
 #[allow(non_snake_case)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimulationReportDetailed {
-	#[serde(default)] id: Option<DocID>,
-	PrintJobID: DocID,
-    PrintJobTitle: String,
-	WorkflowID: DocID,
-    WorkflowTitle: String,
-    RasterizationProfile: String,
-	CreationTime: u32,
-	TotalTimeTaken: u32,
-    StepTimes: HashMap<DocID, u32>,
+	#[serde(default)] pub id: Option<DocID>,
+	pub PrintJobID: DocID,
+    pub PrintJobTitle: String,
+	pub WorkflowID: DocID,
+    pub WorkflowTitle: String,
+    pub RasterizationProfile: String,
+	pub CreationTime: u32,
+	pub TotalTimeTaken: u32,
+    pub StepTimes: HashMap<DocID, u32>,
     //TODO: There is a table in the database called ran_workflow_step, which associates a workflow
     //step with a simulation_report_id and time_taken value. We could potentially store a list of
     //RanWorkflowSteps in this struct, similar to how a workflow struct does.
@@ -107,6 +376,268 @@ pub struct SimulationReportDetailed {
 pub struct SimulationReportArgs {
     pub PrintJobID: DocID,
     pub WorkflowID: DocID,
+    /// What-if changes to apply to the workflow for this simulation only
+    #[serde(default)]
+    pub Overrides: Vec<StepOverride>,
+}
+
+/// Arguments for re-running a simulation report
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RerunArgs {
+    /// Simulate the report's snapshot instead of the current print job and workflow
+    #[serde(default)]
+    pub UseSnapshot: bool,
+}
+
+/// A what-if change to the timing or parameters of workflow steps for a single
+/// simulation. Targets either every step of a type, or a single node of the workflow
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepOverride {
+    /// Apply to every node of this type of workflow step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub WorkflowStepID: Option<DocID>,
+    /// Apply to the node at this index of the workflow's steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub NodeIndex: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub SetupTime: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub TimePerPage: Option<u32>,
+    /// Step parameters by name, e.g. MachineCount, NumCores or NUp
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub Parameters: HashMap<String, serde_json::Value>,
+}
+
+/// The kinds of items which can be deleted through the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay, EnumString)]
+pub enum ItemType {
+    RasterizationProfile,
+    PrintJob,
+    Workflow,
+    SimulationReport,
+}
+
+impl ItemType {
+    pub const ALL: [ItemType; 4] = [
+        ItemType::RasterizationProfile,
+        ItemType::PrintJob,
+        ItemType::Workflow,
+        ItemType::SimulationReport,
+    ];
+
+    /// The table holding items of this type
+    pub(crate) fn table(self) -> &'static str {
+        return match self {
+            ItemType::RasterizationProfile => "rasterization_profile",
+            ItemType::PrintJob => "printjob",
+            ItemType::Workflow => "workflow",
+            ItemType::SimulationReport => "simulation_report",
+        };
+    }
+}
+
+impl FromSql for ItemType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+/// The SQL condition selecting the rows in the trash, or the rows which aren't
+pub(crate) fn trash_condition(trashed: bool) -> &'static str {
+    return match trashed {
+        true => "deleted_at IS NOT NULL",
+        false => "deleted_at IS NULL",
+    };
+}
+
+/// The SQL query listing the trash, with the type, ID, title and deletion time
+/// of each item, most recently deleted first
+pub(crate) fn trash_query() -> String {
+    let tables: Vec<String> = ItemType::ALL.iter()
+        .map(|item_type| format!("SELECT '{item_type}', id, title, deleted_at FROM {} WHERE deleted_at IS NOT NULL",
+            item_type.table()))
+        .collect();
+    return format!("{} ORDER BY 4 DESC, 1, 2", tables.join(" UNION ALL "));
+}
+
+/// An item stored in the database, by its type and ID
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredItem {
+    pub Type: ItemType,
+    pub id: DocID,
+}
+
+impl StoredItem {
+    pub fn new(item_type: ItemType, id: DocID) -> StoredItem {
+        return StoredItem { Type: item_type, id };
+    }
+}
+
+impl Display for StoredItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{} {}", self.Type, self.id);
+    }
+}
+
+/// An item in the trash, which can be restored until it's purged
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedItem {
+    pub Type: ItemType,
+    pub id: DocID,
+    pub Title: String,
+    /// When the item was moved to the trash
+    pub DeletedAt: u32,
+}
+
+/// Options of a DELETE request, taken from the query string
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DeleteOptions {
+    /// Also delete everything which depends on the item
+    #[serde(default)]
+    pub cascade: bool,
+    /// Only report what would be deleted, without deleting it
+    #[serde(default)]
+    pub preview: bool,
+}
+
+/// The kinds of data whose changes are recorded in the audit log.
+/// The error log isn't audited, since it's a log itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay, EnumString)]
+pub enum AuditEntity {
+    RasterizationProfile,
+    PrintJob,
+    Workflow,
+    SimulationReport,
+    WorkflowStep,
+    AssignedWorkflowStep,
+    User,
+}
+
+impl From<ItemType> for AuditEntity {
+    fn from(item_type: ItemType) -> AuditEntity {
+        return match item_type {
+            ItemType::RasterizationProfile => AuditEntity::RasterizationProfile,
+            ItemType::PrintJob => AuditEntity::PrintJob,
+            ItemType::Workflow => AuditEntity::Workflow,
+            ItemType::SimulationReport => AuditEntity::SimulationReport,
+        };
+    }
+}
+
+impl FromSql for AuditEntity {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+/// What a change in the audit log did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumDisplay, EnumString)]
+pub enum AuditAction {
+    Insert,
+    Update,
+    /// Moved to the trash
+    Delete,
+    /// Taken out of the trash
+    Restore,
+    /// Deleted permanently
+    Purge,
+}
+
+impl FromSql for AuditAction {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+/// A change recorded in the audit log
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(default)] pub id: Option<DocID>,
+    pub Time: u32,
+    pub Entity: AuditEntity,
+    pub EntityID: DocID,
+    pub Action: AuditAction,
+    /// Who made the change. Always empty until requests are authenticated
+    pub Actor: Option<String>,
+    /// The item before the change, empty for an insert
+    pub Before: Option<serde_json::Value>,
+    /// The item after the change, empty for a delete
+    pub After: Option<serde_json::Value>,
+}
+
+/// Filters of the audit log, taken from the query string
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub Entity: Option<AuditEntity>,
+    pub EntityID: Option<DocID>,
+    pub Action: Option<AuditAction>,
+    /// Only include changes made at or after this time
+    pub From: Option<u32>,
+    /// Only include changes made at or before this time
+    pub To: Option<u32>,
+    /// Maximum number of changes to return, newest first. Defaults to 100
+    pub Limit: Option<u32>,
+}
+
+/// Filters shared by the analytics queries, taken from the query string
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnalyticsFilter {
+    /// Only include reports created at or after this time
+    pub From: Option<u32>,
+    /// Only include reports created at or before this time
+    pub To: Option<u32>,
+    /// Width of each bucket of a trend, in seconds. Defaults to a day
+    pub Interval: Option<u32>,
+    /// Maximum number of rows to return from a ranking. Defaults to 10
+    pub Limit: Option<u32>,
+}
+
+/// Summary statistics of the times taken by a group of simulations
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeStatistics {
+    pub id: DocID,
+    pub Title: String,
+    pub Count: u32,
+    pub Average: f64,
+    pub Min: u32,
+    pub Max: u32,
+    pub P50: u32,
+    pub P90: u32,
+    pub P95: u32,
+}
+
+/// Average time per page of the simulations created within one interval
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimePerPageTrend {
+    /// Start of the interval
+    pub Time: u32,
+    pub Count: u32,
+    pub AverageTimePerPage: f64,
+}
+
+/// How many simulations used a workflow or rasterization profile
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageCount {
+    pub id: DocID,
+    pub Title: String,
+    pub Count: u32,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Usage {
+    pub Workflows: Vec<UsageCount>,
+    pub RasterizationProfiles: Vec<UsageCount>,
 }
 
 #[allow(non_snake_case)]
@@ -132,7 +663,7 @@ impl ErrorDetailed {
 
 
 impl SimulationReport {
-	pub fn new(print_job_id: DocID, workflow_id: DocID, creation_time: u32, total_time_taken: u32, step_times: HashMap<DocID,u32>) -> SimulationReport {
+	pub fn new(print_job_id: DocID, workflow_id: DocID, creation_time: u32, total_time_taken: u32, step_times: HashMap<DocID,u32>, overrides: Vec<StepOverride>, node_times: Vec<NodeTime>) -> SimulationReport {
 		return SimulationReport{
 			id: None,
 			PrintJobID: print_job_id,
@@ -140,576 +671,878 @@ impl SimulationReport {
 			CreationTime: creation_time,
 			TotalTimeTaken: total_time_taken,
             StepTimes: step_times,
+            Overrides: overrides,
+            NodeTimes: node_times,
+            PrintJobSnapshot: None,
+            WorkflowSnapshot: None,
+            RerunOf: None,
 		}
 	}
-}
 
+    /// The print job this report simulated, from its snapshot if it has one
+    pub async fn print_job(&self) -> Result<PrintJob, CustomError> {
+        return match &self.PrintJobSnapshot {
+            Some(print_job) => Ok(print_job.clone()),
+            None => find_print_job(self.PrintJobID).await,
+        };
+    }
 
-/**
- * Create struct from database row
- * 
- * These functions transform a row from a SQL query and creates the
- * appropriate struct from the result. These functions assume that
- * the columns in the row are in a specific order
- **/
-
-fn error_detailed_from_row(row: &Row) -> Result<ErrorDetailed> {
-    return Ok(ErrorDetailed {
-        id: row.get(0)?,
-        date: row.get(1)?,
-        status: row.get(2)?,
-        domain: row.get(3)?,
-        request: row.get(4)?,
-        method: row.get(5)?,
-        response: row.get(6)?,
-    });
-}
-
-fn print_job_from_row(row: &Row) -> Result<PrintJob> {
-    return Ok(PrintJob {
-        id: row.get(0)?,
-        Title: row.get(1)?,
-        DateCreated: row.get(2)?,
-        PageCount: row.get(3)?,
-        RasterizationProfileID: row.get(4)?,
-    });
-}
-
-fn workflow_from_row(row: &Row) -> Result<Workflow> {
-    return Ok(Workflow {
-        id: row.get(0)?,
-        Title: row.get(1)?,
-        Steps: vec![],
-    });
-}
-
-fn workflow_step_from_row(row: &Row) -> Result<WorkflowStep> {
-    return Ok(WorkflowStep {
-        id: row.get(0)?,        
-        Title: row.get(1)?, 
-        SetupTime: row.get(2)?,
-        TimePerPage: row.get(3)?,
-    });
-}
-
-fn simulation_report_detailed_from_row(row: &Row) -> Result<SimulationReportDetailed> {
-    return Ok(SimulationReportDetailed {
-        id: row.get(0)?, 
-        CreationTime: row.get(2)?,
-        TotalTimeTaken: row.get(3)?,
-        PrintJobID: row.get(4)?,
-        WorkflowID: row.get(5)?,
-        StepTimes: HashMap::from([]),
-        PrintJobTitle: row.get(6)?,
-        WorkflowTitle: row.get(7)?,
-        RasterizationProfile: row.get(8)?,
-    });
-}
-
-fn rasterization_profile_from_row(row: &Row) -> Result<RasterizationProfile> {
-    return Ok(RasterizationProfile {
-        id: row.get(0)?, 
-        title: row.get(1)?, 
-        profile: row.get(2)?,
-    });
-}
-
-fn simulation_report_from_row(row: &Row) -> Result<SimulationReport> {
-    return Ok(SimulationReport {
-        id: row.get(0)?,
-        CreationTime: row.get(1)?,
-        TotalTimeTaken: row.get(2)?, 
-        PrintJobID: row.get(3)?,
-        WorkflowID: row.get(4)?,
-        StepTimes: HashMap::from([(2, 15)]),
-    });
-}
-
-fn assigned_workflow_step_from_row(row: &Row) -> Result<AssignedWorkflowStep> {
-    return Ok(AssignedWorkflowStep {
-        id: row.get(0)?,
-        WorkflowStepID: row.get(2)?,
-        param_id: row.get(3)?,
-        Prev: vec![],
-        Next: vec![],
-    });
+    /// The workflow this report simulated, from its snapshot if it has one
+    pub async fn workflow(&self) -> Result<Workflow, CustomError> {
+        return match &self.WorkflowSnapshot {
+            Some(workflow) => Ok(workflow.clone()),
+            None => find_workflow(self.WorkflowID).await,
+        };
+    }
 }
 
 
 pub async fn setup_database() -> Result<(),CustomError> {
-    enable_foreign_key_checking().await?;
+    migrate_database().await?;
     build_workflow_step_table().await?;
     return Ok(());
 }
 
 
-/// Enables foreign key checking
-async fn enable_foreign_key_checking() -> Result<()> {
-    let db = DB_CONNECTION.lock().unwrap();
-    db.execute("PRAGMA foreign_keys = ON;", [])?;
-    return Ok(())
+/// Opens the database at the given location for every request from now on.
+/// Requests already using the previous database aren't affected
+pub fn open_database(location: DatabaseLocation) -> Result<(), CustomError> {
+    set_repository(open_repository(&location)?);
+    return Ok(());
 }
 
 
-/// Returns the results of a database query or an error
+/// Uses the given repository for every request from now on
+pub fn set_repository(repository: Arc<dyn Repository>) {
+    *REPOSITORY.write().unwrap() = Some(repository);
+    invalidate_cached_workflows();
+}
+
+
+/// Returns the repository of the current unit of work, or else of the current
+/// database, opening the default database if none is open
+pub fn repository() -> Result<Arc<dyn Repository>, CustomError> {
+    if let Ok(repository) = UNIT_OF_WORK.try_with(|repository| repository.clone()) {
+        return Ok(repository);
+    }
+    if let Some(repository) = REPOSITORY.read().unwrap().as_ref() {
+        return Ok(repository.clone());
+    }
+    let mut current = REPOSITORY.write().unwrap();
+    if current.is_none() {
+        *current = Some(open_repository(&DatabaseLocation::from_env())?);
+    }
+    return Ok(current.clone().unwrap());
+}
+
+
+/// Runs a function as one unit of work. Every database function it calls uses
+/// the same connection and transaction, which is committed if the function
+/// succeeds and rolled back if it fails. A unit of work started inside
+/// another one is part of the outer one
 ///
 /// ### Arguments
-/// * `query` - SQL query to perform
-/// * `params` - Array of parameters used in the query (see query_map())
-/// * `f` - Function that turns a row from the database into the desired struct
+/// * `f` - Function doing the work
 ///
 /// ### Returns
-/// A vector of structs or a rusqlite error
-fn query<T,P,F>(query: &str, params: P, f: F) -> Result<Vec<T>> 
-    where P: Params, F: FnMut(&Row<'_>) -> Result<T> {
+/// The result of the function, or the error which rolled the work back
+pub async fn unit_of_work<T, F, Fut>(f: F) -> Result<T, CustomError>
+    where F: FnOnce() -> Fut, Fut: Future<Output = Result<T, CustomError>> {
 
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt = db.prepare(query)?;
-    let rows = stmt.query_map(params, f)?;
-
-    let mut results = Vec::new();
-    for job_result in rows {
-        let job = job_result?;
-        results.push(job);
+    if in_unit_of_work() {
+        return f().await;
+    }
+    let generation = WORKFLOW_CACHE.read().unwrap().generation;
+    let work = repository()?.begin().await?;
+    let result = UNIT_OF_WORK.scope(work.clone(), f()).await;
+    let ended = match &result {
+        Ok(_) => work.commit().await,
+        // The error which caused the rollback matters more than a failed rollback,
+        // which is retried when the unit of work is dropped
+        Err(_) => { let _ = work.rollback().await; Ok(()) },
+    };
+    // Other requests may have cached the workflows this unit changed before it ended
+    if WORKFLOW_CACHE.read().unwrap().generation != generation {
+        invalidate_cached_workflows();
     }
-    
-    return Ok(results);
+    ended?;
+    return result;
+}
+
+
+/// Whether the current task is running in a unit of work
+fn in_unit_of_work() -> bool {
+    return UNIT_OF_WORK.try_with(|_| ()).is_ok();
+}
+
+
+/// Drops every workflow from the cache. Must be called whenever a workflow or
+/// anything it's assembled from changes
+pub(crate) fn invalidate_cached_workflows() {
+    let mut cache = WORKFLOW_CACHE.write().unwrap();
+    cache.workflows.clear();
+    cache.complete = false;
+    cache.generation += 1;
+}
+
+
+/// Returns the current state of the database's connection pool
+pub fn pool_metrics() -> Result<PoolMetrics, CustomError> {
+    return Ok(repository()?.pool_metrics());
 }
 
 
 /// Checks if the results of a database query for an ID is valid
 ///
 /// ### Arguments
-/// * `rows` - The vector returned by query()
+/// * `rows` - The vector returned by a query
 ///
 /// ### Returns
 /// The single item in the vector, or an error
-fn check_id_lookup_results<T>(mut rows: Vec<T>) -> Result<T,CustomError> {
+pub(crate) fn check_id_lookup_results<T>(mut rows: Vec<T>) -> Result<T,CustomError> {
     return match rows.len() {
-        0 => Err(CustomError::DatabaseError(Error::QueryReturnedNoRows)),
+        0 => Err(CustomError::NotFound),
         1 => Ok(rows.pop().unwrap()),
         _ => Err(CustomError::OtherError("ID search returned multiple rows".to_string()))
     };
 }
 
 
+/// Reads the stored value of an assigned workflow step's parameter.
+/// Values stored before parameters were typed are plain strings
+pub fn stored_param_value(text: String) -> serde_json::Value {
+    return serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text));
+}
+
+
+/// The rows of some workflows, loaded by the queries of WorkflowQueries
+#[derive(Debug, Default)]
+pub(crate) struct WorkflowRows {
+    /// The workflows, without their steps
+    pub workflows: Vec<Workflow>,
+    /// Each assigned step, along with the ID of its workflow, in the order of the workflow's nodes
+    pub steps: Vec<(DocID, AssignedWorkflowStep)>,
+    /// (assigned workflow step ID, name, value) of each generic parameter
+    pub params: Vec<(DocID, String, String)>,
+    /// (assigned workflow step ID, ID of a step which comes next)
+    pub next: Vec<(DocID, DocID)>,
+    /// (assigned workflow step ID, ID of a step which comes before it)
+    pub prev: Vec<(DocID, DocID)>,
+}
+
 /**
- * These functions query the database and return the results of that query
- * 
- * The query_ functions return a vector containing every item in the
- * given table
- * TODO: Expand these functions to allow for more specific queries
- * 
- * The find_ functions return the row matching the given ID, or an
- * error if it doesn't exist
+ * The queries loading the rows of workflows, so each backend loads any number
+ * of workflows with the same five queries. Each query selects the columns of
+ * one field of WorkflowRows, in order, for the workflows which aren't in the
+ * trash and match the given condition on the workflow table
  **/
-
-pub async fn query_errors_detailed() -> Result<Vec<ErrorDetailed>> {
-    return query("SELECT id, date_occured, status, domain, request, method, response FROM errors_detailed", 
-        [], error_detailed_from_row);
+pub(crate) struct WorkflowQueries {
+    pub workflows: String,
+    pub steps: String,
+    pub params: String,
+    pub next: String,
+    pub prev: String,
 }
 
-pub async fn query_print_jobs() -> Result<Vec<PrintJob>> {
-    return query("SELECT id, title, creation_time, page_count, rasterization_profile_id FROM printjob;", 
-        [], print_job_from_row);
+impl WorkflowQueries {
+    /// ### Arguments
+    /// * `condition` - SQL condition on the workflow table, e.g. `workflow.id = ?1`, or `TRUE`
+    pub(crate) fn new(condition: &str) -> WorkflowQueries {
+        let from_steps = format!("
+            FROM assigned_workflow_step
+            JOIN workflow ON workflow.id = assigned_workflow_step.workflow_id
+            WHERE workflow.deleted_at IS NULL AND {condition}");
+        let edges = |table: &str, column: &str| format!("
+            SELECT {table}.assigned_workflow_step_id, {table}.{column}
+            FROM {table}
+            JOIN assigned_workflow_step ON assigned_workflow_step.id = {table}.assigned_workflow_step_id
+            JOIN workflow ON workflow.id = assigned_workflow_step.workflow_id
+            WHERE workflow.deleted_at IS NULL AND {condition}
+            ORDER BY 1, 2");
+        return WorkflowQueries {
+            workflows: format!("SELECT id, title FROM workflow WHERE deleted_at IS NULL AND {condition} ORDER BY id"),
+            steps: format!("
+                SELECT
+                    assigned_workflow_step.id,
+                    assigned_workflow_step.workflow_id,
+                    assigned_workflow_step.workflow_step_id,
+                    assigned_workflow_step.setup_time,
+                    assigned_workflow_step.time_per_page
+                {from_steps}
+                ORDER BY assigned_workflow_step.id"),
+            params: format!("
+                SELECT
+                    assigned_workflow_step_param.assigned_workflow_step_id,
+                    assigned_workflow_step_param.name,
+                    assigned_workflow_step_param.value
+                FROM assigned_workflow_step_param
+                JOIN assigned_workflow_step ON assigned_workflow_step.id = assigned_workflow_step_param.assigned_workflow_step_id
+                JOIN workflow ON workflow.id = assigned_workflow_step.workflow_id
+                WHERE workflow.deleted_at IS NULL AND {condition}"),
+            next: edges("next_workflow_step", "next_step_id"),
+            prev: edges("prev_workflow_step", "prev_step_id"),
+        };
+    }
 }
 
 
-// Todo: The method to query a single workflow is kind of a cluster**** right now, and needs rewritten,
-// but I am just going to call it in a loop here for convienence until I get around to rewriting
-// all of it ** soon **.
-pub async fn query_workflows() -> Result<Vec<Workflow>> {
-    let empty_workflows = query("SELECT id, title FROM workflow;",
-     [], workflow_from_row)?;
+/// Builds the graphs of workflows from their rows
+///
+/// ### Arguments
+/// * `rows` - The rows of the workflows, see WorkflowQueries
+///
+/// ### Returns
+/// Each workflow, in the order of `rows.workflows`, or the error which kept it from being built
+pub(crate) fn assemble_workflows(rows: WorkflowRows) -> Vec<Result<Workflow, CustomError>> {
+    let mut params: HashMap<DocID, HashMap<String, String>> = HashMap::new();
+    for (step_id, name, value) in rows.params {
+        params.entry(step_id).or_default().insert(name, value);
+    }
+    let index_of: HashMap<DocID, usize> = rows.workflows.iter().enumerate()
+        .filter_map(|(index, workflow)| Some((workflow.id?, index)))
+        .collect();
+    let mut workflows: Vec<Result<Workflow, CustomError>> = rows.workflows.into_iter().map(Ok).collect();
+
+    // Place each step in its workflow, keeping track of the workflow and node index it ended up at
+    let mut node_of: HashMap<DocID, (usize, usize)> = HashMap::new();
+    for (workflow_id, step) in rows.steps {
+        let Some(&index) = index_of.get(&workflow_id) else { continue };
+        let node = workflow_node(&step, params.remove(&step.id).unwrap_or_default());
+        match (&mut workflows[index], node) {
+            (Ok(workflow), Ok(node)) => {
+                node_of.insert(step.id, (index, workflow.Steps.len()));
+                workflow.Steps.push(node);
+            },
+            (Ok(_), Err(e)) => workflows[index] = Err(e),
+            (Err(_), _) => {},
+        }
+    }
+
+    // Add previous and next workflow step information to each step
+    let next = rows.next.into_iter().map(|(from, to)| (from, to, true));
+    let prev = rows.prev.into_iter().map(|(from, to)| (from, to, false));
+    for (from, to, is_next) in next.chain(prev) {
+        let (Some(&(index, node)), Some(&(_, other))) = (node_of.get(&from), node_of.get(&to)) else { continue };
+        if let Ok(workflow) = &mut workflows[index] {
+            match is_next {
+                true => workflow.Steps[node].next.push(other),
+                false => workflow.Steps[node].prev.push(other),
+            }
+        }
+    }
+    return workflows;
+}
+
 
-    let mut populated_workflows = Vec::new();
-    for workflow in empty_workflows {
-        let wf = find_workflow(workflow.id.unwrap()).await;
-        if let Ok(wf) = wf {
-            populated_workflows.push(wf);
+/// Builds the node of a stored assigned workflow step, without its edges
+pub(crate) fn workflow_node(step: &AssignedWorkflowStep, params: HashMap<String, String>) -> Result<WorkflowNode, CustomError> {
+    let mut node = WorkflowNode::new(get_variant_by_id(step.WorkflowStepID)?);
+    node.setup_time = step.SetupTime;
+    node.time_per_page = step.TimePerPage;
+    for (name, text) in params {
+        let value = stored_param_value(text);
+        if name == MACHINE_COUNT_PARAM {
+            node.machine_count = serde_json::from_value(value).map_err(|_| CustomError::OtherError(
+                format!("Invalid {name} for assigned workflow step {}", step.id)))?;
+        } else if node.parameters.contains_key(&name) {
+            node.parameters.insert(name, value);
         }
     }
+    return Ok(node);
+}
 
-    Ok(populated_workflows)
+
+/// The overrides, print job snapshot and workflow snapshot of a report, as stored
+pub(crate) fn simulation_report_json(report: &SimulationReport) -> Result<(String, String, String), CustomError> {
+    let to_json = |e: serde_json::Error| CustomError::OtherError(e.to_string());
+    return Ok((
+        serde_json::to_string(&report.Overrides).map_err(to_json)?,
+        serde_json::to_string(&report.PrintJobSnapshot).map_err(to_json)?,
+        serde_json::to_string(&report.WorkflowSnapshot).map_err(to_json)?,
+    ));
 }
 
 
-// pub async fn query_workflow_steps() -> Result<Vec<WorkflowStep>> {
-//     return query("SELECT id, title, setup_time, time_per_page FROM workflow_step;",
-//         [], workflow_step_from_row);
-// }
+// These functions query the database and return the results of that query
+//
+// The query_ functions return a vector containing every item in the
+// given table
+// TODO: Expand these functions to allow for more specific queries
+//
+// The find_ functions return the row matching the given ID, or an
+// error if it doesn't exist
+//
+// Each of them forwards to the repository of the current database
+
+pub async fn query_errors_detailed() -> Result<Vec<ErrorDetailed>, CustomError> {
+    return repository()?.query_errors_detailed().await;
+}
+
+pub async fn query_print_jobs() -> Result<Vec<PrintJob>, CustomError> {
+    return repository()?.query_print_jobs().await;
+}
+
+/// Returns every workflow, from the cache once they've all been loaded.
+/// A unit of work always loads them, since it may have changed them
+pub async fn query_workflows() -> Result<Vec<Workflow>, CustomError> {
+    if in_unit_of_work() {
+        return repository()?.query_workflows().await;
+    }
+    let generation = {
+        let cache = WORKFLOW_CACHE.read().unwrap();
+        if cache.complete {
+            return Ok(cache.workflows.values().cloned().collect());
+        }
+        cache.generation
+    };
+    let workflows = repository()?.query_workflows().await?;
+    let mut cache = WORKFLOW_CACHE.write().unwrap();
+    if cache.generation == generation {
+        cache.workflows = workflows.iter().filter_map(|workflow| Some((workflow.id?, workflow.clone()))).collect();
+        cache.complete = true;
+    }
+    return Ok(workflows);
+}
 
-pub async fn query_simulation_reports() -> Result<Vec<SimulationReportDetailed>> {
-    return query("
-        SELECT 
-            simulation_report.id,
-            simulation_report.title,
-            simulation_report.creation_time,
-            simulation_report.total_time_taken,
-            printjobID,
-            workflowID,
-            workflow.title,
-            printjob.title,
-            rasterization_profile.title
-        FROM simulation_report
-        LEFT JOIN workflow
-            ON simulation_report.workflowID=workflow.id
-        LEFT JOIN printjob
-            ON simulation_report.printjobID=printjob.id
-        LEFT JOIN rasterization_profile
-            ON printjob.rasterization_profile_id=rasterization_profile.id;
-    ", [], simulation_report_detailed_from_row);
+pub async fn query_simulation_reports() -> Result<Vec<SimulationReportDetailed>, CustomError> {
+    return repository()?.query_simulation_reports().await;
 }
 
-pub async fn query_rasterization_profiles() -> Result<Vec<RasterizationProfile>> {
-    return query("SELECT id, title, profile FROM rasterization_profile;",
-        [], rasterization_profile_from_row);
+pub async fn query_rasterization_profiles() -> Result<Vec<RasterizationProfile>, CustomError> {
+    return repository()?.query_rasterization_profiles().await;
 }
 
 pub async fn find_print_job(id: DocID) -> Result<PrintJob,CustomError> {
-    let rows = query("SELECT id, title, creation_time, page_count, rasterization_profile_id FROM printjob WHERE id=(?);",
-        [id], print_job_from_row)?;
-    return check_id_lookup_results(rows);
+    return repository()?.find_print_job(id).await;
 }
 
 pub async fn find_rasterization_profile(id: DocID) -> Result<RasterizationProfile,CustomError> {
-    let rows = query("SELECT id, title, profile FROM rasterization_profile WHERE id=(?);",
-        [id], rasterization_profile_from_row)?;
-    return check_id_lookup_results(rows);
+    return repository()?.find_rasterization_profile(id).await;
 }
 
-pub async fn find_simulation_report_workflow_steps(id: DocID) -> Result<HashMap<u32,u32>> {
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt0 = db.prepare("
-        SELECT
-            simulation_report_step_time.workflow_step_id,
-            simulation_report_step_time.step_time
-        FROM simulation_report_step_time
-        WHERE simulation_report_step_time.simulation_report_id = (?);
-    ")?;
-    let mut step_times : HashMap<u32,u32> = HashMap::from([]);
-    let rows = stmt0.query_map([id], |row| {
-        let key : u32 = row.get(0)?;
-        let value : u32 = row.get(1)?;
-        Ok((key,value))
-    })?;
+pub async fn find_simulation_report_workflow_steps(id: DocID) -> Result<HashMap<u32,u32>, CustomError> {
+    return repository()?.find_simulation_report_workflow_steps(id).await;
+}
 
-    for row in rows {
-        let (key, value) = row?;
-        step_times.insert(key,value);
+/// Returns the workflow with the given ID, from the cache if it's there
+pub async fn find_workflow(id: DocID) -> Result<Workflow, CustomError> {
+    if in_unit_of_work() {
+        return repository()?.find_workflow(id).await;
     }
+    let generation = {
+        let cache = WORKFLOW_CACHE.read().unwrap();
+        match cache.workflows.get(&id) {
+            Some(workflow) => return Ok(workflow.clone()),
+            None if cache.complete => return Err(CustomError::NotFound),
+            None => cache.generation,
+        }
+    };
+    let workflow = repository()?.find_workflow(id).await?;
+    let mut cache = WORKFLOW_CACHE.write().unwrap();
+    if cache.generation == generation {
+        cache.workflows.insert(id, workflow.clone());
+    }
+    return Ok(workflow);
+}
 
-    Ok(step_times)
+pub async fn get_workflow_step_ids() -> Result<Vec<DocID>, CustomError> {
+    return repository()?.get_workflow_step_ids().await;
+}
 
+/// Returns the catalog entries of the custom workflow steps, including retired steps
+pub async fn find_custom_workflow_steps() -> Result<Vec<WFSAttributes>, CustomError> {
+    return repository()?.find_custom_workflow_steps().await;
 }
 
-/// Returns the workflow with the given ID
-pub async fn find_workflow(id: DocID) -> Result<Workflow, CustomError> {
-    // TODO: refactor similar to other find functions
-    let db = DB_CONNECTION.lock().unwrap();
+/// Returns the catalog entry a workflow step was last loaded with, if it was stored
+pub async fn find_workflow_step_definition(id: DocID) -> Result<Option<WFSAttributes>, CustomError> {
+    return repository()?.find_workflow_step_definition(id).await;
+}
 
-    // Get the workflow matching the supplied id
-    let mut stmt0 = db.prepare("SELECT id, title FROM workflow WHERE id=(?);")?;
-    let mut workflow_iter = stmt0.query_map([id], workflow_from_row)?;
+/// Returns the generic parameters of every assigned step of the given workflow step,
+/// as (assigned workflow step ID, name, value)
+pub async fn find_workflow_step_params(workflow_step_id: DocID) -> Result<Vec<(DocID, String, String)>, CustomError> {
+    return repository()?.find_workflow_step_params(workflow_step_id).await;
+}
 
-    let mut workflow = match workflow_iter.next() {
-        Some(Ok(w)) => w,
-        _ => return Err(CustomError::DatabaseError(Error::QueryReturnedNoRows)),
-    };
+/// Returns the ID after the highest workflow step ID
+pub async fn find_next_workflow_step_id() -> Result<DocID, CustomError> {
+    return repository()?.find_next_workflow_step_id().await;
+}
 
-    // Get all of the steps that belong to this workflow
-    let mut stmt1 = db.prepare("
-        SELECT 
-            assigned_workflow_step.id, 
-            workflow_id, 
-            workflow_step_id, 
-            rasterization_params.id 
-        FROM assigned_workflow_step 
-        LEFT JOIN rasterization_params 
-            ON rasterization_params.assigned_workflow_step_id = assigned_workflow_step.id 
-        WHERE workflow_id = ?"
-    )?;
-    let steps_iter = stmt1.query_map([id], |row: &Row| assigned_workflow_step_from_row(row))?
-        .collect::<Result<Vec<_>, _>>()?;
-
-    // Place all workflow steps in a vector. Keep track of which step is at which index.
-    drop(workflow_iter); drop(stmt0); drop(stmt1); drop(db);
-    let mut id_to_indice : HashMap<DocID, usize> = HashMap::new();
-    for step in &steps_iter {
-        id_to_indice.insert(step.id, workflow.Steps.len());
-        let mut variant = get_variant_by_id(step.WorkflowStepID).unwrap();
-        match (&mut variant, step.param_id) {
-            (WFSVariant::Rasterization {ref mut num_cores}, Some(id)) => {
-                *num_cores = check_id_lookup_results(
-                    query("SELECT num_of_RIPs FROM rasterization_params WHERE id=(?)", 
-                    [id], |row: &Row| { Ok(row.get(0)?) })?)?;
-                // dbg!(&num_cores);
-            },
-            (WFSVariant::Rasterization {..}, None) => return Err(CustomError::OtherError(
-                "Rasterization requires prop_id".to_string(),)),
-            
-            (EMPTY_WFS_VARIANT!(), Some(_)) => return Err(CustomError::OtherError(
-                "Given WorkflowStep doesn't require prop_id".to_string(),)),
-            (EMPTY_WFS_VARIANT!(), None) => {}
-        }
-        // dbg!(&variant);
-        workflow.Steps.push(WorkflowNode {
-            data: variant,
-            prev: vec![],
-            next: vec![],
-        });
-    }
-
-    // Add previous and next workflow step information to each step.
-    // for step in &mut workflow.Steps {
-    let db = DB_CONNECTION.lock().unwrap();
-    for step in steps_iter {
-        let step_id = step.id;
-
-        // Add all of the steps that come next
-        let mut stmt2 = db.prepare("SELECT assigned_workflow_step_id, next_step_id FROM next_workflow_step WHERE assigned_workflow_step_id=(?);")?;
-        let next_steps_iter = stmt2.query_map([step_id], |row| {
-            let next_step_id: u32 = row.get(1)?;
-            Ok(next_step_id)
-        })?;
-        for next_step_result in next_steps_iter {
-            let next_step = match next_step_result {
-                Ok(s) => s,
-                Err(_) => return Err(CustomError::OtherError("Failed to find steps for workflow.".to_string())),
-            };
-            workflow.Steps[*id_to_indice.get(&step_id).unwrap()]
-                .next.push(*id_to_indice.get(&next_step).unwrap());
-        }
+/// Counts the assigned workflow steps and simulation report times which refer to a workflow step
+pub async fn count_workflow_step_uses(id: DocID) -> Result<u32, CustomError> {
+    return repository()?.count_workflow_step_uses(id).await;
+}
 
-        // Add all of the steps that come before this step
-        let mut stmt3 = db.prepare("SELECT assigned_workflow_step_id, prev_step_id FROM prev_workflow_step WHERE assigned_workflow_step_id=(?);")?;
-        let prev_steps_iter = stmt3.query_map([step_id], |row| {
-            let next_step_id: u32 = row.get(1)?;
-            Ok(next_step_id)
-        })?;
-        for prev_step_result in prev_steps_iter {
-            let prev_step = match prev_step_result {
-                Ok(s) => s,
-                Err(_) => return Err(CustomError::OtherError("Failed to find steps for workflow.".to_string())),
-            };
-            workflow.Steps[*id_to_indice.get(&step_id).unwrap()]
-                .prev.push(*id_to_indice.get(&prev_step).unwrap());
-        }
-    }
+pub async fn find_simulation_report(id: DocID) -> Result<SimulationReport,CustomError> {
+    return repository()?.find_simulation_report(id).await;
+}
 
-    return Ok(workflow);
+/// Returns when each node of the report's workflow started and finished, ordered by node
+pub async fn find_simulation_report_node_times(id: DocID) -> Result<Vec<NodeTime>, CustomError> {
+    return repository()?.find_simulation_report_node_times(id).await;
 }
 
-pub async fn get_workflow_step_ids() -> Result<Vec<DocID>> {
-    return query("SELECT id FROM workflow_step", [], |row: &Row| { Ok(row.get(0)?) });
+
+// Analytics across simulation reports
+//
+// Each of these is filtered to the reports created within the filter's
+// date range. Percentiles use the nearest-rank method
+
+pub(crate) fn filter_range(filter: &AnalyticsFilter) -> (u32, u32) {
+    return (filter.From.unwrap_or(0), filter.To.unwrap_or(u32::MAX));
 }
 
-pub async fn find_rasterization_params(param_id: DocID) -> Result<u32,CustomError> {
-    let rows = query("SELECT num_of_RIPs FROM rasterization_params WHERE id=(?)", 
-        [param_id], |row: &Row| { Ok(row.get(0)?) })?;
-    return check_id_lookup_results(rows);
+/// Total time statistics of the simulations of each workflow
+pub async fn query_workflow_time_statistics(filter: &AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+    return repository()?.query_workflow_time_statistics(filter.clone()).await;
 }
 
-pub async fn find_simulation_report(id: DocID) -> Result<SimulationReport,CustomError> {
-    let rows = query("SELECT id, creation_time, total_time_taken, printjobID, workflowID FROM simulation_report WHERE id=(?);",
-        [id], simulation_report_from_row)?;
-    return check_id_lookup_results(rows);
+/// Time statistics of each type of workflow step across simulations
+pub async fn query_workflow_step_time_statistics(filter: &AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+    let mut output = repository()?.query_workflow_step_time_statistics(filter.clone()).await?;
+    for stats in &mut output {
+        stats.Title = get_variant_by_id(stats.id).map(|v| v.title()).unwrap_or_default();
+    }
+    return Ok(output);
 }
 
+/// Average simulated time per page, bucketed by when the reports were created
+pub async fn query_time_per_page_trend(filter: &AnalyticsFilter) -> Result<Vec<TimePerPageTrend>, CustomError> {
+    return repository()?.query_time_per_page_trend(filter.clone()).await;
+}
+
+/// The workflows and rasterization profiles used by the most simulations
+pub async fn query_usage(filter: &AnalyticsFilter) -> Result<Usage, CustomError> {
+    return repository()?.query_usage(filter.clone()).await;
+}
+
+
+// The audit log. Every insert, update and delete below is recorded in it,
+// in the same unit of work as the change itself
+
+pub(crate) fn audit_filter_range(filter: &AuditFilter) -> (u32, u32) {
+    return (filter.From.unwrap_or(0), filter.To.unwrap_or(u32::MAX));
+}
+
+/// Returns the changes matching the filter, newest first
+pub async fn query_audit_log(filter: &AuditFilter) -> Result<Vec<AuditEntry>, CustomError> {
+    return repository()?.query_audit_log(filter.clone()).await;
+}
 
-pub async fn insert_error_detailed(data: ErrorDetailed) -> Result<u32> {
-    let db = DB_CONNECTION.lock().unwrap();
-    
-    db.execute(
-        "INSERT INTO errors_detailed (id, date_occured, status, domain, request, method, response) VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6)",
-        params![data.date, data.status, data.domain, data.request, data.method, data.response]
-    )?;
+/// Records a change in the audit log
+///
+/// ### Arguments
+/// * `entity`, `id` - The changed item
+/// * `action` - What the change did
+/// * `before` - The item before the change, if it existed
+/// * `after` - The item after the change, if it still exists
+async fn audit(entity: AuditEntity, id: DocID, action: AuditAction,
+    before: Option<serde_json::Value>, after: Option<serde_json::Value>) -> Result<(), CustomError> {
+    repository()?.insert_audit_entry(AuditEntry {
+        id: None,
+        Time: unix_time(),
+        Entity: entity,
+        EntityID: id,
+        Action: action,
+        // Requests aren't authenticated yet
+        Actor: None,
+        Before: before,
+        After: after,
+    }).await?;
+    return Ok(());
+}
 
-    let inserted_id : u32 = db.last_insert_rowid() as u32;
-    return Ok(inserted_id);
+fn to_audit_json<T: Serialize>(value: &T) -> Result<serde_json::Value, CustomError> {
+    return serde_json::to_value(value).map_err(|e| CustomError::OtherError(e.to_string()));
 }
 
-pub async fn insert_print_job(data: PrintJob) -> Result<DocID> {
-    let db = DB_CONNECTION.lock().unwrap();
-    
-    db.execute(
-        "INSERT INTO printjob (id, title, creation_time, page_count, rasterization_profile_id) VALUES (NULL, ?1, ?2, ?3, ?4)",
-        params![data.Title, data.DateCreated, data.PageCount, data.RasterizationProfileID]
-    )?;
 
-    let inserted_id : u32 = db.last_insert_rowid() as u32;
-	return Ok(inserted_id);
+pub async fn insert_error_detailed(data: ErrorDetailed) -> Result<u32, CustomError> {
+    return repository()?.insert_error_detailed(data).await;
 }
 
-pub async fn insert_rasterization_profile(data: RasterizationProfile) -> Result<DocID> {
-    let db = DB_CONNECTION.lock().unwrap();
-    
-    db.execute(
-        "INSERT INTO rasterization_profile (id, title) VALUES (?1, ?2);",
-        params![data.id, data.title]
-    )?;
+pub async fn insert_print_job(data: PrintJob) -> Result<DocID, CustomError> {
+    return unit_of_work(|| async move {
+        // A profile in the trash can't be used until it's restored
+        match find_rasterization_profile(data.RasterizationProfileID).await {
+            Err(CustomError::NotFound) => return Err(CustomError::InvalidPrintJob(
+                format!("RasterizationProfile {} doesn't exist or is in the trash", data.RasterizationProfileID))),
+            result => result?,
+        };
+        let id = repository()?.insert_print_job(data).await?;
+        let after = to_audit_json(&find_print_job(id).await?)?;
+        audit(AuditEntity::PrintJob, id, AuditAction::Insert, None, Some(after)).await?;
+        return Ok(id);
+    }).await;
+}
 
-    let inserted_id : u32 = db.last_insert_rowid() as u32;
-	return Ok(inserted_id);
+pub async fn insert_rasterization_profile(data: RasterizationProfile) -> Result<DocID, CustomError> {
+    return unit_of_work(|| async move {
+        let id = repository()?.insert_rasterization_profile(data).await?;
+        let after = to_audit_json(&find_rasterization_profile(id).await?)?;
+        audit(AuditEntity::RasterizationProfile, id, AuditAction::Insert, None, Some(after)).await?;
+        return Ok(id);
+    }).await;
 }
 
 /// Inserts a new workflow into the database
 pub async fn insert_workflow(data: WorkflowArgs) -> Result<DocID,CustomError> {
     let wf_title = data.Title.clone();
     let mut workflow = Workflow{id: data.id, Title: wf_title, Steps: vec![]};
-    
-    // TODO: Caleb needs to make this less garbage
-    workflow.Steps = match fill_edges(data.WorkflowSteps.clone().into_iter()
-        .map(|s| WorkflowNode { 
-            data: get_variant_by_id(s.WorkflowStepID).unwrap(),
-            prev: vec![],
-            next: vec![]
-        })
-        .collect::<Vec<_>>())
-    {
+
+    let mut nodes = vec![];
+    for step_args in &data.WorkflowSteps {
+        let mut node = WorkflowNode::new(get_variant_by_id(step_args.WorkflowStepID)?);
+        if node.data.deprecated() {
+            return Err(CustomError::InvalidWorkflow(format!("{} is deprecated", node.data.title())));
+        }
+        for (name, value) in step_args.all_parameters() {
+            node.set_parameter(&name, &value).map_err(CustomError::InvalidWorkflow)?;
+        }
+        nodes.push(node);
+    }
+    workflow.Steps = match fill_edges(nodes) {
         Ok(s) => s,
         Err(_) => return Err(CustomError::OtherError("".to_string())),
     };
-    
-    // Open db connection
-    let db = DB_CONNECTION.lock().unwrap();
-
-    // Insert the Workflow
-    db.execute(
-        "INSERT INTO workflow (id, title) VALUES (NULL, ?1)",
-        params![data.Title]
-    )?;
-    let inserted_id : DocID = db.last_insert_rowid() as DocID;
-    
-    // Load all workflow steps into the database.
-    let mut indexcounter : usize = 0;
-    let mut index_to_id : HashMap<usize, DocID> = HashMap::new();
-    for step_args in &data.WorkflowSteps {
-        db.execute(
-            "INSERT INTO assigned_workflow_step (id, workflow_id, workflow_step_id) VALUES (NULL, ?1, ?2)",
-            params![inserted_id, step_args.WorkflowStepID]
-        )?;
-
-        // map the primary key of each AssignedWorkflowStep to it's index in the vector.
-        let inserted_id : DocID = db.last_insert_rowid() as DocID;
-        index_to_id.insert(indexcounter, inserted_id); 
-        indexcounter += 1;
-
-        match get_variant_by_id(step_args.WorkflowStepID)? {
-            WFSVariant::Rasterization {..} => {
-                db.execute("INSERT INTO rasterization_params (id, assigned_workflow_step_id, num_of_RIPs) VALUES (NULL, ?1, ?2)",
-                    params![inserted_id, step_args.NumCores])?;
-            },
-            EMPTY_WFS_VARIANT!() => {}
+
+    return unit_of_work(|| async move {
+        let id = repository()?.insert_workflow(data, workflow).await?;
+        invalidate_cached_workflows();
+        let after = to_audit_json(&find_workflow(id).await?)?;
+        audit(AuditEntity::Workflow, id, AuditAction::Insert, None, Some(after)).await?;
+        return Ok(id);
+    }).await;
+}
+
+/// Inserts a workflow step from the catalog, or updates its title if it already exists
+pub async fn insert_workflow_step(id: DocID, title: &str, definition: &str) -> Result<(),CustomError> {
+    let (title, definition) = (title.to_string(), definition.to_string());
+    return unit_of_work(|| async move {
+        let before = find_workflow_step_definition(id).await?.map(|before| to_audit_json(&before)).transpose()?;
+        repository()?.insert_workflow_step(id, title, definition.clone()).await?;
+        return audit_workflow_step_definition(id, before, &definition).await;
+    }).await;
+}
+
+/// Inserts a custom workflow step along with its catalog entry
+pub async fn insert_custom_workflow_step(id: DocID, title: &str, definition: &str) -> Result<(),CustomError> {
+    let (title, definition) = (title.to_string(), definition.to_string());
+    return unit_of_work(|| async move {
+        repository()?.insert_custom_workflow_step(id, title, definition.clone()).await?;
+        return audit_workflow_step_definition(id, None, &definition).await;
+    }).await;
+}
+
+/// Records a new definition of a workflow step, unless it's the same as the one before.
+/// The catalog is stored again on every startup, so most of the time nothing changes
+async fn audit_workflow_step_definition(id: DocID, before: Option<serde_json::Value>, definition: &str) -> Result<(), CustomError> {
+    let after: serde_json::Value = serde_json::from_str(definition).map_err(|e| CustomError::OtherError(e.to_string()))?;
+    return match before {
+        Some(before) if before == after => Ok(()),
+        Some(before) => audit(AuditEntity::WorkflowStep, id, AuditAction::Update, Some(before), Some(after)).await,
+        None => audit(AuditEntity::WorkflowStep, id, AuditAction::Insert, None, Some(after)).await,
+    };
+}
+
+pub async fn insert_simulation_report(print_job_id: u32, workflow_id: u32, overrides: Vec<StepOverride>) -> Result<DocID,CustomError> {
+    // Run the simulation
+    let new_report = simulate(print_job_id, workflow_id, overrides).await?;
+    return store_simulation_report(&new_report).await;
+}
+
+/// Stores a simulated report along with its step and node times
+pub async fn store_simulation_report(new_report: &SimulationReport) -> Result<DocID,CustomError> {
+    let new_report = new_report.clone();
+    return unit_of_work(|| async move {
+        let id = repository()?.store_simulation_report(new_report).await?;
+        let after = to_audit_json(&find_simulation_report(id).await?)?;
+        audit(AuditEntity::SimulationReport, id, AuditAction::Insert, None, Some(after)).await?;
+        return Ok(id);
+    }).await;
+}
+
+
+// Functions to delete data from the database
+
+/// Moves an item to the trash, where it's hidden until it's restored or purged.
+/// Everything depending on it is moved to the trash first with `cascade`; otherwise,
+/// the item must have no dependents. Nothing is moved with `preview`
+///
+/// ### Arguments
+/// * `item` - The item to delete
+/// * `options` - Whether to cascade, and whether to only preview the deletion
+///
+/// ### Returns
+/// The items which were, or would be, deleted, each one before the items it
+/// depends on. An error if the item doesn't exist or has dependents it can't delete
+pub async fn delete_item(item: StoredItem, options: DeleteOptions) -> Result<Vec<StoredItem>, CustomError> {
+    return unit_of_work(|| async move {
+        find_item(&item).await?;
+        let dependents = find_dependents(&item, false).await?;
+        if !dependents.is_empty() && !options.cascade {
+            return Err(CustomError::HasDependents(item, dependents));
         }
-    }
 
-    indexcounter = 0;
-    for step in &workflow.Steps {
-        for next_step in &step.next {
-            db.execute(
-                "INSERT INTO next_workflow_step (assigned_workflow_step_id, next_step_id) VALUES (?1, ?2)",
-                params![index_to_id.get(&indexcounter), index_to_id.get(next_step)] 
-                // params![step.data.id(), index_to_id.get(next_step)] 
-            )?;
+        let removed = find_dependency_tree(item, false).await?;
+        if !options.preview {
+            let now = unix_time();
+            for item in &removed {
+                let before = find_item(item).await?;
+                repository()?.trash_item(item.clone(), now).await?;
+                if item.Type == ItemType::Workflow {
+                    invalidate_cached_workflows();
+                }
+                audit(item.Type.into(), item.id, AuditAction::Delete, Some(before), None).await?;
+            }
+        }
+        return Ok(removed);
+    }).await;
+}
+
+/// Takes an item out of the trash, along with the items in the trash it depends on
+///
+/// ### Arguments
+/// * `item` - The item to restore
+///
+/// ### Returns
+/// The restored items, each one after the items it depends on.
+/// An error if the item isn't in the trash
+pub async fn restore_item(item: StoredItem) -> Result<Vec<StoredItem>, CustomError> {
+    return unit_of_work(|| async move {
+        let mut restored: Vec<StoredItem> = vec![];
+        let mut pending = vec![item.clone()];
+        while let Some(next) = pending.pop() {
+            if repository()?.restore_item(next.clone()).await? == 0 {
+                if next == item {
+                    return Err(CustomError::NotFound);
+                }
+                continue;
+            }
+            if next.Type == ItemType::Workflow {
+                invalidate_cached_workflows();
+            }
+            let after = find_item(&next).await?;
+            audit(next.Type.into(), next.id, AuditAction::Restore, None, Some(after)).await?;
+            pending.extend(repository()?.find_dependencies(next.clone()).await?);
+            restored.push(next);
+        }
+        restored.reverse();
+        return Ok(restored);
+    }).await;
+}
+
+/// Returns every item in the trash, most recently deleted first
+pub async fn query_trash() -> Result<Vec<TrashedItem>, CustomError> {
+    return repository()?.query_trash().await;
+}
+
+/// Permanently deletes the items which were moved to the trash at or before the given time.
+/// An item which something more recently deleted, or not deleted, depends on is kept
+///
+/// ### Arguments
+/// * `deleted_before` - The latest deletion time to purge, in seconds since the epoch
+///
+/// ### Returns
+/// The purged items, each one before the items it depends on
+pub async fn purge_trash(deleted_before: u32) -> Result<Vec<StoredItem>, CustomError> {
+    return unit_of_work(|| async move {
+        let expired: Vec<StoredItem> = query_trash().await?.into_iter()
+            .filter(|trashed| trashed.DeletedAt <= deleted_before)
+            .map(|trashed| StoredItem::new(trashed.Type, trashed.id))
+            .collect();
+
+        let mut purged: Vec<StoredItem> = vec![];
+        for item in &expired {
+            let tree = find_dependency_tree(item.clone(), true).await?;
+            if !tree.iter().all(|item| expired.contains(item)) || has_live_dependents(&tree).await? {
+                continue;
+            }
+            for item in tree {
+                if !purged.contains(&item) {
+                    remove_item(&item).await?;
+                    purged.push(item);
+                }
+            }
         }
+        return Ok(purged);
+    }).await;
+}
 
-        for prev_step in &step.prev {
-            db.execute(
-                "INSERT INTO prev_workflow_step (assigned_workflow_step_id, prev_step_id) VALUES (?1, ?2)",
-                params![index_to_id.get(&indexcounter), index_to_id.get(prev_step)] 
-                // params![index_to_id.get(&indexcounter), index_to_id.get(prev_step)] 
-            )?;
+/// Purges the trash of items older than the retention, then again every TRASH_PURGE_INTERVAL
+pub async fn purge_trash_periodically(retention: Duration) {
+    let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let deleted_before = unix_time().saturating_sub(retention.as_secs() as u32);
+        match purge_trash(deleted_before).await {
+            Ok(purged) if !purged.is_empty() => println!("Purged {} items from the trash", purged.len()),
+            Ok(_) => {},
+            Err(e) => eprintln!("Failed to purge the trash: {e}"),
         }
-        indexcounter+=1;
     }
+}
 
-    return Ok(inserted_id);
+/// Reads how long deleted items are kept in the trash from the
+/// PRINTOS_TRASH_RETENTION_DAYS environment variable, or the default of 30 days
+pub fn trash_retention() -> Duration {
+    let days = std::env::var(TRASH_RETENTION_ENV).ok()
+        .and_then(|days| days.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS);
+    return Duration::from_secs(days * 24 * 60 * 60);
 }
 
-pub async fn insert_workflow_step(data: DocID) -> Result<(),CustomError> {
-    let db = DB_CONNECTION.lock().unwrap();
-    db.execute("INSERT INTO workflow_step (id) VALUES (?1)", params![data])?;
-    return Ok(());
+pub(crate) fn unix_time() -> u32 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).expect("Issue discerning current time.").as_secs() as u32;
 }
 
-pub async fn insert_simulation_report(print_job_id: u32, workflow_id: u32) -> Result<DocID,CustomError> {
-    // Run the simulation
-    let new_report = match simulate(print_job_id, workflow_id).await {
-		Ok(data) => data,
-		Err(e) => return Err(CustomError::OtherError(e)),
-	};
+/// Returns the item as JSON, or an error if it doesn't exist
+async fn find_item(item: &StoredItem) -> Result<serde_json::Value, CustomError> {
+    return match item.Type {
+        ItemType::RasterizationProfile => to_audit_json(&find_rasterization_profile(item.id).await?),
+        ItemType::PrintJob => to_audit_json(&find_print_job(item.id).await?),
+        ItemType::Workflow => to_audit_json(&find_workflow(item.id).await?),
+        ItemType::SimulationReport => to_audit_json(&find_simulation_report(item.id).await?),
+    };
+}
+
+/// Returns the items which refer to the given item, and would be left dangling without it,
+/// either those in the trash or those which aren't.
+/// Simulation reports keep snapshots of their print job and workflow, so they don't depend on them
+async fn find_dependents(item: &StoredItem, trashed: bool) -> Result<Vec<StoredItem>, CustomError> {
+    let (dependent_type, ids) = match item.Type {
+        ItemType::RasterizationProfile =>
+            (ItemType::PrintJob, repository()?.find_rasterization_profile_print_jobs(item.id, trashed).await?),
+        ItemType::SimulationReport =>
+            (ItemType::SimulationReport, repository()?.find_simulation_report_reruns(item.id, trashed).await?),
+        ItemType::PrintJob | ItemType::Workflow => return Ok(vec![]),
+    };
+    return Ok(ids.into_iter().map(|id| StoredItem::new(dependent_type, id)).collect());
+}
 
-    // Store resulting simulation data in the db.
-    let db = DB_CONNECTION.lock().unwrap();
-    db.execute(
-        "INSERT INTO simulation_report (id, title, creation_time, total_time_taken, printjobID, workflowID) VALUES (NULL, 'Default', ?1, ?2, ?3, ?4)",
-        params![new_report.CreationTime, new_report.TotalTimeTaken, new_report.PrintJobID, new_report.WorkflowID]
-    )?;
-    let inserted_id : u32 = db.last_insert_rowid() as u32;
-    
-    //Store the simulation time data in the db.
-    for (id, time) in new_report.StepTimes {
-        db.execute(
-            "INSERT INTO simulation_report_step_time (simulation_report_id, workflow_step_id, step_time) VALUES (?1, ?2, ?3)",
-            params![inserted_id, id, time]
-        )?;
+/// Returns the item along with everything depending on it, recursively,
+/// each one before the items it depends on
+async fn find_dependency_tree(item: StoredItem, trashed: bool) -> Result<Vec<StoredItem>, CustomError> {
+    // Every item comes after what depends on it, once reversed
+    let mut tree: Vec<StoredItem> = vec![];
+    let mut pending = vec![item];
+    while let Some(next) = pending.pop() {
+        if tree.contains(&next) {
+            continue;
+        }
+        pending.extend(find_dependents(&next, trashed).await?);
+        tree.push(next);
     }
+    tree.reverse();
+    return Ok(tree);
+}
 
-    return Ok(inserted_id);
+/// Whether any of the items has dependents which aren't in the trash,
+/// such as a print job created with a profile after the profile was deleted
+async fn has_live_dependents(items: &[StoredItem]) -> Result<bool, CustomError> {
+    for item in items {
+        if !find_dependents(item, false).await?.is_empty() {
+            return Ok(true);
+        }
+    }
+    return Ok(false);
 }
 
+/// Permanently deletes an item with the given function, and records it in the audit log
+/// along with how it was listed in the trash, if it was there
+async fn remove_audited<F, Fut>(item: StoredItem, remove: F) -> Result<usize, CustomError>
+    where F: FnOnce(Arc<dyn Repository>) -> Fut, Fut: Future<Output = Result<usize, CustomError>> {
+
+    return unit_of_work(|| async move {
+        let before = query_trash().await?.into_iter()
+            .find(|trashed| trashed.Type == item.Type && trashed.id == item.id)
+            .map(|trashed| to_audit_json(&trashed))
+            .transpose()?;
+        let removed = remove(repository()?).await?;
+        if removed > 0 {
+            audit(item.Type.into(), item.id, AuditAction::Purge, before, None).await?;
+        }
+        return Ok(removed);
+    }).await;
+}
+
+async fn remove_item(item: &StoredItem) -> Result<usize, CustomError> {
+    return match item.Type {
+        ItemType::RasterizationProfile => remove_rasterization_profile(item.id).await,
+        ItemType::PrintJob => remove_print_job(item.id).await,
+        ItemType::Workflow => remove_workflow(item.id).await,
+        ItemType::SimulationReport => remove_simulation_report(item.id).await,
+    };
+}
 
-/**
- * Functions to delete data from the database
- **/
 
-pub async fn remove_print_job(id: DocID) -> Result<usize> {
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt = db.prepare("DELETE FROM printjob WHERE id=(?)")?;
-    let res = stmt.execute([id])?;
-    return Ok(res);
+pub async fn remove_print_job(id: DocID) -> Result<usize, CustomError> {
+    return remove_audited(StoredItem::new(ItemType::PrintJob, id),
+        |repository| async move { repository.remove_print_job(id).await }).await;
 }
 
 
-pub async fn remove_rasterization_profile(id: DocID) -> Result<usize> {
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt = db.prepare("DELETE FROM rasterization_profile WHERE id=(?)")?;
-    let res = stmt.execute([id])?;
-    return Ok(res);
+pub async fn remove_rasterization_profile(id: DocID) -> Result<usize, CustomError> {
+    return remove_audited(StoredItem::new(ItemType::RasterizationProfile, id),
+        |repository| async move { repository.remove_rasterization_profile(id).await }).await;
 }
 
 
 /// Deletes the assigned workflow steps associated with this
 /// workflow id, then deletes the workflow itself.
-pub async fn remove_workflow(id: DocID) -> Result<usize> {
-    let db = DB_CONNECTION.lock().unwrap();
-
-    // Delete all assigned workflow steps associated with the workflow
-    let mut stmt_steps = db.prepare("DELETE FROM assigned_workflow_step WHERE workflow_id=(?)")?;
-    stmt_steps.execute([id])?;
+pub async fn remove_workflow(id: DocID) -> Result<usize, CustomError> {
+    return remove_audited(StoredItem::new(ItemType::Workflow, id),
+        |repository| async move {
+            invalidate_cached_workflows();
+            return repository.remove_workflow(id).await;
+        }).await;
+}
 
-    // Delete the workflow
-    let mut stmt = db.prepare("DELETE FROM workflow WHERE id=(?)")?;
-    let res = stmt.execute([id])?;
 
-    return Ok(res);
+/// Changes every assigned step of one workflow step into the other, returning how many were changed
+pub async fn update_assigned_workflow_step_type(from: DocID, to: DocID) -> Result<usize, CustomError> {
+    return unit_of_work(|| async move {
+        let changed = repository()?.update_assigned_workflow_step_type(from, to).await?;
+        invalidate_cached_workflows();
+        if changed > 0 {
+            audit(AuditEntity::WorkflowStep, from, AuditAction::Update,
+                Some(serde_json::json!({ "WorkflowStepID": from, "AssignedSteps": changed })),
+                Some(serde_json::json!({ "WorkflowStepID": to, "AssignedSteps": changed }))).await?;
+        }
+        return Ok(changed);
+    }).await;
 }
 
+pub async fn remove_assigned_workflow_step_param(id: DocID, name: &str) -> Result<usize, CustomError> {
+    let name = name.to_string();
+    return unit_of_work(|| async move {
+        let removed = repository()?.remove_assigned_workflow_step_param(id, name.clone()).await?;
+        invalidate_cached_workflows();
+        if removed > 0 {
+            audit(AuditEntity::AssignedWorkflowStep, id, AuditAction::Update,
+                Some(serde_json::json!({ "Parameter": name })), Some(serde_json::json!({}))).await?;
+        }
+        return Ok(removed);
+    }).await;
+}
 
-pub async fn remove_workflow_step(id: DocID) -> Result<usize> {
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt = db.prepare("DELETE FROM workflow_step WHERE id=(?)")?;
-    let res = stmt.execute([id])?;
-    return Ok(res);
+pub async fn remove_workflow_step(id: DocID) -> Result<usize, CustomError> {
+    return unit_of_work(|| async move {
+        let before = find_workflow_step_definition(id).await?.map(|before| to_audit_json(&before)).transpose()?;
+        let removed = repository()?.remove_workflow_step(id).await?;
+        if removed > 0 {
+            audit(AuditEntity::WorkflowStep, id, AuditAction::Purge, before, None).await?;
+        }
+        return Ok(removed);
+    }).await;
 }
 
 
-pub async fn remove_simulation_report(id: DocID) -> Result<usize> {
-    let db = DB_CONNECTION.lock().unwrap();
-    let mut stmt = db.prepare("DELETE FROM simulation_report WHERE id=(?)")?;
-    let res = stmt.execute([id])?;
-    return Ok(res);
+pub async fn remove_simulation_report(id: DocID) -> Result<usize, CustomError> {
+    return remove_audited(StoredItem::new(ItemType::SimulationReport, id),
+        |repository| async move { repository.remove_simulation_report(id).await }).await;
 }
 
 pub async fn insert_user(email: String, password: String) -> Result<DocID,CustomError> {
@@ -717,11 +1550,10 @@ pub async fn insert_user(email: String, password: String) -> Result<DocID,Custom
     hasher.update(password.as_bytes());
     let password_hash = hasher.finalize().to_vec();
 
-    let db = DB_CONNECTION.lock().unwrap();
-    db.execute(
-        "INSERT INTO user (email, password_hash) VALUES (?1, ?2)",
-        params![email, password_hash]
-    )?;
-    let inserted_id : u32 = db.last_insert_rowid() as u32;
-    return Ok(inserted_id);
+    return unit_of_work(|| async move {
+        let id = repository()?.insert_user(email.clone(), password_hash).await?;
+        // Leave out the password
+        audit(AuditEntity::User, id, AuditAction::Insert, None, Some(serde_json::json!({ "email": email }))).await?;
+        return Ok(id);
+    }).await;
 }
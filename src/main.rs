@@ -1,10 +1,17 @@
 use crate::api::*;
 use crate::database::*;
+use crate::migrations::*;
 use std::env;
 use std::process;
+pub mod analysis;
+pub mod backup;
 pub mod api;
 pub mod database;
+pub mod migrations;
+pub mod postgresql;
+pub mod repository;
 pub mod simulation;
+pub mod sqlite;
 pub mod workflow;
 pub mod workflow_steps;
 
@@ -38,15 +45,88 @@ pub async fn run_server(host: &str, port: &str) {
     }
 }
 
+/// Prints which migrations the database still needs, without applying them
+async fn print_migration_status() {
+    match migration_status().await {
+        Ok(status) => {
+            println!("Schema version {} of {}", status.CurrentVersion, status.LatestVersion);
+            for name in &status.Pending {
+                println!("Pending migration {name}");
+            }
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Migrates the database and inserts the sample data, unless it already has rasterization profiles
+async fn seed_sample_data() {
+    let result = match migrate_database().await {
+        Ok(()) => seed_database().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(true) => println!("Inserted the sample data"),
+        Ok(false) => println!("The database already has rasterization profiles, so no sample data was inserted"),
+        Err(e) => {
+            eprintln!("Failed to seed database: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Removes the database options from the arguments and returns where the database is.
+/// `--database <path>` and `--in-memory` take precedence over the PRINTOS_DATABASE
+/// environment variable, which defaults to ./db/database.db3
+fn take_database_location(args: &mut Vec<String>) -> Result<DatabaseLocation, String> {
+    let mut location = DatabaseLocation::from_env();
+    while let Some(i) = args.iter().position(|arg| arg == "--database" || arg == "--in-memory") {
+        let option = args.remove(i);
+        location = match option.as_str() {
+            "--in-memory" => DatabaseLocation::InMemory,
+            _ if i < args.len() => DatabaseLocation::parse(&args.remove(i)),
+            _ => return Err("--database requires a path".to_string()),
+        };
+    }
+    return Ok(location);
+}
+
 #[tokio::main]
 pub async fn main() {
     // Use 'l' as an argument for local testing
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let location = match take_database_location(&mut args) {
+        Ok(location) => location,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    if let Err(e) = open_database(location.clone()) {
+        eprintln!("Failed to open database {location}: {e}");
+        process::exit(1);
+    }
+
+    // Sample data is only inserted when asked for, so it never ends up in a production database
+    if let Some(i) = args.iter().position(|arg| arg == "--seed") {
+        args.remove(i);
+        seed_sample_data().await;
+    }
+    if args.len() > 1 && args[1] == "status" {
+        print_migration_status().await;
+        return;
+    }
     let (host, port) = if args.len() > 1 && args[1] == "l" {
         ("localhost", "5040")
     } else {
         (HOST, PORT)
     };
 
+    // Purge the trash of old items in the background. This is only done by the server
+    // process itself, so tests running the server don't purge each other's databases
+    tokio::spawn(purge_trash_periodically(trash_retention()));
+
     run_server(host, port).await;
 }
@@ -0,0 +1,231 @@
+use crate::database::*;
+use serde::Serialize;
+
+/// A versioned change to the database schema, embedded in the binary
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/**
+ * Every migration, in the order they are applied. Each one is applied once,
+ * in its own transaction, and recorded in the schema_version table
+ *
+ * To change the schema, add a new file to db/migrations and a new entry
+ * below with the next version, along with the same change for PostgreSQL
+ * in POSTGRES_MIGRATIONS. A migration which has been released must
+ * not be edited, since databases which already applied it won't run it again.
+ * Migrations only change the schema, sample data is inserted by seed_database()
+ **/
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_database",
+        sql: include_str!("../db/migrations/0001_create_database.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "step_parameters",
+        sql: include_str!("../db/migrations/0002_step_parameters.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "print_job_layout",
+        sql: include_str!("../db/migrations/0003_print_job_layout.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "simulation_overrides",
+        sql: include_str!("../db/migrations/0004_simulation_overrides.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "simulation_report_node_time",
+        sql: include_str!("../db/migrations/0005_simulation_report_node_time.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "simulation_snapshots",
+        sql: include_str!("../db/migrations/0006_simulation_snapshots.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "simulation_rerun",
+        sql: include_str!("../db/migrations/0007_simulation_rerun.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "workflow_step_title",
+        sql: include_str!("../db/migrations/0008_workflow_step_title.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "workflow_step_definition",
+        sql: include_str!("../db/migrations/0009_workflow_step_definition.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "drop_rasterization_params",
+        sql: include_str!("../db/migrations/0010_drop_rasterization_params.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "step_timing_overrides",
+        sql: include_str!("../db/migrations/0011_step_timing_overrides.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "soft_delete",
+        sql: include_str!("../db/migrations/0012_soft_delete.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "audit_log",
+        sql: include_str!("../db/migrations/0013_audit_log.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "workflow_step_index",
+        sql: include_str!("../db/migrations/0014_workflow_step_index.sql"),
+    },
+];
+
+/// The same migrations as MIGRATIONS, written for PostgreSQL.
+/// Each one must have the same version and name as its SQLite counterpart
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_database",
+        sql: include_str!("../db/migrations/postgres/0001_create_database.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "step_parameters",
+        sql: include_str!("../db/migrations/postgres/0002_step_parameters.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "print_job_layout",
+        sql: include_str!("../db/migrations/postgres/0003_print_job_layout.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "simulation_overrides",
+        sql: include_str!("../db/migrations/postgres/0004_simulation_overrides.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "simulation_report_node_time",
+        sql: include_str!("../db/migrations/postgres/0005_simulation_report_node_time.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "simulation_snapshots",
+        sql: include_str!("../db/migrations/postgres/0006_simulation_snapshots.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "simulation_rerun",
+        sql: include_str!("../db/migrations/postgres/0007_simulation_rerun.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "workflow_step_title",
+        sql: include_str!("../db/migrations/postgres/0008_workflow_step_title.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "workflow_step_definition",
+        sql: include_str!("../db/migrations/postgres/0009_workflow_step_definition.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "drop_rasterization_params",
+        sql: include_str!("../db/migrations/postgres/0010_drop_rasterization_params.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "step_timing_overrides",
+        sql: include_str!("../db/migrations/postgres/0011_step_timing_overrides.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "soft_delete",
+        sql: include_str!("../db/migrations/postgres/0012_soft_delete.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "audit_log",
+        sql: include_str!("../db/migrations/postgres/0013_audit_log.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "workflow_step_index",
+        sql: include_str!("../db/migrations/postgres/0014_workflow_step_index.sql"),
+    },
+];
+
+/// Sample rasterization profiles, which can't be created from the GUI.
+/// They aren't part of the schema, so a database only gets them when it's seeded
+pub const SEED: &str = include_str!("../db/seed/dummy_data.sql");
+/// The same sample data as SEED, written for PostgreSQL
+pub const POSTGRES_SEED: &str = include_str!("../db/seed/postgres/dummy_data.sql");
+
+/// Which migrations a database has, and which it still needs
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub CurrentVersion: u32,
+    pub LatestVersion: u32,
+    /// Names of the migrations which haven't been applied, in order
+    pub Pending: Vec<String>,
+}
+
+/// The version of the newest migration this server knows about
+pub fn latest_version() -> u32 {
+    return MIGRATIONS.last().map_or(0, |m| m.version);
+}
+
+impl MigrationStatus {
+    /// The status of a database at the given version
+    pub fn new(current: u32) -> MigrationStatus {
+        return MigrationStatus {
+            CurrentVersion: current,
+            LatestVersion: latest_version(),
+            Pending: MIGRATIONS.iter()
+                .filter(|m| m.version > current)
+                .map(|m| format!("{:04}_{}", m.version, m.name))
+                .collect(),
+        };
+    }
+}
+
+/// Applies every pending migration to the database.
+/// Returns an error without changing anything if the database is newer than this server
+pub async fn migrate_database() -> Result<(), CustomError> {
+    return repository()?.migrate().await;
+}
+
+/// Reports which migrations are pending, without applying them.
+/// Returns an error if the database is newer than this server
+pub async fn migration_status() -> Result<MigrationStatus, CustomError> {
+    return repository()?.migration_status().await;
+}
+
+/// Inserts the sample data into the database, unless it already has rasterization
+/// profiles. The database must be migrated first
+///
+/// ### Returns
+/// Whether the sample data was inserted
+pub async fn seed_database() -> Result<bool, CustomError> {
+    return repository()?.seed().await;
+}
+
+/// Returns the given database version, or an error if it is newer than this server
+pub(crate) fn check_schema_version(version: u32) -> Result<u32, CustomError> {
+    if version > latest_version() {
+        return Err(CustomError::SchemaTooNew(version, latest_version()));
+    }
+    return Ok(version);
+}
@@ -0,0 +1,1009 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use async_trait::async_trait;
+use postgres::{types::ToSql, Client, Config, NoTls, Row};
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use crate::{
+    backup::BackupRow,
+    database::*,
+    migrations::*,
+    repository::Repository,
+    simulation::NodeTime,
+    workflow::*,
+    workflow_steps::*,
+};
+
+
+/// The client a unit of work holds until it ends. Its transaction is rolled
+/// back if the unit of work is dropped without being committed
+struct UnitOfWorkClient {
+    client: Option<PooledConnection<PostgresConnectionManager<NoTls>>>,
+    /// Whether the transaction hasn't been committed or rolled back yet
+    open: bool,
+}
+
+impl UnitOfWorkClient {
+    fn client(&mut self) -> &mut Client {
+        return self.client.as_mut().unwrap();
+    }
+}
+
+impl Drop for UnitOfWorkClient {
+    /// Rolling back blocks, which isn't allowed on a thread driving the async runtime
+    fn drop(&mut self) {
+        if let (true, Some(mut client)) = (self.open, self.client.take()) {
+            std::thread::spawn(move || client.batch_execute("ROLLBACK"));
+        }
+    }
+}
+
+
+/// Stores everything in a PostgreSQL database.
+///
+/// PostgreSQL has no unsigned integers, so IDs and times are stored as BIGINT
+/// and converted to and from the u32 the rest of the server uses
+pub struct PostgresRepository {
+    /// Always set, until the repository is dropped
+    pool: Option<Pool<PostgresConnectionManager<NoTls>>>,
+    stats: Arc<PoolStats>,
+    /// The client of the unit of work this repository belongs to, if any
+    unit_of_work: Option<Arc<Mutex<UnitOfWorkClient>>>,
+}
+
+impl PostgresRepository {
+    /// Creates a pool of connections to the database at the given URL
+    pub fn open(url: &str) -> Result<PostgresRepository, CustomError> {
+        let config: Config = url.parse()?;
+        let pool = Pool::builder()
+            .max_size(MAX_DB_CONNECTIONS)
+            .connection_timeout(DB_CONNECTION_TIMEOUT)
+            .build(PostgresConnectionManager::new(config, NoTls))?;
+        return Ok(PostgresRepository { pool: Some(pool), stats: Arc::new(PoolStats::default()), unit_of_work: None });
+    }
+
+    fn pool(&self) -> &Pool<PostgresConnectionManager<NoTls>> {
+        return self.pool.as_ref().unwrap();
+    }
+
+    /// Runs a function with a client borrowed from the pool, or the unit of work's
+    /// client, on a thread where blocking is allowed, so database work doesn't
+    /// hold up the async runtime
+    ///
+    /// ### Arguments
+    /// * `f` - Function that uses the client
+    ///
+    /// ### Returns
+    /// The result of the function, or an error if no client could be borrowed
+    async fn with_client<T, F>(&self, f: F) -> Result<T, CustomError>
+        where T: Send + 'static, F: FnOnce(&mut Client) -> Result<T, CustomError> + Send + 'static {
+
+        let pool = self.pool().clone();
+        let stats = self.stats.clone();
+        let unit_of_work = self.unit_of_work.clone();
+        return tokio::task::spawn_blocking(move || {
+            return match unit_of_work {
+                Some(connection) => f(connection.lock().unwrap().client()),
+                None => f(&mut *stats.checkout(&pool)?),
+            };
+        }).await?;
+    }
+
+    /// Runs a write of several rows like with_client(), in a transaction which is rolled
+    /// back if the write fails. In a unit of work, the write gets a savepoint instead
+    async fn with_transaction<T, F>(&self, f: F) -> Result<T, CustomError>
+        where T: Send + 'static, F: FnOnce(&mut Client) -> Result<T, CustomError> + Send + 'static {
+
+        let (begin, commit, rollback) = match self.unit_of_work {
+            None => ("BEGIN", "COMMIT", "ROLLBACK"),
+            Some(_) => ("SAVEPOINT write", "RELEASE SAVEPOINT write", "ROLLBACK TO SAVEPOINT write"),
+        };
+        return self.with_client(move |db| {
+            db.batch_execute(begin)?;
+            let result = f(db);
+            db.batch_execute(match result {
+                Ok(_) => commit,
+                Err(_) => rollback,
+            })?;
+            return result;
+        }).await;
+    }
+
+    /// Ends the transaction of this repository's unit of work
+    async fn end_unit_of_work(&self, sql: &'static str) -> Result<(), CustomError> {
+        let unit_of_work = self.unit_of_work.clone()
+            .ok_or_else(|| CustomError::OtherError("No unit of work is in progress".to_string()))?;
+        return tokio::task::spawn_blocking(move || {
+            let mut connection = unit_of_work.lock().unwrap();
+            connection.client().batch_execute(sql)?;
+            connection.open = false;
+            return Ok(());
+        }).await?;
+    }
+
+    /// Returns the results of a database query or an error, using a client from the pool
+    ///
+    /// ### Arguments
+    /// * `query` - SQL query to perform
+    /// * `params` - Integer parameters used in the query, as $1, $2, ...
+    /// * `f` - Function that turns a row from the database into the desired struct
+    ///
+    /// ### Returns
+    /// A vector of structs or an error
+    async fn query<T,F>(&self, query: impl Into<String>, params: Vec<i64>, f: F) -> Result<Vec<T>, CustomError>
+        where T: Send + 'static, F: FnMut(&Row) -> Result<T, CustomError> + Send + 'static {
+
+        let query = query.into();
+        return self.with_client(move |db| query_with(db, &query, &params, f)).await;
+    }
+}
+
+impl Drop for PostgresRepository {
+    /// Closing a client blocks, which isn't allowed on a thread driving the async
+    /// runtime, so the pool is closed on a thread of its own
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            std::thread::spawn(move || drop(pool));
+        }
+    }
+}
+
+
+/// Returns the results of a database query on the given client, for use
+/// inside with_client() when several queries should share a client
+fn query_with<T,F>(db: &mut Client, query: &str, params: &[i64], f: F) -> Result<Vec<T>, CustomError>
+    where F: FnMut(&Row) -> Result<T, CustomError> {
+
+    let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+    return db.query(query, &params)?.iter().map(f).collect();
+}
+
+
+// Create struct from database row
+//
+// These functions transform a row from a SQL query and creates the
+// appropriate struct from the result. These functions assume that
+// the columns in the row are in a specific order
+
+/// Reads a BIGINT column as a u32
+fn u32_at(row: &Row, index: usize) -> Result<u32, CustomError> {
+    return Ok(row.try_get::<_, i64>(index)? as u32);
+}
+
+/// Reads a nullable BIGINT column as a u32
+fn optional_u32_at(row: &Row, index: usize) -> Result<Option<u32>, CustomError> {
+    return Ok(row.try_get::<_, Option<i64>>(index)?.map(|value| value as u32));
+}
+
+/// Reads a column holding a JSON document
+fn json_at<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> Result<T, CustomError> {
+    let text: String = row.try_get(index)?;
+    return serde_json::from_str(&text).map_err(|e| CustomError::OtherError(e.to_string()));
+}
+
+/// Reads a nullable column holding a JSON document
+fn optional_json_at<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> Result<Option<T>, CustomError> {
+    return match row.try_get::<_, Option<String>>(index)? {
+        Some(_) => json_at(row, index).map(Some),
+        None => Ok(None),
+    };
+}
+
+fn error_detailed_from_row(row: &Row) -> Result<ErrorDetailed, CustomError> {
+    return Ok(ErrorDetailed {
+        id: Some(u32_at(row, 0)?),
+        date: u32_at(row, 1)?,
+        status: u32_at(row, 2)?,
+        domain: row.try_get(3)?,
+        request: row.try_get(4)?,
+        method: row.try_get(5)?,
+        response: row.try_get(6)?,
+    });
+}
+
+fn print_job_from_row(row: &Row) -> Result<PrintJob, CustomError> {
+    return Ok(PrintJob {
+        id: Some(u32_at(row, 0)?),
+        Title: row.try_get(1)?,
+        DateCreated: optional_u32_at(row, 2)?,
+        PageCount: u32_at(row, 3)?,
+        RasterizationProfileID: u32_at(row, 4)?,
+        PageSize: row.try_get::<_, String>(5)?.parse().map_err(|e: strum::ParseError| CustomError::OtherError(e.to_string()))?,
+        Duplex: row.try_get(6)?,
+        Copies: u32_at(row, 7)?,
+    });
+}
+
+fn workflow_from_row(row: &Row) -> Result<Workflow, CustomError> {
+    return Ok(Workflow {
+        id: Some(u32_at(row, 0)?),
+        Title: row.try_get(1)?,
+        Steps: vec![],
+    });
+}
+
+fn simulation_report_detailed_from_row(row: &Row) -> Result<SimulationReportDetailed, CustomError> {
+    return Ok(SimulationReportDetailed {
+        id: Some(u32_at(row, 0)?),
+        CreationTime: u32_at(row, 2)?,
+        TotalTimeTaken: u32_at(row, 3)?,
+        PrintJobID: u32_at(row, 4)?,
+        WorkflowID: u32_at(row, 5)?,
+        StepTimes: HashMap::from([]),
+        PrintJobTitle: row.try_get::<_, Option<String>>(6)?.unwrap_or_default(),
+        WorkflowTitle: row.try_get::<_, Option<String>>(7)?.unwrap_or_default(),
+        RasterizationProfile: row.try_get::<_, Option<String>>(8)?.unwrap_or_default(),
+    });
+}
+
+fn rasterization_profile_from_row(row: &Row) -> Result<RasterizationProfile, CustomError> {
+    return Ok(RasterizationProfile {
+        id: u32_at(row, 0)?,
+        title: row.try_get(1)?,
+        profile: row.try_get(2)?,
+    });
+}
+
+fn simulation_report_from_row(row: &Row) -> Result<SimulationReport, CustomError> {
+    return Ok(SimulationReport {
+        id: Some(u32_at(row, 0)?),
+        CreationTime: u32_at(row, 1)?,
+        TotalTimeTaken: u32_at(row, 2)?,
+        PrintJobID: u32_at(row, 3)?,
+        WorkflowID: u32_at(row, 4)?,
+        StepTimes: HashMap::new(),
+        Overrides: json_at(row, 5)?,
+        NodeTimes: vec![],
+        PrintJobSnapshot: optional_json_at(row, 6)?,
+        WorkflowSnapshot: optional_json_at(row, 7)?,
+        RerunOf: optional_u32_at(row, 8)?,
+    });
+}
+
+fn audit_entry_from_row(row: &Row) -> Result<AuditEntry, CustomError> {
+    let parse_error = |e: strum::ParseError| CustomError::OtherError(e.to_string());
+    return Ok(AuditEntry {
+        id: Some(u32_at(row, 0)?),
+        Time: u32_at(row, 1)?,
+        Entity: row.try_get::<_, String>(2)?.parse().map_err(parse_error)?,
+        EntityID: u32_at(row, 3)?,
+        Action: row.try_get::<_, String>(4)?.parse().map_err(parse_error)?,
+        Actor: row.try_get(5)?,
+        Before: optional_json_at(row, 6)?,
+        After: optional_json_at(row, 7)?,
+    });
+}
+
+fn trashed_item_from_row(row: &Row) -> Result<TrashedItem, CustomError> {
+    return Ok(TrashedItem {
+        Type: row.try_get::<_, String>(0)?.parse().map_err(|e: strum::ParseError| CustomError::OtherError(e.to_string()))?,
+        id: u32_at(row, 1)?,
+        Title: row.try_get(2)?,
+        DeletedAt: u32_at(row, 3)?,
+    });
+}
+
+fn node_time_from_row(row: &Row) -> Result<NodeTime, CustomError> {
+    return Ok(NodeTime {
+        NodeIndex: u32_at(row, 0)? as usize,
+        WorkflowStepID: u32_at(row, 1)?,
+        StartTime: u32_at(row, 2)?,
+        EndTime: u32_at(row, 3)?,
+    });
+}
+
+fn time_statistics_from_row(row: &Row) -> Result<TimeStatistics, CustomError> {
+    return Ok(TimeStatistics {
+        id: u32_at(row, 0)?,
+        Title: row.try_get::<_, Option<String>>(1)?.unwrap_or_default(),
+        Count: u32_at(row, 2)?,
+        Average: row.try_get(3)?,
+        Min: u32_at(row, 4)?,
+        Max: u32_at(row, 5)?,
+        P50: u32_at(row, 6)?,
+        P90: u32_at(row, 7)?,
+        P95: u32_at(row, 8)?,
+    });
+}
+
+fn time_per_page_trend_from_row(row: &Row) -> Result<TimePerPageTrend, CustomError> {
+    return Ok(TimePerPageTrend {
+        Time: u32_at(row, 0)?,
+        Count: u32_at(row, 1)?,
+        AverageTimePerPage: row.try_get(2)?,
+    });
+}
+
+fn usage_count_from_row(row: &Row) -> Result<UsageCount, CustomError> {
+    return Ok(UsageCount {
+        id: u32_at(row, 0)?,
+        Title: row.try_get::<_, Option<String>>(1)?.unwrap_or_default(),
+        Count: u32_at(row, 2)?,
+    });
+}
+
+fn assigned_workflow_step_from_row(row: &Row) -> Result<AssignedWorkflowStep, CustomError> {
+    return Ok(AssignedWorkflowStep {
+        id: u32_at(row, 0)?,
+        WorkflowStepID: u32_at(row, 2)?,
+        SetupTime: optional_u32_at(row, 3)?,
+        TimePerPage: optional_u32_at(row, 4)?,
+        Prev: vec![],
+        Next: vec![],
+    });
+}
+
+/// Builds a query for the count, average, min, max and percentiles of `value`
+/// grouped by `key`, over the rows of `source` created within $1 and $2
+fn time_statistics_query(source: &str, key: &str, value: &str) -> String {
+    return format!("
+        WITH ranked AS (
+            SELECT {key} AS key, {value} AS value,
+                ROW_NUMBER() OVER (PARTITION BY {key} ORDER BY {value}) AS row_number,
+                COUNT(*) OVER (PARTITION BY {key}) AS n
+            FROM {source}
+            WHERE simulation_report.creation_time BETWEEN $1 AND $2
+                AND simulation_report.deleted_at IS NULL
+        )
+        SELECT key, COUNT(*) AS count, AVG(value)::DOUBLE PRECISION AS average, MIN(value) AS min, MAX(value) AS max,
+            MIN(CASE WHEN row_number >= 0.50 * n THEN value END) AS p50,
+            MIN(CASE WHEN row_number >= 0.90 * n THEN value END) AS p90,
+            MIN(CASE WHEN row_number >= 0.95 * n THEN value END) AS p95
+        FROM ranked
+        GROUP BY key");
+}
+
+
+// Schema migrations
+
+/// Returns the version of the database, or an error if it is newer than this server
+fn schema_version(db: &mut Client) -> Result<u32, CustomError> {
+    let version = u32_at(&db.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])?, 0)?;
+    return check_schema_version(version);
+}
+
+fn has_table(db: &mut Client, name: &str) -> Result<bool, CustomError> {
+    return Ok(db.query_one("
+        SELECT EXISTS (SELECT 1 FROM information_schema.tables
+            WHERE table_schema = current_schema() AND table_name = $1)",
+        &[&name])?.try_get(0)?);
+}
+
+fn table_columns(db: &mut Client, table: &str) -> Result<Vec<String>, CustomError> {
+    return db.query("
+        SELECT column_name::TEXT FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1",
+        &[&table])?.iter().map(|row| Ok(row.try_get(0)?)).collect();
+}
+
+
+/// Returns the workflows which aren't in the trash, or only the one with the given ID,
+/// using the given client. Every workflow is loaded with the same five queries
+fn find_workflows_with(db: &mut Client, id: Option<DocID>) -> Result<Vec<Result<Workflow, CustomError>>, CustomError> {
+    let queries = WorkflowQueries::new(match id {
+        Some(_) => "workflow.id = $1",
+        None => "TRUE",
+    });
+    let params: Vec<i64> = id.map(i64::from).into_iter().collect();
+    let rows = WorkflowRows {
+        workflows: query_with(db, &queries.workflows, &params, workflow_from_row)?,
+        steps: query_with(db, &queries.steps, &params,
+            |row: &Row| { Ok((u32_at(row, 1)?, assigned_workflow_step_from_row(row)?)) })?,
+        params: query_with(db, &queries.params, &params,
+            |row: &Row| { Ok((u32_at(row, 0)?, row.try_get(1)?, row.try_get(2)?)) })?,
+        next: query_with(db, &queries.next, &params, |row: &Row| { Ok((u32_at(row, 0)?, u32_at(row, 1)?)) })?,
+        prev: query_with(db, &queries.prev, &params, |row: &Row| { Ok((u32_at(row, 0)?, u32_at(row, 1)?)) })?,
+    };
+    return Ok(assemble_workflows(rows));
+}
+
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn migrate(&self) -> Result<(), CustomError> {
+        return self.with_client(|db| {
+            db.batch_execute("
+                CREATE TABLE IF NOT EXISTS schema_version (
+                    version BIGINT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at BIGINT NOT NULL
+                );
+            ")?;
+            let current = schema_version(db)?;
+
+            for migration in POSTGRES_MIGRATIONS.iter().filter(|m| m.version > current) {
+                println!("Applying migration {} {}", migration.version, migration.name);
+                let mut tx = db.transaction()?;
+                tx.batch_execute(migration.sql)?;
+                tx.execute("INSERT INTO schema_version (version, name, applied_at) VALUES ($1, $2, EXTRACT(EPOCH FROM now())::BIGINT)",
+                    &[&i64::from(migration.version), &migration.name])?;
+                tx.commit()?;
+            }
+            return Ok(());
+        }).await;
+    }
+
+    async fn migration_status(&self) -> Result<MigrationStatus, CustomError> {
+        let current = self.with_client(|db| {
+            return Ok(match has_table(db, "schema_version")? {
+                true => schema_version(db)?,
+                false => 0,
+            });
+        }).await?;
+        return Ok(MigrationStatus::new(current));
+    }
+
+    async fn seed(&self) -> Result<bool, CustomError> {
+        return self.with_transaction(|db| {
+            let profiles = db.query_one("SELECT COUNT(*) FROM rasterization_profile", &[])?.try_get::<_, i64>(0)?;
+            if profiles > 0 {
+                return Ok(false);
+            }
+            db.batch_execute(POSTGRES_SEED)?;
+            return Ok(true);
+        }).await;
+    }
+
+    fn pool_metrics(&self) -> PoolMetrics {
+        return self.stats.metrics(self.pool());
+    }
+
+    async fn begin(&self) -> Result<Arc<dyn Repository>, CustomError> {
+        if self.unit_of_work.is_some() {
+            return Err(CustomError::OtherError("A unit of work is already in progress".to_string()));
+        }
+        let pool = self.pool().clone();
+        let stats = self.stats.clone();
+        let client = tokio::task::spawn_blocking(move || {
+            let mut db = stats.checkout(&pool)?;
+            db.batch_execute("BEGIN")?;
+            return Ok::<_, CustomError>(db);
+        }).await??;
+        return Ok(Arc::new(PostgresRepository {
+            pool: Some(self.pool().clone()),
+            stats: self.stats.clone(),
+            unit_of_work: Some(Arc::new(Mutex::new(UnitOfWorkClient { client: Some(client), open: true }))),
+        }));
+    }
+
+    async fn commit(&self) -> Result<(), CustomError> {
+        return self.end_unit_of_work("COMMIT").await;
+    }
+
+    async fn rollback(&self) -> Result<(), CustomError> {
+        return self.end_unit_of_work("ROLLBACK").await;
+    }
+
+    async fn query_errors_detailed(&self) -> Result<Vec<ErrorDetailed>, CustomError> {
+        return self.query("SELECT id, date_occured, status, domain, request, method, response FROM errors_detailed ORDER BY id",
+            vec![], error_detailed_from_row).await;
+    }
+
+    async fn insert_error_detailed(&self, data: ErrorDetailed) -> Result<DocID, CustomError> {
+        return self.with_client(move |db| {
+            let row = db.query_one(
+                "INSERT INTO errors_detailed (date_occured, status, domain, request, method, response) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                &[&i64::from(data.date), &i64::from(data.status), &data.domain, &data.request, &data.method, &data.response]
+            )?;
+            return u32_at(&row, 0);
+        }).await;
+    }
+
+    async fn insert_audit_entry(&self, entry: AuditEntry) -> Result<DocID, CustomError> {
+        let to_json = |value: &Option<serde_json::Value>| value.as_ref().map(|value| value.to_string());
+        let (before, after) = (to_json(&entry.Before), to_json(&entry.After));
+        return self.with_client(move |db| {
+            let row = db.query_one(
+                "INSERT INTO audit_log (time, entity, entity_id, action, actor, before_json, after_json) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+                &[&i64::from(entry.Time), &entry.Entity.to_string(), &i64::from(entry.EntityID), &entry.Action.to_string(),
+                    &entry.Actor, &before, &after]
+            )?;
+            return u32_at(&row, 0);
+        }).await;
+    }
+
+    async fn query_audit_log(&self, filter: AuditFilter) -> Result<Vec<AuditEntry>, CustomError> {
+        let (from, to) = audit_filter_range(&filter);
+        return self.with_client(move |db| {
+            return db.query("
+                SELECT id, time, entity, entity_id, action, actor, before_json, after_json
+                FROM audit_log
+                WHERE ($1::TEXT IS NULL OR entity = $1)
+                    AND ($2::BIGINT IS NULL OR entity_id = $2)
+                    AND ($3::TEXT IS NULL OR action = $3)
+                    AND time BETWEEN $4 AND $5
+                ORDER BY id DESC
+                LIMIT $6;",
+                &[&filter.Entity.map(|entity| entity.to_string()), &filter.EntityID.map(i64::from),
+                    &filter.Action.map(|action| action.to_string()), &i64::from(from), &i64::from(to),
+                    &i64::from(filter.Limit.unwrap_or(100))])?
+                .iter().map(audit_entry_from_row).collect();
+        }).await;
+    }
+
+    async fn query_print_jobs(&self) -> Result<Vec<PrintJob>, CustomError> {
+        return self.query("SELECT id, title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies FROM printjob WHERE deleted_at IS NULL ORDER BY id;",
+            vec![], print_job_from_row).await;
+    }
+
+    async fn find_print_job(&self, id: DocID) -> Result<PrintJob, CustomError> {
+        let rows = self.query("SELECT id, title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies FROM printjob WHERE id=$1 AND deleted_at IS NULL;",
+            vec![i64::from(id)], print_job_from_row).await?;
+        return check_id_lookup_results(rows);
+    }
+
+    async fn insert_print_job(&self, data: PrintJob) -> Result<DocID, CustomError> {
+        return self.with_client(move |db| {
+            let row = db.query_one(
+                "INSERT INTO printjob (title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+                &[&data.Title, &data.DateCreated.map(i64::from), &i64::from(data.PageCount), &i64::from(data.RasterizationProfileID),
+                    &data.PageSize.to_string(), &data.Duplex, &i64::from(data.Copies)]
+            )?;
+            return u32_at(&row, 0);
+        }).await;
+    }
+
+    async fn remove_print_job(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute("DELETE FROM printjob WHERE id=$1", &[&i64::from(id)])? as usize)
+        }).await;
+    }
+
+    async fn query_rasterization_profiles(&self) -> Result<Vec<RasterizationProfile>, CustomError> {
+        return self.query("SELECT id, title, profile FROM rasterization_profile WHERE deleted_at IS NULL ORDER BY id;",
+            vec![], rasterization_profile_from_row).await;
+    }
+
+    async fn find_rasterization_profile(&self, id: DocID) -> Result<RasterizationProfile, CustomError> {
+        let rows = self.query("SELECT id, title, profile FROM rasterization_profile WHERE id=$1 AND deleted_at IS NULL;",
+            vec![i64::from(id)], rasterization_profile_from_row).await?;
+        return check_id_lookup_results(rows);
+    }
+
+    async fn insert_rasterization_profile(&self, data: RasterizationProfile) -> Result<DocID, CustomError> {
+        return self.with_client(move |db| {
+            let row = db.query_one(
+                "INSERT INTO rasterization_profile (id, title) VALUES ($1, $2) RETURNING id",
+                &[&i64::from(data.id), &data.title]
+            )?;
+            return u32_at(&row, 0);
+        }).await;
+    }
+
+    async fn remove_rasterization_profile(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute("DELETE FROM rasterization_profile WHERE id=$1", &[&i64::from(id)])? as usize)
+        }).await;
+    }
+
+    async fn find_rasterization_profile_print_jobs(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError> {
+        return self.query(format!("SELECT id FROM printjob WHERE rasterization_profile_id=$1 AND {} ORDER BY id", trash_condition(trashed)),
+            vec![i64::from(id)], |row: &Row| u32_at(row, 0)).await;
+    }
+
+    async fn query_workflows(&self) -> Result<Vec<Workflow>, CustomError> {
+        // Workflows which can't be built are left out
+        return self.with_client(|db| {
+            return Ok(find_workflows_with(db, None)?.into_iter().filter_map(Result::ok).collect());
+        }).await;
+    }
+
+    async fn find_workflow(&self, id: DocID) -> Result<Workflow, CustomError> {
+        return self.with_client(move |db| check_id_lookup_results(find_workflows_with(db, Some(id))?)?).await;
+    }
+
+    async fn insert_workflow(&self, data: WorkflowArgs, workflow: Workflow) -> Result<DocID, CustomError> {
+        return self.with_transaction(move |db| {
+            // Insert the Workflow
+            let inserted_id = u32_at(&db.query_one("INSERT INTO workflow (title) VALUES ($1) RETURNING id", &[&data.Title])?, 0)?;
+
+            // Load all workflow steps into the database, keeping the ID of the step at each index
+            let mut index_to_id : Vec<i64> = Vec::new();
+            for step_args in &data.WorkflowSteps {
+                let row = db.query_one(
+                    "INSERT INTO assigned_workflow_step (workflow_id, workflow_step_id, setup_time, time_per_page) VALUES ($1, $2, $3, $4) RETURNING id",
+                    &[&i64::from(inserted_id), &i64::from(step_args.WorkflowStepID), &step_args.SetupTime.map(i64::from), &step_args.TimePerPage.map(i64::from)]
+                )?;
+                let step_id: i64 = row.try_get(0)?;
+                index_to_id.push(step_id);
+
+                for (name, value) in step_args.all_parameters() {
+                    db.execute("INSERT INTO assigned_workflow_step_param (assigned_workflow_step_id, name, value) VALUES ($1, $2, $3)",
+                        &[&step_id, &name, &value.to_string()])?;
+                }
+            }
+
+            for (index, step) in workflow.Steps.iter().enumerate() {
+                for next_step in &step.next {
+                    db.execute(
+                        "INSERT INTO next_workflow_step (assigned_workflow_step_id, next_step_id) VALUES ($1, $2)",
+                        &[&index_to_id.get(index), &index_to_id.get(*next_step)]
+                    )?;
+                }
+
+                for prev_step in &step.prev {
+                    db.execute(
+                        "INSERT INTO prev_workflow_step (assigned_workflow_step_id, prev_step_id) VALUES ($1, $2)",
+                        &[&index_to_id.get(index), &index_to_id.get(*prev_step)]
+                    )?;
+                }
+            }
+
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    /// Deletes the assigned workflow steps associated with this
+    /// workflow id, then deletes the workflow itself.
+    async fn remove_workflow(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_transaction(move |db| {
+            let id = i64::from(id);
+            // Delete the generic parameters and edges of the workflow's steps
+            for table in ["assigned_workflow_step_param", "next_workflow_step", "prev_workflow_step"] {
+                db.execute(&format!("
+                    DELETE FROM {table} WHERE assigned_workflow_step_id IN
+                        (SELECT id FROM assigned_workflow_step WHERE workflow_id=$1)"), &[&id])?;
+            }
+
+            // Delete all assigned workflow steps associated with the workflow
+            db.execute("DELETE FROM assigned_workflow_step WHERE workflow_id=$1", &[&id])?;
+
+            // Delete the workflow
+            return Ok(db.execute("DELETE FROM workflow WHERE id=$1", &[&id])? as usize);
+        }).await;
+    }
+
+    async fn get_workflow_step_ids(&self) -> Result<Vec<DocID>, CustomError> {
+        return self.query("SELECT id FROM workflow_step ORDER BY id", vec![], |row: &Row| u32_at(row, 0)).await;
+    }
+
+    async fn find_custom_workflow_steps(&self) -> Result<Vec<WFSAttributes>, CustomError> {
+        return self.query("SELECT definition FROM workflow_step WHERE definition::json->>'variant' = 'Custom' ORDER BY id",
+            vec![], |row: &Row| json_at(row, 0)).await;
+    }
+
+    async fn find_workflow_step_definition(&self, id: DocID) -> Result<Option<WFSAttributes>, CustomError> {
+        let rows = self.query("SELECT definition FROM workflow_step WHERE id=$1 AND definition IS NOT NULL",
+            vec![i64::from(id)], |row: &Row| json_at(row, 0)).await?;
+        return Ok(rows.into_iter().next());
+    }
+
+    async fn find_workflow_step_params(&self, workflow_step_id: DocID) -> Result<Vec<(DocID, String, String)>, CustomError> {
+        return self.query("
+            SELECT assigned_workflow_step_param.assigned_workflow_step_id, name, value
+            FROM assigned_workflow_step_param
+            JOIN assigned_workflow_step ON assigned_workflow_step.id = assigned_workflow_step_param.assigned_workflow_step_id
+            WHERE assigned_workflow_step.workflow_step_id=$1",
+            vec![i64::from(workflow_step_id)], |row: &Row| { Ok((u32_at(row, 0)?, row.try_get(1)?, row.try_get(2)?)) }).await;
+    }
+
+    async fn find_next_workflow_step_id(&self) -> Result<DocID, CustomError> {
+        // Other transactions can read the table, but not add to it until this one ends
+        let lock = self.unit_of_work.is_some();
+        return self.with_client(move |db| {
+            if lock {
+                db.batch_execute("LOCK TABLE workflow_step IN SHARE ROW EXCLUSIVE MODE")?;
+            }
+            u32_at(&db.query_one("SELECT COALESCE(MAX(id) + 1, 0) FROM workflow_step", &[])?, 0)
+        }).await;
+    }
+
+    async fn count_workflow_step_uses(&self, id: DocID) -> Result<u32, CustomError> {
+        return self.with_client(move |db| {
+            u32_at(&db.query_one("
+                SELECT
+                    (SELECT COUNT(*) FROM assigned_workflow_step WHERE workflow_step_id = $1) +
+                    (SELECT COUNT(*) FROM simulation_report_step_time WHERE workflow_step_id = $1) +
+                    (SELECT COUNT(*) FROM simulation_report_node_time WHERE workflow_step_id = $1)
+            ", &[&i64::from(id)])?, 0)
+        }).await;
+    }
+
+    async fn insert_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError> {
+        return self.with_client(move |db| {
+            db.execute("INSERT INTO workflow_step (id, title, definition) VALUES ($1, $2, $3)
+                ON CONFLICT(id) DO UPDATE SET title=excluded.title, definition=excluded.definition",
+                &[&i64::from(id), &title, &definition])?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn insert_custom_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError> {
+        return self.with_client(move |db| {
+            db.execute("INSERT INTO workflow_step (id, title, definition) VALUES ($1, $2, $3)",
+                &[&i64::from(id), &title, &definition])?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn update_assigned_workflow_step_type(&self, from: DocID, to: DocID) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute("UPDATE assigned_workflow_step SET workflow_step_id=$2 WHERE workflow_step_id=$1",
+                &[&i64::from(from), &i64::from(to)])? as usize)
+        }).await;
+    }
+
+    async fn remove_assigned_workflow_step_param(&self, id: DocID, name: String) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute("DELETE FROM assigned_workflow_step_param WHERE assigned_workflow_step_id=$1 AND name=$2",
+                &[&i64::from(id), &name])? as usize)
+        }).await;
+    }
+
+    async fn remove_workflow_step(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute("DELETE FROM workflow_step WHERE id=$1", &[&i64::from(id)])? as usize)
+        }).await;
+    }
+
+    async fn query_simulation_reports(&self) -> Result<Vec<SimulationReportDetailed>, CustomError> {
+        return self.query("
+            SELECT
+                simulation_report.id,
+                simulation_report.title,
+                simulation_report.creation_time,
+                simulation_report.total_time_taken,
+                printjobID,
+                workflowID,
+                COALESCE(printjob.title, print_job_snapshot::json->>'Title'),
+                COALESCE(workflow.title, workflow_snapshot::json->>'Title'),
+                rasterization_profile.title
+            FROM simulation_report
+            LEFT JOIN workflow
+                ON simulation_report.workflowID=workflow.id
+            LEFT JOIN printjob
+                ON simulation_report.printjobID=printjob.id
+            LEFT JOIN rasterization_profile
+                ON rasterization_profile.id=COALESCE(printjob.rasterization_profile_id,
+                    (print_job_snapshot::json->>'RasterizationProfileID')::BIGINT)
+            WHERE simulation_report.deleted_at IS NULL
+            ORDER BY simulation_report.id;
+        ", vec![], simulation_report_detailed_from_row).await;
+    }
+
+    async fn find_simulation_report(&self, id: DocID) -> Result<SimulationReport, CustomError> {
+        let rows = self.query("SELECT id, creation_time, total_time_taken, printjobID, workflowID, overrides, print_job_snapshot, workflow_snapshot, rerun_of FROM simulation_report WHERE id=$1 AND deleted_at IS NULL;",
+            vec![i64::from(id)], simulation_report_from_row).await?;
+        let mut report = check_id_lookup_results(rows)?;
+        report.StepTimes = self.find_simulation_report_workflow_steps(id).await?;
+        report.NodeTimes = self.find_simulation_report_node_times(id).await?;
+        return Ok(report);
+    }
+
+    async fn find_simulation_report_workflow_steps(&self, id: DocID) -> Result<HashMap<DocID, u32>, CustomError> {
+        let rows = self.query("
+            SELECT workflow_step_id, step_time
+            FROM simulation_report_step_time
+            WHERE simulation_report_id = $1;
+        ", vec![i64::from(id)], |row: &Row| { Ok((u32_at(row, 0)?, u32_at(row, 1)?)) }).await?;
+        return Ok(rows.into_iter().collect());
+    }
+
+    async fn find_simulation_report_node_times(&self, id: DocID) -> Result<Vec<NodeTime>, CustomError> {
+        return self.query("SELECT node_index, workflow_step_id, start_time, end_time FROM simulation_report_node_time WHERE simulation_report_id=$1 ORDER BY node_index;",
+            vec![i64::from(id)], node_time_from_row).await;
+    }
+
+    async fn store_simulation_report(&self, new_report: SimulationReport) -> Result<DocID, CustomError> {
+        let (overrides_json, print_job_json, workflow_json) = simulation_report_json(&new_report)?;
+
+        return self.with_transaction(move |db| {
+            let row = db.query_one(
+                "INSERT INTO simulation_report (title, creation_time, total_time_taken, printjobID, workflowID, overrides, print_job_snapshot, workflow_snapshot, rerun_of) VALUES ('Default', $1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+                &[&i64::from(new_report.CreationTime), &i64::from(new_report.TotalTimeTaken), &i64::from(new_report.PrintJobID), &i64::from(new_report.WorkflowID),
+                    &overrides_json, &print_job_json, &workflow_json, &new_report.RerunOf.map(i64::from)]
+            )?;
+            let inserted_id: i64 = row.try_get(0)?;
+
+            //Store the simulation time data in the db.
+            for (id, time) in &new_report.StepTimes {
+                db.execute(
+                    "INSERT INTO simulation_report_step_time (simulation_report_id, workflow_step_id, step_time) VALUES ($1, $2, $3)",
+                    &[&inserted_id, &i64::from(*id), &i64::from(*time)]
+                )?;
+            }
+            for node_time in &new_report.NodeTimes {
+                db.execute(
+                    "INSERT INTO simulation_report_node_time (simulation_report_id, node_index, workflow_step_id, start_time, end_time) VALUES ($1, $2, $3, $4, $5)",
+                    &[&inserted_id, &(node_time.NodeIndex as i64), &i64::from(node_time.WorkflowStepID), &i64::from(node_time.StartTime), &i64::from(node_time.EndTime)]
+                )?;
+            }
+
+            return Ok(inserted_id as DocID);
+        }).await;
+    }
+
+    async fn remove_simulation_report(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_transaction(move |db| {
+            let id = i64::from(id);
+            db.execute("DELETE FROM simulation_report_step_time WHERE simulation_report_id=$1", &[&id])?;
+            db.execute("DELETE FROM simulation_report_node_time WHERE simulation_report_id=$1", &[&id])?;
+            return Ok(db.execute("DELETE FROM simulation_report WHERE id=$1", &[&id])? as usize);
+        }).await;
+    }
+
+    async fn find_simulation_report_reruns(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError> {
+        return self.query(format!("SELECT id FROM simulation_report WHERE rerun_of=$1 AND {} ORDER BY id", trash_condition(trashed)),
+            vec![i64::from(id)], |row: &Row| u32_at(row, 0)).await;
+    }
+
+    async fn trash_item(&self, item: StoredItem, time: u32) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute(&format!("UPDATE {} SET deleted_at=$1 WHERE id=$2 AND deleted_at IS NULL", item.Type.table()),
+                &[&i64::from(time), &i64::from(item.id)])? as usize)
+        }).await;
+    }
+
+    async fn restore_item(&self, item: StoredItem) -> Result<usize, CustomError> {
+        return self.with_client(move |db| {
+            Ok(db.execute(&format!("UPDATE {} SET deleted_at=NULL WHERE id=$1 AND deleted_at IS NOT NULL", item.Type.table()),
+                &[&i64::from(item.id)])? as usize)
+        }).await;
+    }
+
+    async fn query_trash(&self) -> Result<Vec<TrashedItem>, CustomError> {
+        return self.query(trash_query(), vec![], trashed_item_from_row).await;
+    }
+
+    async fn find_dependencies(&self, item: StoredItem) -> Result<Vec<StoredItem>, CustomError> {
+        let (dependency_type, query) = match item.Type {
+            ItemType::PrintJob => (ItemType::RasterizationProfile, "SELECT rasterization_profile_id FROM printjob WHERE id=$1"),
+            ItemType::SimulationReport => (ItemType::SimulationReport, "SELECT rerun_of FROM simulation_report WHERE id=$1"),
+            ItemType::RasterizationProfile | ItemType::Workflow => return Ok(vec![]),
+        };
+        let ids = self.query(query, vec![i64::from(item.id)], |row: &Row| optional_u32_at(row, 0)).await?;
+        return Ok(ids.into_iter().flatten().map(|id| StoredItem::new(dependency_type, id)).collect());
+    }
+
+    async fn query_workflow_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let stats = time_statistics_query("simulation_report", "workflowID", "total_time_taken");
+        return self.query(format!("
+            SELECT key, workflow.title, count, average, min, max, p50, p90, p95
+            FROM ({stats}) AS stats
+            LEFT JOIN workflow ON workflow.id = stats.key
+            ORDER BY key;"),
+            vec![i64::from(from), i64::from(to)], time_statistics_from_row).await;
+    }
+
+    async fn query_workflow_step_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let stats = time_statistics_query("
+            simulation_report_step_time JOIN simulation_report
+                ON simulation_report.id = simulation_report_step_time.simulation_report_id",
+            "simulation_report_step_time.workflow_step_id", "simulation_report_step_time.step_time");
+        return self.query(format!("
+            SELECT key, NULL::TEXT, count, average, min, max, p50, p90, p95
+            FROM ({stats}) AS stats
+            ORDER BY key;"),
+            vec![i64::from(from), i64::from(to)], time_statistics_from_row).await;
+    }
+
+    async fn query_time_per_page_trend(&self, filter: AnalyticsFilter) -> Result<Vec<TimePerPageTrend>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let interval = filter.Interval.unwrap_or(86400).max(1);
+        // Reports whose print job was deleted use the print job of their snapshot
+        return self.query("
+            SELECT (creation_time / $3) * $3 AS bucket, COUNT(*),
+                AVG(total_time_taken::DOUBLE PRECISION / (page_count * copies))
+            FROM (
+                SELECT simulation_report.creation_time, simulation_report.total_time_taken,
+                    COALESCE(printjob.page_count, (print_job_snapshot::json->>'PageCount')::BIGINT) AS page_count,
+                    COALESCE(printjob.copies, (print_job_snapshot::json->>'Copies')::BIGINT) AS copies
+                FROM simulation_report
+                LEFT JOIN printjob ON printjob.id = simulation_report.printjobID
+                WHERE simulation_report.creation_time BETWEEN $1 AND $2
+                    AND simulation_report.deleted_at IS NULL
+            ) AS report
+            WHERE page_count * copies > 0
+            GROUP BY bucket
+            ORDER BY bucket;",
+            vec![i64::from(from), i64::from(to), i64::from(interval)], time_per_page_trend_from_row).await;
+    }
+
+    async fn query_usage(&self, filter: AnalyticsFilter) -> Result<Usage, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let limit = filter.Limit.unwrap_or(10);
+        let workflows = self.query("
+            SELECT simulation_report.workflowID, workflow.title, COUNT(*) AS uses
+            FROM simulation_report
+            LEFT JOIN workflow ON workflow.id = simulation_report.workflowID
+            WHERE simulation_report.creation_time BETWEEN $1 AND $2
+                AND simulation_report.deleted_at IS NULL
+            GROUP BY simulation_report.workflowID, workflow.title
+            ORDER BY uses DESC, simulation_report.workflowID
+            LIMIT $3;",
+            vec![i64::from(from), i64::from(to), i64::from(limit)], usage_count_from_row).await?;
+        let profiles = self.query("
+            SELECT report.profile_id, rasterization_profile.title, COUNT(*) AS uses
+            FROM (
+                SELECT COALESCE(printjob.rasterization_profile_id,
+                    (print_job_snapshot::json->>'RasterizationProfileID')::BIGINT) AS profile_id
+                FROM simulation_report
+                LEFT JOIN printjob ON printjob.id = simulation_report.printjobID
+                WHERE simulation_report.creation_time BETWEEN $1 AND $2
+                    AND simulation_report.deleted_at IS NULL
+            ) AS report
+            LEFT JOIN rasterization_profile ON rasterization_profile.id = report.profile_id
+            WHERE report.profile_id IS NOT NULL
+            GROUP BY report.profile_id, rasterization_profile.title
+            ORDER BY uses DESC, report.profile_id
+            LIMIT $3;",
+            vec![i64::from(from), i64::from(to), i64::from(limit)], usage_count_from_row).await?;
+        return Ok(Usage { Workflows: workflows, RasterizationProfiles: profiles });
+    }
+
+    async fn export_tables(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<BackupRow>>, CustomError> {
+        // Outside a unit of work, every table is read from the same snapshot
+        let own_transaction = self.unit_of_work.is_none();
+        return self.with_transaction(move |db| {
+            if own_transaction {
+                db.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")?;
+            }
+            let mut exported = BTreeMap::new();
+            for table in tables {
+                let rows = db.query(&format!("SELECT row_to_json(t)::TEXT FROM {table} AS t"), &[])?.iter()
+                    .map(|row| json_at(row, 0))
+                    .collect::<Result<Vec<BackupRow>, CustomError>>()?;
+                exported.insert(table.to_string(), rows);
+            }
+            return Ok(exported);
+        }).await;
+    }
+
+    async fn table_columns(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<String>>, CustomError> {
+        return self.with_client(move |db| {
+            let mut columns = BTreeMap::new();
+            for table in tables {
+                columns.insert(table.to_string(), table_columns(db, table)?);
+            }
+            return Ok(columns);
+        }).await;
+    }
+
+    async fn clear_tables(&self, tables: Vec<&'static str>) -> Result<(), CustomError> {
+        return self.with_transaction(move |db| {
+            for table in tables {
+                db.batch_execute(&format!("DELETE FROM {table}"))?;
+            }
+            return Ok(());
+        }).await;
+    }
+
+    async fn import_table(&self, table: &'static str, rows: Vec<BackupRow>) -> Result<usize, CustomError> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        return self.with_transaction(move |db| {
+            let columns = table_columns(db, table)?;
+            if let Some(column) = rows.iter().flat_map(|row| row.keys()).find(|column| !columns.contains(column)) {
+                return Err(CustomError::InvalidBackup(format!("Table {table} has no column {column}")));
+            }
+            // PostgreSQL converts the values to the types of the columns, such as 0 and 1 to booleans
+            let json = serde_json::Value::from(rows.into_iter().map(serde_json::Value::Object).collect::<Vec<_>>()).to_string();
+            let inserted = db.execute(&format!("
+                INSERT INTO {table}
+                SELECT * FROM json_populate_recordset(NULL::{table}, $1::TEXT::JSON)
+                ON CONFLICT DO NOTHING"), &[&json])?;
+            // The rows kept their IDs, so new IDs must continue after the largest one
+            if columns.iter().any(|column| column == "id") {
+                db.execute(&format!("
+                    SELECT setval(pg_get_serial_sequence('{table}', 'id'), MAX(id))
+                    FROM {table} HAVING MAX(id) IS NOT NULL"), &[])?;
+            }
+            return Ok(inserted as usize);
+        }).await;
+    }
+
+    async fn insert_user(&self, email: String, password_hash: Vec<u8>) -> Result<DocID, CustomError> {
+        return self.with_client(move |db| {
+            let row = db.query_one(
+                "INSERT INTO \"user\" (email, password_hash) VALUES ($1, $2) RETURNING user_id",
+                &[&email, &password_hash]
+            )?;
+            return u32_at(&row, 0);
+        }).await;
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::{
+    backup::BackupRow,
+    database::*,
+    migrations::MigrationStatus,
+    postgresql::PostgresRepository,
+    simulation::NodeTime,
+    sqlite::SqliteRepository,
+    workflow::{Workflow, WorkflowArgs},
+    workflow_steps::WFSAttributes,
+};
+
+/**
+ * Everything the server stores goes through a Repository, so the database
+ * it's stored in can be chosen by configuration. The free functions in
+ * database.rs forward to the repository of the open database.
+ *
+ * Each implementation must pass the conformance tests in tests/repository.rs.
+ * Data is validated before it gets here, so implementations only store and load it.
+ * A method which writes several rows must write all of them or none
+ **/
+#[async_trait]
+pub trait Repository: Send + Sync {
+    /// Applies every pending schema migration
+    async fn migrate(&self) -> Result<(), CustomError>;
+    /// Reports which schema migrations are pending, without applying them
+    async fn migration_status(&self) -> Result<MigrationStatus, CustomError>;
+    /// Inserts the sample data if there are no rasterization profiles yet, returning whether it did
+    async fn seed(&self) -> Result<bool, CustomError>;
+    /// The state of the repository's connection pool
+    fn pool_metrics(&self) -> PoolMetrics;
+
+    // Units of work, see unit_of_work() in database.rs
+    /// Starts a unit of work: a repository which keeps one connection, and its
+    /// changes in one transaction, until commit() or rollback() is called on it
+    async fn begin(&self) -> Result<Arc<dyn Repository>, CustomError>;
+    /// Commits the changes of a unit of work
+    async fn commit(&self) -> Result<(), CustomError>;
+    /// Discards the changes of a unit of work
+    async fn rollback(&self) -> Result<(), CustomError>;
+
+    // Error log
+    async fn query_errors_detailed(&self) -> Result<Vec<ErrorDetailed>, CustomError>;
+    async fn insert_error_detailed(&self, data: ErrorDetailed) -> Result<DocID, CustomError>;
+
+    // Audit log
+    async fn insert_audit_entry(&self, entry: AuditEntry) -> Result<DocID, CustomError>;
+    /// Changes matching the filter, newest first
+    async fn query_audit_log(&self, filter: AuditFilter) -> Result<Vec<AuditEntry>, CustomError>;
+
+    // Print jobs
+    async fn query_print_jobs(&self) -> Result<Vec<PrintJob>, CustomError>;
+    async fn find_print_job(&self, id: DocID) -> Result<PrintJob, CustomError>;
+    async fn insert_print_job(&self, data: PrintJob) -> Result<DocID, CustomError>;
+    async fn remove_print_job(&self, id: DocID) -> Result<usize, CustomError>;
+
+    // Rasterization profiles
+    async fn query_rasterization_profiles(&self) -> Result<Vec<RasterizationProfile>, CustomError>;
+    async fn find_rasterization_profile(&self, id: DocID) -> Result<RasterizationProfile, CustomError>;
+    async fn insert_rasterization_profile(&self, data: RasterizationProfile) -> Result<DocID, CustomError>;
+    async fn remove_rasterization_profile(&self, id: DocID) -> Result<usize, CustomError>;
+    /// IDs of the print jobs using the profile, either those in the trash or those which aren't
+    async fn find_rasterization_profile_print_jobs(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError>;
+
+    // Workflows
+    async fn query_workflows(&self) -> Result<Vec<Workflow>, CustomError>;
+    async fn find_workflow(&self, id: DocID) -> Result<Workflow, CustomError>;
+    /// Stores a workflow. `workflow` holds the edges between the steps in `data`
+    async fn insert_workflow(&self, data: WorkflowArgs, workflow: Workflow) -> Result<DocID, CustomError>;
+    async fn remove_workflow(&self, id: DocID) -> Result<usize, CustomError>;
+
+    // Workflow steps
+    async fn get_workflow_step_ids(&self) -> Result<Vec<DocID>, CustomError>;
+    async fn find_custom_workflow_steps(&self) -> Result<Vec<WFSAttributes>, CustomError>;
+    async fn find_workflow_step_definition(&self, id: DocID) -> Result<Option<WFSAttributes>, CustomError>;
+    async fn find_workflow_step_params(&self, workflow_step_id: DocID) -> Result<Vec<(DocID, String, String)>, CustomError>;
+    /// The ID after the largest workflow step ID. In a unit of work, other units of work
+    /// can't take the same ID until this one ends
+    async fn find_next_workflow_step_id(&self) -> Result<DocID, CustomError>;
+    async fn count_workflow_step_uses(&self, id: DocID) -> Result<u32, CustomError>;
+    async fn insert_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError>;
+    async fn insert_custom_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError>;
+    async fn update_assigned_workflow_step_type(&self, from: DocID, to: DocID) -> Result<usize, CustomError>;
+    async fn remove_assigned_workflow_step_param(&self, id: DocID, name: String) -> Result<usize, CustomError>;
+    async fn remove_workflow_step(&self, id: DocID) -> Result<usize, CustomError>;
+
+    // Simulation reports
+    async fn query_simulation_reports(&self) -> Result<Vec<SimulationReportDetailed>, CustomError>;
+    async fn find_simulation_report(&self, id: DocID) -> Result<SimulationReport, CustomError>;
+    async fn find_simulation_report_workflow_steps(&self, id: DocID) -> Result<HashMap<DocID, u32>, CustomError>;
+    async fn find_simulation_report_node_times(&self, id: DocID) -> Result<Vec<NodeTime>, CustomError>;
+    async fn store_simulation_report(&self, report: SimulationReport) -> Result<DocID, CustomError>;
+    async fn remove_simulation_report(&self, id: DocID) -> Result<usize, CustomError>;
+    /// IDs of the reports which re-ran the report, either those in the trash or those which aren't
+    async fn find_simulation_report_reruns(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError>;
+
+    // Trash. Items in the trash are left out of every query_* and find_* method above
+    /// Moves an item to the trash at the given time, unless it's already there
+    async fn trash_item(&self, item: StoredItem, time: u32) -> Result<usize, CustomError>;
+    /// Takes an item out of the trash, if it's there
+    async fn restore_item(&self, item: StoredItem) -> Result<usize, CustomError>;
+    async fn query_trash(&self) -> Result<Vec<TrashedItem>, CustomError>;
+    /// The items an item refers to, whether they're in the trash or not
+    async fn find_dependencies(&self, item: StoredItem) -> Result<Vec<StoredItem>, CustomError>;
+
+    // Analytics, see database.rs
+    async fn query_workflow_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError>;
+    /// Statistics of each type of workflow step, without their titles
+    async fn query_workflow_step_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError>;
+    async fn query_time_per_page_trend(&self, filter: AnalyticsFilter) -> Result<Vec<TimePerPageTrend>, CustomError>;
+    async fn query_usage(&self, filter: AnalyticsFilter) -> Result<Usage, CustomError>;
+
+    // Backups, see backup.rs
+    /// Every row of the given tables, by table name, read in one transaction
+    async fn export_tables(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<BackupRow>>, CustomError>;
+    /// The names of the columns of each of the given tables, by table name
+    async fn table_columns(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<String>>, CustomError>;
+    /// Deletes every row of the given tables, in order
+    async fn clear_tables(&self, tables: Vec<&'static str>) -> Result<(), CustomError>;
+    /// Inserts exported rows into a table, skipping those whose key is already taken,
+    /// so IDs are kept. Returns how many rows were inserted, or an InvalidBackup error
+    /// if a row has a column the table doesn't
+    async fn import_table(&self, table: &'static str, rows: Vec<BackupRow>) -> Result<usize, CustomError>;
+
+    // Users
+    async fn insert_user(&self, email: String, password_hash: Vec<u8>) -> Result<DocID, CustomError>;
+}
+
+/// Connects to the database at the given location, with the backend it calls for
+pub fn open_repository(location: &DatabaseLocation) -> Result<Arc<dyn Repository>, CustomError> {
+    return Ok(match location {
+        DatabaseLocation::File(_) | DatabaseLocation::InMemory => Arc::new(SqliteRepository::open(location)?),
+        DatabaseLocation::Postgres(url) => Arc::new(PostgresRepository::open(url)?),
+    });
+}
@@ -1,6 +1,7 @@
 use crate::database::*;
 use crate::workflow::*;
 use crate::workflow_steps::*;
+use serde::{Deserialize, Serialize};
 use std::{
 	  sync::RwLock,
 	  collections::HashMap,
@@ -8,40 +9,234 @@ use std::{
 };
 use futures::future::join_all;
 
+/// Number of sheets gathered into one stack for finishing
+const SHEETS_PER_STACK: u32 = 500;
+
+/// The amount of work flowing out of a step, counted in each unit
+/// a step can be timed on. Prepress work is done once for a single copy,
+/// the print job's copies are only counted from printing onward
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct WorkUnits {
+    pages: u32,
+    sheets: u32,
+    stacks: u32,
+    /// Whether the units count every copy rather than a single one
+    copied: bool,
+}
+
+impl WorkUnits {
+    /// Work at the start of a workflow, before any imposition,
+    /// where every side of a sheet holds a single page
+    fn new(print_job: &PrintJob) -> WorkUnits {
+        return WorkUnits::with_n_up(print_job, 1);
+    }
+
+    /// Work for a single copy after imposing `n_up` pages onto each side of a sheet
+    fn with_n_up(print_job: &PrintJob, n_up: u32) -> WorkUnits {
+        let sides = if print_job.Duplex { 2 } else { 1 };
+        let sheets = print_job.PageCount.div_ceil(n_up.max(1).saturating_mul(sides));
+        return WorkUnits {
+            pages: print_job.PageCount,
+            sheets,
+            stacks: sheets.div_ceil(SHEETS_PER_STACK),
+            copied: false,
+        };
+    }
+
+    /// The same work repeated for every copy of the print job,
+    /// or None if there are too many copies to count
+    fn copied(self, print_job: &PrintJob) -> Option<WorkUnits> {
+        if self.copied {
+            return Some(self);
+        }
+        let sheets = self.sheets.checked_mul(print_job.Copies)?;
+        return Some(WorkUnits {
+            pages: self.pages.checked_mul(print_job.Copies)?,
+            sheets,
+            stacks: sheets.div_ceil(SHEETS_PER_STACK),
+            copied: true,
+        });
+    }
+
+    fn get(&self, unit: WorkUnit) -> u32 {
+        return match unit {
+            WorkUnit::Page => self.pages,
+            WorkUnit::Sheet => self.sheets,
+            WorkUnit::Stack => self.stacks,
+        };
+    }
+
+    fn max(self, other: WorkUnits) -> WorkUnits {
+        return WorkUnits {
+            pages: self.pages.max(other.pages),
+            sheets: self.sheets.max(other.sheets),
+            stacks: self.stacks.max(other.stacks),
+            copied: self.copied || other.copied,
+        };
+    }
+}
+
+/// Setup time and time per unit of work a node is simulated with
+#[derive(Clone, Copy, Debug)]
+struct StepTiming {
+    setup_time: u32,
+    time_per_page: u32,
+}
+
 struct SearchData {
     visited: Vec<bool>,
+    /// Timing of each step, after any overrides
+    timings: Vec<StepTiming>,
     step_times_cumulative: Vec<u32>,
+    /// Time taken by each step on its own
+    step_durations: Vec<u32>,
+    /// Work produced by each step, passed on to the steps after it
+    work_units: Vec<WorkUnits>,
     step_times_by_id: HashMap<DocID, u32>,
     cumulative_time: u32,
+    /// Whether some work or time was too large to count
+    overflowed: bool,
 }
 struct Search(RwLock<SearchData>);
 
-pub async fn simulate(print_job_id: DocID, workflow_id: DocID) -> Result<SimulationReport, String> {
+/// When a node of the workflow started and finished during a simulation
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeTime {
+    pub NodeIndex: usize,
+    pub WorkflowStepID: DocID,
+    pub StartTime: u32,
+    pub EndTime: u32,
+}
+
+/// The outcome of simulating a print job going through a workflow
+#[derive(Debug, Clone)]
+pub struct SimulationResult {
+    pub total_time: u32,
+    pub step_times_by_id: HashMap<DocID, u32>,
+    pub node_times: Vec<NodeTime>,
+}
+
+pub async fn simulate(print_job_id: DocID, workflow_id: DocID, overrides: Vec<StepOverride>) -> Result<SimulationReport, CustomError> {
     // Get PrintJob and Workflow
     let print_job = match find_print_job(print_job_id).await {
         Ok(pjid) => pjid,
-        Err(_) => return Err("PrintJob not found".to_string()),
+        Err(_) => return Err(CustomError::OtherError("PrintJob not found".to_string())),
     };
     let workflow: Workflow = match find_workflow(workflow_id).await {
         Ok(wfid) => wfid,
-        Err(_) => return Err("Workflow not found".to_string()),
+        Err(_) => return Err(CustomError::OtherError("Workflow not found".to_string())),
     };
 
-    // Graph Search
-    let search = Search::new(&workflow);
-    traverse_graph(&print_job, &workflow, &search, &workflow.Steps.clone(), 0).await;
+    return simulate_inputs(print_job_id, workflow_id, print_job, workflow, overrides).await;
+}
+
+/// Simulates the given print job and workflow, such as the snapshots stored in a report,
+/// and returns the report. `print_job_id` and `workflow_id` are the IDs they came from
+pub async fn simulate_inputs(print_job_id: DocID, workflow_id: DocID, print_job: PrintJob, workflow: Workflow, overrides: Vec<StepOverride>) -> Result<SimulationReport, CustomError> {
+    let result = simulate_workflow(&print_job, &workflow, &overrides).await?;
 
     // Pass results to SimulationReport constructor
-    return Ok(SimulationReport::new(
+    let mut report = SimulationReport::new(
         print_job_id,
         workflow_id,
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Issue discerning current time.")
             .as_secs() as u32,
-        search.get_cumulative_time(),
-        search.get_step_times_by_id(),
-    ));
+        result.total_time,
+        result.step_times_by_id,
+        overrides,
+        result.node_times,
+    );
+    report.PrintJobSnapshot = Some(print_job);
+    report.WorkflowSnapshot = Some(workflow);
+    return Ok(report);
+}
+
+/// Simulates a print job going through a workflow that has already been
+/// loaded, without storing anything in the database
+pub async fn simulate_workflow(print_job: &PrintJob, workflow: &Workflow, overrides: &[StepOverride])
+-> Result<SimulationResult, CustomError> {
+    let mut workflow = workflow.clone();
+    let timings = apply_overrides(&mut workflow, overrides)?;
+    check_imposition(print_job, &workflow).map_err(CustomError::InvalidWorkflow)?;
+    if workflow.Steps.is_empty() {
+        return Ok(SimulationResult { total_time: 0, step_times_by_id: HashMap::new(), node_times: vec![] });
+    }
+
+    // Graph Search
+    let search = Search::new(&workflow, timings);
+    traverse_graph(print_job, &workflow, &search, &workflow.Steps.clone(), 0).await;
+    if search.overflowed() {
+        return Err(CustomError::InvalidWorkflow(
+            "The print job's work or the workflow's times are too large to simulate".to_string()));
+    }
+
+    return Ok(SimulationResult {
+        total_time: search.get_cumulative_time(),
+        step_times_by_id: search.get_step_times_by_id(),
+        node_times: search.get_node_times(&workflow),
+    });
+}
+
+/// Applies what-if overrides to the workflow and returns the timing each of
+/// its nodes should be simulated with. Nodes start from the timing stored with
+/// the workflow, or the catalog's if there is none. Overrides for every step of
+/// a type are applied first, so overrides for a specific node take precedence over them
+fn apply_overrides(workflow: &mut Workflow, overrides: &[StepOverride]) -> Result<Vec<StepTiming>, CustomError> {
+    let mut timings: Vec<StepTiming> = workflow.Steps.iter()
+        .map(|node| StepTiming {
+            setup_time: node.setup_time(),
+            time_per_page: node.time_per_page(),
+        })
+        .collect();
+
+    let by_step = overrides.iter().filter(|o| o.NodeIndex.is_none());
+    let by_node = overrides.iter().filter(|o| o.NodeIndex.is_some());
+    for step_override in by_step.chain(by_node) {
+        let targets: Vec<usize> = match (step_override.WorkflowStepID, step_override.NodeIndex) {
+            (Some(wfs_id), None) => {
+                get_variant_by_id(wfs_id).map_err(|_| CustomError::InvalidOverride(
+                    format!("WorkflowStep {wfs_id} not found")))?;
+                (0..workflow.Steps.len()).filter(|&i| workflow.Steps[i].data.id() == wfs_id).collect()
+            },
+            (None, Some(index)) if index < workflow.Steps.len() => vec![index],
+            (None, Some(index)) => return Err(CustomError::InvalidOverride(
+                format!("Workflow has no step at index {index}"))),
+            _ => return Err(CustomError::InvalidOverride(
+                "Exactly one of WorkflowStepID or NodeIndex is required".to_string())),
+        };
+
+        for i in targets {
+            if let Some(setup_time) = step_override.SetupTime {
+                timings[i].setup_time = setup_time;
+            }
+            if let Some(time_per_page) = step_override.TimePerPage {
+                timings[i].time_per_page = time_per_page;
+            }
+            for (name, value) in &step_override.Parameters {
+                workflow.Steps[i].set_parameter(name, value).map_err(CustomError::InvalidOverride)?;
+            }
+        }
+    }
+
+    return Ok(timings);
+}
+
+/// Makes sure every Impose step can fit its pages on its sheets
+fn check_imposition(print_job: &PrintJob, workflow: &Workflow) -> Result<(), String> {
+    for node in &workflow.Steps {
+        if let (Some(n_up), Some(sheet_size)) =
+            (node.parameter::<u32>(N_UP_PARAM), node.parameter::<MediaSize>(SHEET_SIZE_PARAM)) {
+            let fits = sheet_size.pages_per_sheet(print_job.PageSize);
+            if n_up > fits {
+                return Err(format!("Cannot impose {n_up} {} pages on a {sheet_size} sheet, at most {fits} fit",
+                    print_job.PageSize));
+            }
+        }
+    }
+    return Ok(());
 }
 
 /// Assumes graph is acyclic and connected
@@ -62,10 +257,32 @@ async fn traverse_graph(
     // Recursively visit all previous nodes first
     traverse_list(&steps[step].prev, print_job, workflow, search, steps).await;
 
+    // Work arriving from the previous steps, counting every copy once printing starts
+    let mut work_in = steps[step]
+        .prev
+        .iter()
+        .map(|&i| search.get_work_units(i))
+        .reduce(WorkUnits::max)
+        .unwrap_or_else(|| WorkUnits::new(print_job));
+    if steps[step].data.prints_copies() {
+        work_in = search.checked(work_in.copied(print_job)).unwrap_or(work_in);
+    }
+
     // Simulate the current step
-    let result = simulate_step(print_job, &steps[step]).await;
+    let result = search.checked(simulate_step(&steps[step], search.get_timing(step), &work_in).await).unwrap_or(u32::MAX);
+    search.set_work_units(step, match steps[step].data {
+        WFSVariant::Impose => {
+            let imposed = WorkUnits::with_n_up(print_job, steps[step].parameter(N_UP_PARAM).unwrap_or(1));
+            match work_in.copied {
+                true => search.checked(imposed.copied(print_job)).unwrap_or(imposed),
+                false => imposed,
+            }
+        },
+        _ => work_in,
+    });
 
     // Update times
+    search.set_step_duration(step, result);
     search.update_step_time_by_id(&steps[step].data.id(), result);
     match Iterator::max(
         steps[step]
@@ -73,7 +290,7 @@ async fn traverse_graph(
             .iter()
             .map(|&i| search.get_step_time_cumulative(i)),
     ) {
-        Some(data) => search.update_step_time_cumulative(step, result + data),
+        Some(data) => search.update_step_time_cumulative(step, search.checked(result.checked_add(data)).unwrap_or(u32::MAX)),
         None => search.update_step_time_cumulative(step, result),
     };
 
@@ -97,28 +314,73 @@ async fn traverse_list(
     .await;
 }
 
-async fn simulate_step(print_job: &PrintJob, wfs: &WorkflowNode) -> u32 {
-    return match wfs.data {
-        WFSVariant::Rasterization { num_cores } => {
-            return ((print_job.PageCount as f32) / (num_cores as f32)).ceil() as u32
-                * wfs.data.time_per_page()
-                + wfs.data.setup_time();
-        }
-        _ => print_job.PageCount * wfs.data.time_per_page() + wfs.data.setup_time(),
+/// Time taken by a step, or None if it's too long to count.
+/// Every RIP core and every cutting blade works as a worker of its own
+async fn simulate_step(wfs: &WorkflowNode, timing: StepTiming, work_in: &WorkUnits) -> Option<u32> {
+    let workers = u64::from(wfs.parameter::<u32>(NUM_CORES_PARAM).unwrap_or(1))
+        * u64::from(wfs.parameter::<u32>(BLADE_COUNT_PARAM).unwrap_or(1))
+        * u64::from(wfs.machine_count);
+    let film_factor = film_time_factor(wfs.parameter::<String>(FILM_TYPE_PARAM).as_deref());
+    return parallel_step_time(
+        wfs.data.parallelism(),
+        workers,
+        work_in.get(wfs.data.work_unit()),
+        timing.setup_time,
+        timing.time_per_page.checked_mul(film_factor)?,
+    );
+}
+
+/// How many times longer a film takes to apply per sheet than gloss film
+fn film_time_factor(film_type: Option<&str>) -> u32 {
+    return match film_type {
+        Some("SoftTouch") => 2,
+        _ => 1,
+    };
+}
+
+/// Time taken by a step for the given units of work when its work is
+/// divided between `workers` according to the step's parallelism,
+/// or None if it's too long to count. The time is worked out in u64,
+/// which holds any number of u32 units times a u32 time per unit
+fn parallel_step_time(parallelism: Parallelism, workers: u64, units: u32, setup_time: u32, time_per_page: u32) -> Option<u32> {
+    let units = u64::from(units);
+    let pages_per_worker = units.div_ceil(workers.max(1));
+    let time = match parallelism {
+        Parallelism::None => units * u64::from(time_per_page) + u64::from(setup_time),
+        Parallelism::SplitPages => pages_per_worker * u64::from(time_per_page) + u64::from(setup_time),
+        // Each lane is set up at the same time as the others
+        Parallelism::Lanes => pages_per_worker * u64::from(time_per_page) + u64::from(setup_time),
     };
+    return u32::try_from(time).ok();
 }
 
 impl Search {
-    fn new(workflow: &Workflow) -> Search {
+    fn new(workflow: &Workflow, timings: Vec<StepTiming>) -> Search {
 		let length = workflow.Steps.len();	
         return Search(RwLock::new(SearchData {
             visited: vec![false; length],
+            timings,
             step_times_cumulative: vec![0; length],
+            step_durations: vec![0; length],
+            work_units: vec![WorkUnits::default(); length],
             step_times_by_id: HashMap::new(),
             cumulative_time: 0,
+            overflowed: false,
         }));
     }
 
+    // Returns the value, or records that the simulation overflowed if there is none
+    fn checked<T>(&self, value: Option<T>) -> Option<T> {
+        if value.is_none() {
+            self.0.write().unwrap().overflowed = true;
+        }
+        return value;
+    }
+
+    fn overflowed(&self) -> bool {
+        return self.0.read().unwrap().overflowed;
+    }
+
     // Returns false to indicate that an index can't be visited because
     // it already has been, otherwise sets visited[index] to true
     // and returns true to indicate that it's OK to evaluate that step
@@ -145,14 +407,39 @@ impl Search {
         return self.0.read().unwrap().step_times_cumulative[step];
     }
 
+    fn set_step_duration(&self, step: usize, time: u32) {
+        self.0.write().unwrap().step_durations[step] = time;
+    }
+
+    fn get_node_times(&self, workflow: &Workflow) -> Vec<NodeTime> {
+        let data = self.0.read().unwrap();
+        return workflow.Steps.iter().enumerate()
+            .map(|(i, node)| NodeTime {
+                NodeIndex: i,
+                WorkflowStepID: node.data.id(),
+                StartTime: data.step_times_cumulative[i] - data.step_durations[i],
+                EndTime: data.step_times_cumulative[i],
+            })
+            .collect();
+    }
+
+    fn get_timing(&self, step: usize) -> StepTiming {
+        return self.0.read().unwrap().timings[step];
+    }
+
+    fn set_work_units(&self, step: usize, units: WorkUnits) {
+        self.0.write().unwrap().work_units[step] = units;
+    }
+
+    fn get_work_units(&self, step: usize) -> WorkUnits {
+        return self.0.read().unwrap().work_units[step];
+    }
+
     fn update_step_time_by_id(&self, id: &DocID, time: u32) {
-        self.0
-            .write()
-            .unwrap()
-            .step_times_by_id
-            .entry(*id)
-            .and_modify(|val| *val += time)
-            .or_insert(time);
+        let mut data = self.0.write().unwrap();
+        let total = data.step_times_by_id.get(id).map_or(Some(time), |val| val.checked_add(time));
+        data.overflowed |= total.is_none();
+        data.step_times_by_id.insert(*id, total.unwrap_or(u32::MAX));
     }
 
     fn get_step_times_by_id(&self) -> HashMap<DocID, u32> {
@@ -0,0 +1,1114 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex},
+    time::Duration,
+};
+use async_trait::async_trait;
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use rusqlite::{params, params_from_iter, Connection, Error, OpenFlags, ParamsFromIter, Row, Result, Params};
+use rusqlite::types::{Type, Value, ValueRef};
+use crate::{
+    backup::BackupRow,
+    database::*,
+    migrations::*,
+    repository::Repository,
+    simulation::NodeTime,
+    workflow::*,
+    workflow_steps::*,
+};
+
+/// How long a connection waits for another connection's write lock before giving up
+const DB_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Databases created before migrations were tracked already have every migration up to this version
+const BASELINE_VERSION: u32 = 1;
+static IN_MEMORY_DATABASES: AtomicU64 = AtomicU64::new(0);
+
+
+/// Opens the connections of the pool. Each connection to a file uses WAL mode,
+/// so readers don't block each other or the writer, and every connection checks foreign keys
+pub struct SqliteConnectionManager {
+    path: String,
+    flags: OpenFlags,
+}
+
+impl SqliteConnectionManager {
+    pub fn new(path: &str) -> SqliteConnectionManager {
+        return SqliteConnectionManager { path: path.to_string(), flags: OpenFlags::default() };
+    }
+
+    /// Connects to a new in-memory database. Its connections share one cache, so
+    /// they see the same data, and the database is freed along with its last connection
+    pub fn in_memory() -> SqliteConnectionManager {
+        let n = IN_MEMORY_DATABASES.fetch_add(1, Ordering::Relaxed);
+        return SqliteConnectionManager {
+            path: format!("file:printos-memory-{}-{n}?mode=memory&cache=shared", std::process::id()),
+            flags: OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+        };
+    }
+}
+
+impl ManageConnection for SqliteConnectionManager {
+    type Connection = Connection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Connection> {
+        let db = Connection::open_with_flags(&self.path, self.flags)?;
+        db.busy_timeout(DB_BUSY_TIMEOUT)?;
+        // In-memory databases keep their journal in memory, and report that instead
+        db.query_row("PRAGMA journal_mode = WAL;", [], |row| row.get::<_, String>(0))?;
+        db.execute_batch("PRAGMA foreign_keys = ON;")?;
+        return Ok(db);
+    }
+
+    fn is_valid(&self, db: &mut Connection) -> Result<()> {
+        return db.execute_batch("");
+    }
+
+    fn has_broken(&self, _db: &mut Connection) -> bool {
+        return false;
+    }
+}
+
+
+/// The connection a unit of work holds until it ends. Its transaction is
+/// rolled back if the unit of work is dropped without being committed
+struct UnitOfWorkConnection(PooledConnection<SqliteConnectionManager>);
+
+impl Drop for UnitOfWorkConnection {
+    fn drop(&mut self) {
+        if !self.0.is_autocommit() {
+            let _ = self.0.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+
+/// Stores everything in a SQLite database file, or in memory
+pub struct SqliteRepository {
+    pool: Pool<SqliteConnectionManager>,
+    stats: Arc<PoolStats>,
+    /// The connection of the unit of work this repository belongs to, if any
+    unit_of_work: Option<Arc<Mutex<UnitOfWorkConnection>>>,
+}
+
+impl SqliteRepository {
+    /// Creates a pool of connections to the database at the given location
+    pub fn open(location: &DatabaseLocation) -> Result<SqliteRepository, CustomError> {
+        let builder = Pool::builder().connection_timeout(DB_CONNECTION_TIMEOUT);
+        let pool = match location {
+            // Connections to a shared in-memory database lock whole tables and don't wait
+            // for each other, so they're used one at a time. The connection is never
+            // closed by the pool, since that would free the database
+            DatabaseLocation::InMemory => builder
+                .max_size(1)
+                .max_lifetime(None)
+                .idle_timeout(None)
+                .build(SqliteConnectionManager::in_memory())?,
+            DatabaseLocation::File(path) => builder
+                .max_size(MAX_DB_CONNECTIONS)
+                .build(SqliteConnectionManager::new(path))?,
+            DatabaseLocation::Postgres(_) => return Err(CustomError::OtherError(
+                format!("{location} is not a SQLite database"))),
+        };
+        let repository = SqliteRepository { pool, stats: Arc::new(PoolStats::default()), unit_of_work: None };
+        // An in-memory database starts out empty, so it's given the schema right away
+        if *location == DatabaseLocation::InMemory {
+            apply_migrations(&mut *repository.connection()?)?;
+        }
+        return Ok(repository);
+    }
+
+    /// Borrows a connection from the pool, waiting for one to become free if needed.
+    /// This blocks, so async code should use with_connection() instead
+    pub fn connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, CustomError> {
+        return self.stats.checkout(&self.pool);
+    }
+
+    /// Runs a function with a connection borrowed from the pool, or the unit of work's
+    /// connection, on a thread where blocking is allowed, so database work doesn't
+    /// hold up the async runtime
+    ///
+    /// ### Arguments
+    /// * `f` - Function that uses the connection
+    ///
+    /// ### Returns
+    /// The result of the function, or an error if no connection could be borrowed
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, CustomError>
+        where T: Send + 'static, F: FnOnce(&mut Connection) -> Result<T, CustomError> + Send + 'static {
+
+        let pool = self.pool.clone();
+        let stats = self.stats.clone();
+        let unit_of_work = self.unit_of_work.clone();
+        return tokio::task::spawn_blocking(move || {
+            return match unit_of_work {
+                Some(connection) => f(&mut connection.lock().unwrap().0),
+                None => f(&mut *stats.checkout(&pool)?),
+            };
+        }).await?;
+    }
+
+    /// Runs a write of several rows like with_connection(), in a savepoint which is rolled
+    /// back if the write fails. Outside a unit of work, the savepoint is a transaction
+    async fn with_transaction<T, F>(&self, f: F) -> Result<T, CustomError>
+        where T: Send + 'static, F: FnOnce(&mut Connection) -> Result<T, CustomError> + Send + 'static {
+
+        return self.with_connection(move |db| {
+            db.execute_batch("SAVEPOINT write")?;
+            let result = f(db);
+            db.execute_batch(match result {
+                Ok(_) => "RELEASE write",
+                Err(_) => "ROLLBACK TO write; RELEASE write",
+            })?;
+            return result;
+        }).await;
+    }
+
+    /// Ends the transaction of this repository's unit of work
+    async fn end_unit_of_work(&self, sql: &'static str) -> Result<(), CustomError> {
+        if self.unit_of_work.is_none() {
+            return Err(CustomError::OtherError("No unit of work is in progress".to_string()));
+        }
+        return self.with_connection(move |db| Ok(db.execute_batch(sql)?)).await;
+    }
+
+    /// Returns the results of a database query or an error, using a connection from the pool
+    ///
+    /// ### Arguments
+    /// * `query` - SQL query to perform
+    /// * `params` - Array of parameters used in the query (see query_map())
+    /// * `f` - Function that turns a row from the database into the desired struct
+    ///
+    /// ### Returns
+    /// A vector of structs or an error
+    async fn query<T,P,F>(&self, query: impl Into<String>, params: P, f: F) -> Result<Vec<T>, CustomError>
+        where T: Send + 'static, P: Params + Send + 'static, F: FnMut(&Row<'_>) -> Result<T> + Send + 'static {
+
+        let query = query.into();
+        return self.with_connection(move |db| Ok(query_with(db, &query, params, f)?)).await;
+    }
+}
+
+
+/// Parameters of a query which has none. Unlike `[]`, these can be sent to another thread
+fn no_params() -> ParamsFromIter<std::iter::Empty<DocID>> {
+    return params_from_iter(std::iter::empty());
+}
+
+
+/// Returns the results of a database query on the given connection, for use
+/// inside with_connection() when several queries should share a connection
+fn query_with<T,P,F>(db: &Connection, query: &str, params: P, f: F) -> Result<Vec<T>>
+    where P: Params, F: FnMut(&Row<'_>) -> Result<T> {
+
+    let mut stmt = db.prepare(query)?;
+    let rows = stmt.query_map(params, f)?;
+
+    let mut results = Vec::new();
+    for job_result in rows {
+        let job = job_result?;
+        results.push(job);
+    }
+
+    return Ok(results);
+}
+
+
+// Create struct from database row
+//
+// These functions transform a row from a SQL query and creates the
+// appropriate struct from the result. These functions assume that
+// the columns in the row are in a specific order
+
+fn error_detailed_from_row(row: &Row) -> Result<ErrorDetailed> {
+    return Ok(ErrorDetailed {
+        id: row.get(0)?,
+        date: row.get(1)?,
+        status: row.get(2)?,
+        domain: row.get(3)?,
+        request: row.get(4)?,
+        method: row.get(5)?,
+        response: row.get(6)?,
+    });
+}
+
+fn print_job_from_row(row: &Row) -> Result<PrintJob> {
+    return Ok(PrintJob {
+        id: row.get(0)?,
+        Title: row.get(1)?,
+        DateCreated: row.get(2)?,
+        PageCount: row.get(3)?,
+        RasterizationProfileID: row.get(4)?,
+        PageSize: row.get(5)?,
+        Duplex: row.get(6)?,
+        Copies: row.get(7)?,
+    });
+}
+
+fn workflow_from_row(row: &Row) -> Result<Workflow> {
+    return Ok(Workflow {
+        id: row.get(0)?,
+        Title: row.get(1)?,
+        Steps: vec![],
+    });
+}
+
+fn simulation_report_detailed_from_row(row: &Row) -> Result<SimulationReportDetailed> {
+    return Ok(SimulationReportDetailed {
+        id: row.get(0)?, 
+        CreationTime: row.get(2)?,
+        TotalTimeTaken: row.get(3)?,
+        PrintJobID: row.get(4)?,
+        WorkflowID: row.get(5)?,
+        StepTimes: HashMap::from([]),
+        PrintJobTitle: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+        WorkflowTitle: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+        RasterizationProfile: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+    });
+}
+
+fn rasterization_profile_from_row(row: &Row) -> Result<RasterizationProfile> {
+    return Ok(RasterizationProfile {
+        id: row.get(0)?, 
+        title: row.get(1)?, 
+        profile: row.get(2)?,
+    });
+}
+
+fn simulation_report_from_row(row: &Row) -> Result<SimulationReport> {
+    return Ok(SimulationReport {
+        id: row.get(0)?,
+        CreationTime: row.get(1)?,
+        TotalTimeTaken: row.get(2)?, 
+        PrintJobID: row.get(3)?,
+        WorkflowID: row.get(4)?,
+        StepTimes: HashMap::new(),
+        Overrides: json_from_row(row, 5)?,
+        NodeTimes: vec![],
+        PrintJobSnapshot: optional_json_from_row(row, 6)?,
+        WorkflowSnapshot: optional_json_from_row(row, 7)?,
+        RerunOf: row.get(8)?,
+    });
+}
+
+fn audit_entry_from_row(row: &Row) -> Result<AuditEntry> {
+    return Ok(AuditEntry {
+        id: row.get(0)?,
+        Time: row.get(1)?,
+        Entity: row.get(2)?,
+        EntityID: row.get(3)?,
+        Action: row.get(4)?,
+        Actor: row.get(5)?,
+        Before: optional_json_from_row(row, 6)?,
+        After: optional_json_from_row(row, 7)?,
+    });
+}
+
+fn trashed_item_from_row(row: &Row) -> Result<TrashedItem> {
+    return Ok(TrashedItem {
+        Type: row.get(0)?,
+        id: row.get(1)?,
+        Title: row.get(2)?,
+        DeletedAt: row.get(3)?,
+    });
+}
+
+/// Reads a column holding a JSON document
+fn json_from_row<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> Result<T> {
+    let text: String = row.get(index)?;
+    return serde_json::from_str(&text).map_err(|e|
+        Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)));
+}
+
+/// Reads a nullable column holding a JSON document
+fn optional_json_from_row<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> Result<Option<T>> {
+    return match row.get::<_, Option<String>>(index)? {
+        Some(_) => json_from_row(row, index).map(Some),
+        None => Ok(None),
+    };
+}
+
+/// Reads a row of any table, naming each value after its column
+fn backup_row_from_row(row: &Row, columns: &[String]) -> Result<BackupRow> {
+    let mut values = BackupRow::new();
+    for (index, column) in columns.iter().enumerate() {
+        let value = match row.get_ref(index)? {
+            ValueRef::Null => serde_json::Value::Null,
+            ValueRef::Integer(n) => serde_json::Value::from(n),
+            ValueRef::Real(n) => serde_json::Value::from(n),
+            ValueRef::Text(text) => serde_json::Value::from(String::from_utf8_lossy(text).into_owned()),
+            ValueRef::Blob(_) => return Err(Error::InvalidColumnType(index, column.clone(), Type::Blob)),
+        };
+        values.insert(column.clone(), value);
+    }
+    return Ok(values);
+}
+
+fn node_time_from_row(row: &Row) -> Result<NodeTime> {
+    return Ok(NodeTime {
+        NodeIndex: row.get::<_, u32>(0)? as usize,
+        WorkflowStepID: row.get(1)?,
+        StartTime: row.get(2)?,
+        EndTime: row.get(3)?,
+    });
+}
+
+fn time_statistics_from_row(row: &Row) -> Result<TimeStatistics> {
+    return Ok(TimeStatistics {
+        id: row.get(0)?,
+        Title: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        Count: row.get(2)?,
+        Average: row.get(3)?,
+        Min: row.get(4)?,
+        Max: row.get(5)?,
+        P50: row.get(6)?,
+        P90: row.get(7)?,
+        P95: row.get(8)?,
+    });
+}
+
+fn time_per_page_trend_from_row(row: &Row) -> Result<TimePerPageTrend> {
+    return Ok(TimePerPageTrend {
+        Time: row.get(0)?,
+        Count: row.get(1)?,
+        AverageTimePerPage: row.get(2)?,
+    });
+}
+
+fn usage_count_from_row(row: &Row) -> Result<UsageCount> {
+    return Ok(UsageCount {
+        id: row.get(0)?,
+        Title: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        Count: row.get(2)?,
+    });
+}
+
+fn assigned_workflow_step_from_row(row: &Row) -> Result<AssignedWorkflowStep> {
+    return Ok(AssignedWorkflowStep {
+        id: row.get(0)?,
+        WorkflowStepID: row.get(2)?,
+        SetupTime: row.get(3)?,
+        TimePerPage: row.get(4)?,
+        Prev: vec![],
+        Next: vec![],
+    });
+}
+
+/// Builds a query for the count, average, min, max and percentiles of `value`
+/// grouped by `key`, over the rows of `source` created within ?1 and ?2
+fn time_statistics_query(source: &str, key: &str, value: &str) -> String {
+    return format!("
+        WITH ranked AS (
+            SELECT {key} AS key, {value} AS value,
+                ROW_NUMBER() OVER (PARTITION BY {key} ORDER BY {value}) AS rank,
+                COUNT(*) OVER (PARTITION BY {key}) AS n
+            FROM {source}
+            WHERE simulation_report.creation_time BETWEEN ?1 AND ?2
+                AND simulation_report.deleted_at IS NULL
+        )
+        SELECT key, COUNT(*) AS count, AVG(value) AS average, MIN(value) AS min, MAX(value) AS max,
+            MIN(CASE WHEN rank >= 0.50 * n THEN value END) AS p50,
+            MIN(CASE WHEN rank >= 0.90 * n THEN value END) AS p90,
+            MIN(CASE WHEN rank >= 0.95 * n THEN value END) AS p95
+        FROM ranked
+        GROUP BY key");
+}
+
+
+// Schema migrations
+
+/// Creates the schema_version table if it doesn't exist. A database which
+/// already has tables, but no schema_version, is recorded at the baseline version
+fn create_schema_version_table(db: &Connection) -> Result<()> {
+    if has_table(db, "schema_version")? {
+        return Ok(());
+    }
+    let is_legacy = has_table(db, "workflow")?;
+    db.execute_batch("
+        CREATE TABLE schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        );
+    ")?;
+    if is_legacy {
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= BASELINE_VERSION) {
+            db.execute("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, strftime('%s', 'now'))",
+                params![migration.version, migration.name])?;
+        }
+    }
+    return Ok(());
+}
+
+/// Applies every migration the database doesn't have yet, each in its own transaction
+fn apply_migrations(db: &mut Connection) -> Result<(), CustomError> {
+    create_schema_version_table(db)?;
+    let current = schema_version(db)?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        println!("Applying migration {} {}", migration.version, migration.name);
+        let tx = db.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        tx.execute("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, strftime('%s', 'now'))",
+            params![migration.version, migration.name])?;
+        tx.commit()?;
+    }
+    return Ok(());
+}
+
+/// Returns the version of the database, or an error if it is newer than this server
+fn schema_version(db: &Connection) -> Result<u32, CustomError> {
+    let version: u32 = db.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+    return check_schema_version(version);
+}
+
+fn has_table(db: &Connection, name: &str) -> Result<bool> {
+    return db.query_row("SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name=(?)",
+        [name], |row| row.get(0));
+}
+
+
+fn table_columns(db: &Connection, table: &str) -> Result<Vec<String>> {
+    return query_with(db, "SELECT name FROM pragma_table_info(?)", [table], |row: &Row| row.get(0));
+}
+
+/// Converts a value of an exported row to the value SQLite stores.
+/// Booleans are stored as integers
+fn sql_value(value: serde_json::Value) -> Value {
+    return match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Value::Integer(n),
+            None => Value::Real(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(text) => Value::Text(text),
+        other => Value::Text(other.to_string()),
+    };
+}
+
+
+/// Returns the workflows which aren't in the trash, or only the one with the given ID,
+/// using the given connection. Every workflow is loaded with the same five queries
+fn find_workflows_with(db: &Connection, id: Option<DocID>) -> Result<Vec<Result<Workflow, CustomError>>, CustomError> {
+    let queries = WorkflowQueries::new(match id {
+        Some(_) => "workflow.id = ?1",
+        None => "TRUE",
+    });
+    let rows = WorkflowRows {
+        workflows: query_with(db, &queries.workflows, params_from_iter(id), workflow_from_row)?,
+        steps: query_with(db, &queries.steps, params_from_iter(id),
+            |row: &Row| { Ok((row.get(1)?, assigned_workflow_step_from_row(row)?)) })?,
+        params: query_with(db, &queries.params, params_from_iter(id),
+            |row: &Row| { Ok((row.get(0)?, row.get(1)?, row.get(2)?)) })?,
+        next: query_with(db, &queries.next, params_from_iter(id), |row: &Row| { Ok((row.get(0)?, row.get(1)?)) })?,
+        prev: query_with(db, &queries.prev, params_from_iter(id), |row: &Row| { Ok((row.get(0)?, row.get(1)?)) })?,
+    };
+    return Ok(assemble_workflows(rows));
+}
+
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn migrate(&self) -> Result<(), CustomError> {
+        return self.with_connection(apply_migrations).await;
+    }
+
+    async fn migration_status(&self) -> Result<MigrationStatus, CustomError> {
+        let current = self.with_connection(|db| {
+            return Ok(match has_table(db, "schema_version")? {
+                true => schema_version(db)?,
+                false if has_table(db, "workflow")? => BASELINE_VERSION,
+                false => 0,
+            });
+        }).await?;
+        return Ok(MigrationStatus::new(current));
+    }
+
+    async fn seed(&self) -> Result<bool, CustomError> {
+        return self.with_transaction(|db| {
+            let profiles: u32 = db.query_row("SELECT COUNT(*) FROM rasterization_profile", [], |row| row.get(0))?;
+            if profiles > 0 {
+                return Ok(false);
+            }
+            db.execute_batch(SEED)?;
+            return Ok(true);
+        }).await;
+    }
+
+    fn pool_metrics(&self) -> PoolMetrics {
+        return self.stats.metrics(&self.pool);
+    }
+
+    async fn begin(&self) -> Result<Arc<dyn Repository>, CustomError> {
+        if self.unit_of_work.is_some() {
+            return Err(CustomError::OtherError("A unit of work is already in progress".to_string()));
+        }
+        let pool = self.pool.clone();
+        let stats = self.stats.clone();
+        let connection = tokio::task::spawn_blocking(move || {
+            let db = stats.checkout(&pool)?;
+            // Take the write lock up front, so the transaction can't fail later on upgrading to it
+            db.execute_batch("BEGIN IMMEDIATE")?;
+            return Ok::<_, CustomError>(UnitOfWorkConnection(db));
+        }).await??;
+        return Ok(Arc::new(SqliteRepository {
+            pool: self.pool.clone(),
+            stats: self.stats.clone(),
+            unit_of_work: Some(Arc::new(Mutex::new(connection))),
+        }));
+    }
+
+    async fn commit(&self) -> Result<(), CustomError> {
+        return self.end_unit_of_work("COMMIT").await;
+    }
+
+    async fn rollback(&self) -> Result<(), CustomError> {
+        return self.end_unit_of_work("ROLLBACK").await;
+    }
+
+    async fn query_errors_detailed(&self) -> Result<Vec<ErrorDetailed>, CustomError> {
+        return self.query("SELECT id, date_occured, status, domain, request, method, response FROM errors_detailed",
+            no_params(), error_detailed_from_row).await;
+    }
+
+    async fn insert_error_detailed(&self, data: ErrorDetailed) -> Result<DocID, CustomError> {
+        return self.with_connection(move |db| {
+            db.execute(
+                "INSERT INTO errors_detailed (id, date_occured, status, domain, request, method, response) VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6)",
+                params![data.date, data.status, data.domain, data.request, data.method, data.response]
+            )?;
+
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    async fn insert_audit_entry(&self, entry: AuditEntry) -> Result<DocID, CustomError> {
+        let to_json = |value: &Option<serde_json::Value>| value.as_ref().map(|value| value.to_string());
+        let (before, after) = (to_json(&entry.Before), to_json(&entry.After));
+        return self.with_connection(move |db| {
+            db.execute(
+                "INSERT INTO audit_log (id, time, entity, entity_id, action, actor, before_json, after_json) VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![entry.Time, entry.Entity.to_string(), entry.EntityID, entry.Action.to_string(), entry.Actor, before, after]
+            )?;
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    async fn query_audit_log(&self, filter: AuditFilter) -> Result<Vec<AuditEntry>, CustomError> {
+        let (from, to) = audit_filter_range(&filter);
+        return self.with_connection(move |db| {
+            return Ok(query_with(db, "
+                SELECT id, time, entity, entity_id, action, actor, before_json, after_json
+                FROM audit_log
+                WHERE (?1 IS NULL OR entity = ?1)
+                    AND (?2 IS NULL OR entity_id = ?2)
+                    AND (?3 IS NULL OR action = ?3)
+                    AND time BETWEEN ?4 AND ?5
+                ORDER BY id DESC
+                LIMIT ?6;",
+                params![filter.Entity.map(|entity| entity.to_string()), filter.EntityID,
+                    filter.Action.map(|action| action.to_string()), from, to, filter.Limit.unwrap_or(100)],
+                audit_entry_from_row)?);
+        }).await;
+    }
+
+    async fn query_print_jobs(&self) -> Result<Vec<PrintJob>, CustomError> {
+        return self.query("SELECT id, title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies FROM printjob WHERE deleted_at IS NULL;",
+            no_params(), print_job_from_row).await;
+    }
+
+    async fn find_print_job(&self, id: DocID) -> Result<PrintJob, CustomError> {
+        let rows = self.query("SELECT id, title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies FROM printjob WHERE id=(?) AND deleted_at IS NULL;",
+            [id], print_job_from_row).await?;
+        return check_id_lookup_results(rows);
+    }
+
+    async fn insert_print_job(&self, data: PrintJob) -> Result<DocID, CustomError> {
+        return self.with_connection(move |db| {
+            db.execute(
+                "INSERT INTO printjob (id, title, creation_time, page_count, rasterization_profile_id, page_size, duplex, copies) VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![data.Title, data.DateCreated, data.PageCount, data.RasterizationProfileID, data.PageSize, data.Duplex, data.Copies]
+            )?;
+
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    async fn remove_print_job(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            let mut stmt = db.prepare("DELETE FROM printjob WHERE id=(?)")?;
+            let res = stmt.execute([id])?;
+            return Ok(res);
+        }).await;
+    }
+
+    async fn query_rasterization_profiles(&self) -> Result<Vec<RasterizationProfile>, CustomError> {
+        return self.query("SELECT id, title, profile FROM rasterization_profile WHERE deleted_at IS NULL;",
+            no_params(), rasterization_profile_from_row).await;
+    }
+
+    async fn find_rasterization_profile(&self, id: DocID) -> Result<RasterizationProfile, CustomError> {
+        let rows = self.query("SELECT id, title, profile FROM rasterization_profile WHERE id=(?) AND deleted_at IS NULL;",
+            [id], rasterization_profile_from_row).await?;
+        return check_id_lookup_results(rows);
+    }
+
+    async fn insert_rasterization_profile(&self, data: RasterizationProfile) -> Result<DocID, CustomError> {
+        return self.with_connection(move |db| {
+            db.execute(
+                "INSERT INTO rasterization_profile (id, title) VALUES (?1, ?2);",
+                params![data.id, data.title]
+            )?;
+
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    async fn remove_rasterization_profile(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            let mut stmt = db.prepare("DELETE FROM rasterization_profile WHERE id=(?)")?;
+            let res = stmt.execute([id])?;
+            return Ok(res);
+        }).await;
+    }
+
+    async fn find_rasterization_profile_print_jobs(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError> {
+        return self.query(format!("SELECT id FROM printjob WHERE rasterization_profile_id=(?) AND {} ORDER BY id", trash_condition(trashed)),
+            [id], |row: &Row| { Ok(row.get(0)?) }).await;
+    }
+
+    async fn query_workflows(&self) -> Result<Vec<Workflow>, CustomError> {
+        // Workflows which can't be built are left out
+        return self.with_connection(|db| {
+            return Ok(find_workflows_with(db, None)?.into_iter().filter_map(Result::ok).collect());
+        }).await;
+    }
+
+    async fn find_workflow(&self, id: DocID) -> Result<Workflow, CustomError> {
+        return self.with_connection(move |db| check_id_lookup_results(find_workflows_with(db, Some(id))?)?).await;
+    }
+
+    async fn insert_workflow(&self, data: WorkflowArgs, workflow: Workflow) -> Result<DocID, CustomError> {
+        return self.with_transaction(move |db| {
+            // Insert the Workflow
+            db.execute(
+                "INSERT INTO workflow (id, title) VALUES (NULL, ?1)",
+                params![data.Title]
+            )?;
+            let inserted_id : DocID = db.last_insert_rowid() as DocID;
+
+            // Load all workflow steps into the database.
+            let mut indexcounter : usize = 0;
+            let mut index_to_id : HashMap<usize, DocID> = HashMap::new();
+            for step_args in &data.WorkflowSteps {
+                db.execute(
+                    "INSERT INTO assigned_workflow_step (id, workflow_id, workflow_step_id, setup_time, time_per_page) VALUES (NULL, ?1, ?2, ?3, ?4)",
+                    params![inserted_id, step_args.WorkflowStepID, step_args.SetupTime, step_args.TimePerPage]
+                )?;
+
+                // map the primary key of each AssignedWorkflowStep to it's index in the vector.
+                let inserted_id : DocID = db.last_insert_rowid() as DocID;
+                index_to_id.insert(indexcounter, inserted_id);
+                indexcounter += 1;
+
+                for (name, value) in step_args.all_parameters() {
+                    db.execute("INSERT INTO assigned_workflow_step_param (assigned_workflow_step_id, name, value) VALUES (?1, ?2, ?3)",
+                        params![inserted_id, name, value.to_string()])?;
+                }
+            }
+
+            indexcounter = 0;
+            for step in &workflow.Steps {
+                for next_step in &step.next {
+                    db.execute(
+                        "INSERT INTO next_workflow_step (assigned_workflow_step_id, next_step_id) VALUES (?1, ?2)",
+                        params![index_to_id.get(&indexcounter), index_to_id.get(next_step)]
+                    )?;
+                }
+
+                for prev_step in &step.prev {
+                    db.execute(
+                        "INSERT INTO prev_workflow_step (assigned_workflow_step_id, prev_step_id) VALUES (?1, ?2)",
+                        params![index_to_id.get(&indexcounter), index_to_id.get(prev_step)]
+                    )?;
+                }
+                indexcounter+=1;
+            }
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    /// Deletes the assigned workflow steps associated with this
+    /// workflow id, then deletes the workflow itself.
+    async fn remove_workflow(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_transaction(move |db| {
+            // Delete the generic parameters of the workflow's steps
+            let mut stmt_params = db.prepare("
+                DELETE FROM assigned_workflow_step_param WHERE assigned_workflow_step_id IN
+                    (SELECT id FROM assigned_workflow_step WHERE workflow_id=(?))")?;
+            stmt_params.execute([id])?;
+
+            // Delete the edges between the workflow's steps
+            for table in ["next_workflow_step", "prev_workflow_step"] {
+                db.execute(&format!("
+                    DELETE FROM {table} WHERE assigned_workflow_step_id IN
+                        (SELECT id FROM assigned_workflow_step WHERE workflow_id=(?))"), [id])?;
+            }
+
+            // Delete all assigned workflow steps associated with the workflow
+            let mut stmt_steps = db.prepare("DELETE FROM assigned_workflow_step WHERE workflow_id=(?)")?;
+            stmt_steps.execute([id])?;
+
+            // Delete the workflow
+            let mut stmt = db.prepare("DELETE FROM workflow WHERE id=(?)")?;
+            let res = stmt.execute([id])?;
+
+            return Ok(res);
+        }).await;
+    }
+
+    async fn get_workflow_step_ids(&self) -> Result<Vec<DocID>, CustomError> {
+        return self.query("SELECT id FROM workflow_step", no_params(), |row: &Row| { Ok(row.get(0)?) }).await;
+    }
+
+    async fn find_custom_workflow_steps(&self) -> Result<Vec<WFSAttributes>, CustomError> {
+        return self.query("SELECT definition FROM workflow_step WHERE json_extract(definition, '$.variant') = 'Custom' ORDER BY id",
+            no_params(), |row: &Row| json_from_row(row, 0)).await;
+    }
+
+    async fn find_workflow_step_definition(&self, id: DocID) -> Result<Option<WFSAttributes>, CustomError> {
+        let rows = self.query("SELECT definition FROM workflow_step WHERE id=(?) AND definition IS NOT NULL",
+            [id], |row: &Row| json_from_row(row, 0)).await?;
+        return Ok(rows.into_iter().next());
+    }
+
+    async fn find_workflow_step_params(&self, workflow_step_id: DocID) -> Result<Vec<(DocID, String, String)>, CustomError> {
+        return self.query("
+            SELECT assigned_workflow_step_param.assigned_workflow_step_id, name, value
+            FROM assigned_workflow_step_param
+            JOIN assigned_workflow_step ON assigned_workflow_step.id = assigned_workflow_step_param.assigned_workflow_step_id
+            WHERE assigned_workflow_step.workflow_step_id=(?)",
+            [workflow_step_id], |row: &Row| { Ok((row.get(0)?, row.get(1)?, row.get(2)?)) }).await;
+    }
+
+    async fn find_next_workflow_step_id(&self) -> Result<DocID, CustomError> {
+        return self.with_connection(|db| {
+            Ok(db.query_row("SELECT COALESCE(MAX(id) + 1, 0) FROM workflow_step", [], |row| row.get(0))?)
+        }).await;
+    }
+
+    async fn count_workflow_step_uses(&self, id: DocID) -> Result<u32, CustomError> {
+        return self.with_connection(move |db| {
+            Ok(db.query_row("
+                SELECT
+                    (SELECT COUNT(*) FROM assigned_workflow_step WHERE workflow_step_id = ?1) +
+                    (SELECT COUNT(*) FROM simulation_report_step_time WHERE workflow_step_id = ?1) +
+                    (SELECT COUNT(*) FROM simulation_report_node_time WHERE workflow_step_id = ?1)
+            ", [id], |row| row.get(0))?)
+        }).await;
+    }
+
+    async fn insert_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError> {
+        return self.with_connection(move |db| {
+            db.execute("INSERT INTO workflow_step (id, title, definition) VALUES (?1, ?2, ?3)
+                ON CONFLICT(id) DO UPDATE SET title=excluded.title, definition=excluded.definition",
+                params![id, title, definition])?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn insert_custom_workflow_step(&self, id: DocID, title: String, definition: String) -> Result<(), CustomError> {
+        return self.with_connection(move |db| {
+            db.execute("INSERT INTO workflow_step (id, title, definition) VALUES (?1, ?2, ?3)",
+                params![id, title, definition])?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn update_assigned_workflow_step_type(&self, from: DocID, to: DocID) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            Ok(db.execute("UPDATE assigned_workflow_step SET workflow_step_id=?2 WHERE workflow_step_id=?1",
+                params![from, to])?)
+        }).await;
+    }
+
+    async fn remove_assigned_workflow_step_param(&self, id: DocID, name: String) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            Ok(db.execute("DELETE FROM assigned_workflow_step_param WHERE assigned_workflow_step_id=?1 AND name=?2",
+                params![id, name])?)
+        }).await;
+    }
+
+    async fn remove_workflow_step(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            let mut stmt = db.prepare("DELETE FROM workflow_step WHERE id=(?)")?;
+            let res = stmt.execute([id])?;
+            return Ok(res);
+        }).await;
+    }
+
+    async fn query_simulation_reports(&self) -> Result<Vec<SimulationReportDetailed>, CustomError> {
+        return self.query("
+            SELECT
+                simulation_report.id,
+                simulation_report.title,
+                simulation_report.creation_time,
+                simulation_report.total_time_taken,
+                printjobID,
+                workflowID,
+                COALESCE(printjob.title, json_extract(print_job_snapshot, '$.Title')),
+                COALESCE(workflow.title, json_extract(workflow_snapshot, '$.Title')),
+                rasterization_profile.title
+            FROM simulation_report
+            LEFT JOIN workflow
+                ON simulation_report.workflowID=workflow.id
+            LEFT JOIN printjob
+                ON simulation_report.printjobID=printjob.id
+            LEFT JOIN rasterization_profile
+                ON rasterization_profile.id=COALESCE(printjob.rasterization_profile_id,
+                    json_extract(print_job_snapshot, '$.RasterizationProfileID'))
+            WHERE simulation_report.deleted_at IS NULL;
+        ", no_params(), simulation_report_detailed_from_row).await;
+    }
+
+    async fn find_simulation_report(&self, id: DocID) -> Result<SimulationReport, CustomError> {
+        let rows = self.query("SELECT id, creation_time, total_time_taken, printjobID, workflowID, overrides, print_job_snapshot, workflow_snapshot, rerun_of FROM simulation_report WHERE id=(?) AND deleted_at IS NULL;",
+            [id], simulation_report_from_row).await?;
+        let mut report = check_id_lookup_results(rows)?;
+        report.StepTimes = self.find_simulation_report_workflow_steps(id).await?;
+        report.NodeTimes = self.find_simulation_report_node_times(id).await?;
+        return Ok(report);
+    }
+
+    async fn find_simulation_report_workflow_steps(&self, id: DocID) -> Result<HashMap<DocID, u32>, CustomError> {
+        let rows = self.query("
+            SELECT
+                simulation_report_step_time.workflow_step_id,
+                simulation_report_step_time.step_time
+            FROM simulation_report_step_time
+            WHERE simulation_report_step_time.simulation_report_id = (?);
+        ", [id], |row: &Row| { Ok((row.get(0)?, row.get(1)?)) }).await?;
+        return Ok(rows.into_iter().collect());
+    }
+
+    async fn find_simulation_report_node_times(&self, id: DocID) -> Result<Vec<NodeTime>, CustomError> {
+        return self.query("SELECT node_index, workflow_step_id, start_time, end_time FROM simulation_report_node_time WHERE simulation_report_id=(?) ORDER BY node_index;",
+            [id], node_time_from_row).await;
+    }
+
+    async fn store_simulation_report(&self, new_report: SimulationReport) -> Result<DocID, CustomError> {
+        let (overrides_json, print_job_json, workflow_json) = simulation_report_json(&new_report)?;
+
+        return self.with_transaction(move |db| {
+            db.execute(
+                "INSERT INTO simulation_report (id, title, creation_time, total_time_taken, printjobID, workflowID, overrides, print_job_snapshot, workflow_snapshot, rerun_of) VALUES (NULL, 'Default', ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![new_report.CreationTime, new_report.TotalTimeTaken, new_report.PrintJobID, new_report.WorkflowID, overrides_json, print_job_json, workflow_json, new_report.RerunOf]
+            )?;
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+
+            //Store the simulation time data in the db.
+            for (id, time) in &new_report.StepTimes {
+                db.execute(
+                    "INSERT INTO simulation_report_step_time (simulation_report_id, workflow_step_id, step_time) VALUES (?1, ?2, ?3)",
+                    params![inserted_id, id, time]
+                )?;
+            }
+            for node_time in &new_report.NodeTimes {
+                db.execute(
+                    "INSERT INTO simulation_report_node_time (simulation_report_id, node_index, workflow_step_id, start_time, end_time) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![inserted_id, node_time.NodeIndex as u32, node_time.WorkflowStepID, node_time.StartTime, node_time.EndTime]
+                )?;
+            }
+
+            return Ok(inserted_id);
+        }).await;
+    }
+
+    async fn remove_simulation_report(&self, id: DocID) -> Result<usize, CustomError> {
+        return self.with_transaction(move |db| {
+            db.execute("DELETE FROM simulation_report_step_time WHERE simulation_report_id=(?)", [id])?;
+            db.execute("DELETE FROM simulation_report_node_time WHERE simulation_report_id=(?)", [id])?;
+            let mut stmt = db.prepare("DELETE FROM simulation_report WHERE id=(?)")?;
+            let res = stmt.execute([id])?;
+            return Ok(res);
+        }).await;
+    }
+
+    async fn find_simulation_report_reruns(&self, id: DocID, trashed: bool) -> Result<Vec<DocID>, CustomError> {
+        return self.query(format!("SELECT id FROM simulation_report WHERE rerun_of=(?) AND {} ORDER BY id", trash_condition(trashed)),
+            [id], |row: &Row| { Ok(row.get(0)?) }).await;
+    }
+
+    async fn trash_item(&self, item: StoredItem, time: u32) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            return Ok(db.execute(&format!("UPDATE {} SET deleted_at=?1 WHERE id=?2 AND deleted_at IS NULL", item.Type.table()),
+                [time, item.id])?);
+        }).await;
+    }
+
+    async fn restore_item(&self, item: StoredItem) -> Result<usize, CustomError> {
+        return self.with_connection(move |db| {
+            return Ok(db.execute(&format!("UPDATE {} SET deleted_at=NULL WHERE id=(?) AND deleted_at IS NOT NULL", item.Type.table()),
+                [item.id])?);
+        }).await;
+    }
+
+    async fn query_trash(&self) -> Result<Vec<TrashedItem>, CustomError> {
+        return self.query(trash_query(), no_params(), trashed_item_from_row).await;
+    }
+
+    async fn find_dependencies(&self, item: StoredItem) -> Result<Vec<StoredItem>, CustomError> {
+        let (dependency_type, query) = match item.Type {
+            ItemType::PrintJob => (ItemType::RasterizationProfile, "SELECT rasterization_profile_id FROM printjob WHERE id=(?)"),
+            ItemType::SimulationReport => (ItemType::SimulationReport, "SELECT rerun_of FROM simulation_report WHERE id=(?)"),
+            ItemType::RasterizationProfile | ItemType::Workflow => return Ok(vec![]),
+        };
+        let ids = self.query(query, [item.id], |row: &Row| row.get::<_, Option<DocID>>(0)).await?;
+        return Ok(ids.into_iter().flatten().map(|id| StoredItem::new(dependency_type, id)).collect());
+    }
+
+    async fn query_workflow_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let stats = time_statistics_query("simulation_report", "workflowID", "total_time_taken");
+        return self.query(format!("
+            SELECT key, workflow.title, count, average, min, max, p50, p90, p95
+            FROM ({stats}) AS stats
+            LEFT JOIN workflow ON workflow.id = stats.key
+            ORDER BY key;"),
+            [from, to], time_statistics_from_row).await;
+    }
+
+    async fn query_workflow_step_time_statistics(&self, filter: AnalyticsFilter) -> Result<Vec<TimeStatistics>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let stats = time_statistics_query("
+            simulation_report_step_time JOIN simulation_report
+                ON simulation_report.id = simulation_report_step_time.simulation_report_id",
+            "simulation_report_step_time.workflow_step_id", "simulation_report_step_time.step_time");
+        return self.query(format!("
+            SELECT key, NULL, count, average, min, max, p50, p90, p95
+            FROM ({stats}) AS stats
+            ORDER BY key;"),
+            [from, to], time_statistics_from_row).await;
+    }
+
+    async fn query_time_per_page_trend(&self, filter: AnalyticsFilter) -> Result<Vec<TimePerPageTrend>, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let interval = filter.Interval.unwrap_or(86400).max(1);
+        // Reports whose print job was deleted use the print job of their snapshot
+        return self.query("
+            SELECT (creation_time / ?3) * ?3 AS bucket, COUNT(*),
+                AVG(CAST(total_time_taken AS REAL) / (page_count * copies))
+            FROM (
+                SELECT simulation_report.creation_time, simulation_report.total_time_taken,
+                    COALESCE(printjob.page_count, json_extract(print_job_snapshot, '$.PageCount')) AS page_count,
+                    COALESCE(printjob.copies, json_extract(print_job_snapshot, '$.Copies')) AS copies
+                FROM simulation_report
+                LEFT JOIN printjob ON printjob.id = simulation_report.printjobID
+                WHERE simulation_report.creation_time BETWEEN ?1 AND ?2
+                    AND simulation_report.deleted_at IS NULL
+            ) AS report
+            WHERE page_count * copies > 0
+            GROUP BY bucket
+            ORDER BY bucket;",
+            [from, to, interval], time_per_page_trend_from_row).await;
+    }
+
+    async fn query_usage(&self, filter: AnalyticsFilter) -> Result<Usage, CustomError> {
+        let (from, to) = filter_range(&filter);
+        let limit = filter.Limit.unwrap_or(10);
+        let workflows = self.query("
+            SELECT simulation_report.workflowID, workflow.title, COUNT(*) AS uses
+            FROM simulation_report
+            LEFT JOIN workflow ON workflow.id = simulation_report.workflowID
+            WHERE simulation_report.creation_time BETWEEN ?1 AND ?2
+                AND simulation_report.deleted_at IS NULL
+            GROUP BY simulation_report.workflowID
+            ORDER BY uses DESC, simulation_report.workflowID
+            LIMIT ?3;",
+            [from, to, limit], usage_count_from_row).await?;
+        let profiles = self.query("
+            SELECT report.profile_id, rasterization_profile.title, COUNT(*) AS uses
+            FROM (
+                SELECT COALESCE(printjob.rasterization_profile_id,
+                    json_extract(print_job_snapshot, '$.RasterizationProfileID')) AS profile_id
+                FROM simulation_report
+                LEFT JOIN printjob ON printjob.id = simulation_report.printjobID
+                WHERE simulation_report.creation_time BETWEEN ?1 AND ?2
+                    AND simulation_report.deleted_at IS NULL
+            ) AS report
+            LEFT JOIN rasterization_profile ON rasterization_profile.id = report.profile_id
+            WHERE report.profile_id IS NOT NULL
+            GROUP BY report.profile_id
+            ORDER BY uses DESC, report.profile_id
+            LIMIT ?3;",
+            [from, to, limit], usage_count_from_row).await?;
+        return Ok(Usage { Workflows: workflows, RasterizationProfiles: profiles });
+    }
+
+    async fn export_tables(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<BackupRow>>, CustomError> {
+        // The transaction reads from one snapshot of the database
+        return self.with_transaction(move |db| {
+            let mut exported = BTreeMap::new();
+            for table in tables {
+                let mut stmt = db.prepare(&format!("SELECT * FROM {table}"))?;
+                let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+                let rows = stmt.query_map([], |row| backup_row_from_row(row, &columns))?
+                    .collect::<Result<Vec<BackupRow>>>()?;
+                exported.insert(table.to_string(), rows);
+            }
+            return Ok(exported);
+        }).await;
+    }
+
+    async fn table_columns(&self, tables: Vec<&'static str>) -> Result<BTreeMap<String, Vec<String>>, CustomError> {
+        return self.with_connection(move |db| {
+            let mut columns = BTreeMap::new();
+            for table in tables {
+                columns.insert(table.to_string(), table_columns(db, table)?);
+            }
+            return Ok(columns);
+        }).await;
+    }
+
+    async fn clear_tables(&self, tables: Vec<&'static str>) -> Result<(), CustomError> {
+        return self.with_transaction(move |db| {
+            for table in tables {
+                db.execute_batch(&format!("DELETE FROM {table}"))?;
+            }
+            return Ok(());
+        }).await;
+    }
+
+    async fn import_table(&self, table: &'static str, rows: Vec<BackupRow>) -> Result<usize, CustomError> {
+        return self.with_transaction(move |db| {
+            let columns = table_columns(db, table)?;
+            let mut inserted = 0;
+            for row in rows {
+                // Only known columns make it into the statement
+                if let Some(column) = row.keys().find(|column| !columns.contains(column)) {
+                    return Err(CustomError::InvalidBackup(format!("Table {table} has no column {column}")));
+                }
+                let names: Vec<&str> = row.keys().map(|column| column.as_str()).collect();
+                let mut stmt = db.prepare_cached(&format!("INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT DO NOTHING",
+                    names.join(", "), vec!["?"; names.len()].join(", ")))?;
+                inserted += stmt.execute(params_from_iter(row.into_iter().map(|(_, value)| sql_value(value))))?;
+            }
+            return Ok(inserted);
+        }).await;
+    }
+
+    async fn insert_user(&self, email: String, password_hash: Vec<u8>) -> Result<DocID, CustomError> {
+        return self.with_connection(move |db| {
+            db.execute(
+                "INSERT INTO user (email, password_hash) VALUES (?1, ?2)",
+                params![email, password_hash]
+            )?;
+            let inserted_id : u32 = db.last_insert_rowid() as u32;
+            return Ok(inserted_id);
+        }).await;
+    }
+}
@@ -1,15 +1,31 @@
 use std::string;
 
-use crate::database::DocID;
+use crate::database::{DocID, MACHINE_COUNT_PARAM, NUM_CORES_PARAM, N_UP_PARAM, SHEET_SIZE_PARAM};
 use crate::workflow_steps::*;
-use serde::de::{Deserializer, Error};
+use serde::de::{DeserializeOwned, Deserializer, Error};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
 
 /// A node in the workflow graph
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowNode {
     pub data: WFSVariant,
+    /// Number of machines or workers running this step
+    #[serde(default = "default_machine_count")]
+    pub machine_count: u32,
+    /// Values of the parameters declared by the step in the catalog, by name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub parameters: HashMap<String, Value>,
+    /// Setup time of this node, if it differs from the catalog's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup_time: Option<u32>,
+    /// Time per unit of work of this node, if it differs from the catalog's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_per_page: Option<u32>,
+    /// Title of the step when the workflow was snapshotted, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
     /// List of indices corresponding to previous nodes
     pub prev: Vec<usize>,
     /// List of indices corresponding to subsequent nodes
@@ -37,39 +53,189 @@ pub struct WorkflowArgs {
 	pub WorkflowSteps: Vec<AssignedWorkflowStepArgs>
 }
 
+fn default_machine_count() -> u32 {
+    return 1;
+}
+
+/// Parameters which used to be fields of the step itself, by their old and new names
+const LEGACY_PARAMETERS: [(&str, &str); 3] = [
+    ("num_cores", NUM_CORES_PARAM),
+    ("n_up", N_UP_PARAM),
+    ("sheet_size", SHEET_SIZE_PARAM),
+];
+
+impl WorkflowNode {
+    /// Creates a node with no edges, one machine and the step's default parameters
+    pub fn new(data: WFSVariant) -> WorkflowNode {
+        let mut node = WorkflowNode {
+            data,
+            machine_count: 1,
+            parameters: HashMap::new(),
+            setup_time: None,
+            time_per_page: None,
+            title: None,
+            prev: vec![],
+            next: vec![],
+        };
+        node.fill_default_parameters();
+        return node;
+    }
+
+    /// Title of the step, as snapshotted or else from the catalog
+    pub fn title(&self) -> String {
+        return self.title.clone().unwrap_or_else(|| self.data.title());
+    }
+
+    /// Setup time of this node, which may override the catalog's
+    pub fn setup_time(&self) -> u32 {
+        return self.setup_time.unwrap_or_else(|| self.data.setup_time());
+    }
+
+    /// Time per unit of work of this node, which may override the catalog's
+    pub fn time_per_page(&self) -> u32 {
+        return self.time_per_page.unwrap_or_else(|| self.data.time_per_page());
+    }
+
+    /// Sets any of the step's parameters which this node doesn't have to their defaults
+    pub fn fill_default_parameters(&mut self) {
+        for definition in self.data.parameters() {
+            self.parameters.entry(definition.name).or_insert(definition.default);
+        }
+    }
+
+    /// Sets one of this node's parameters by name, checking that the step
+    /// declares it and that the value is of its type and within its range
+    pub fn set_parameter(&mut self, name: &str, value: &Value) -> Result<(), String> {
+        let title = self.title();
+        let definition = match name {
+            MACHINE_COUNT_PARAM => self.data.machine_count_parameter(),
+            _ => self.data.parameters().into_iter()
+                .find(|p| p.name == name)
+                .ok_or_else(|| format!("{title} has no parameter {name}"))?,
+        };
+        if !definition.accepts(value) {
+            return Err(format!("Invalid value {value} for {name} of {title}"));
+        }
+        match name {
+            // The count is an integer of at least 1, but may not fit a machine count
+            MACHINE_COUNT_PARAM => self.machine_count = value.as_u64()
+                .and_then(|count| u32::try_from(count).ok())
+                .ok_or_else(|| format!("Invalid value {value} for {name} of {title}"))?,
+            _ => { self.parameters.insert(name.to_string(), value.clone()); },
+        }
+        return Ok(());
+    }
+
+    /// Returns the value of one of this node's parameters as the given type
+    pub fn parameter<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        return self.parameters.get(name).and_then(|value| serde_json::from_value(value.clone()).ok());
+    }
+}
+
+/// Fields written with each step of a snapshot, which hold the step as it was then
+const SNAPSHOT_FIELDS: [&str; 3] = ["title", "setup_time", "time_per_page"];
+
+/// Removes the parameters which used to be fields of the step from a step's JSON object
+fn take_legacy_parameters(fields: &mut Map<String, Value>) -> Map<String, Value> {
+    return LEGACY_PARAMETERS.iter()
+        .filter_map(|(old, new)| fields.remove(*old).map(|value| (new.to_string(), value)))
+        .collect();
+}
+
 fn deserialize_steps<'de, D>(deserializer: D) -> Result<Vec<WorkflowNode>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let json_vector: Vec<Value> = Deserialize::deserialize(deserializer)?;
+
+    // Nodes that were serialized together with their edges, e.g. a workflow snapshot.
+    // The step's title and timings written with them are kept, so a snapshot doesn't
+    // change along with the catalog
+    if !json_vector.is_empty() && json_vector.iter().all(|o| o.get("data").is_some()) {
+        let mut nodes = Vec::<WorkflowNode>::new();
+        for mut object in json_vector {
+            if let Some(object) = object.as_object_mut() {
+                let (legacy, snapshotted) = match object.get_mut("data").and_then(Value::as_object_mut) {
+                    Some(data) => (take_legacy_parameters(data), SNAPSHOT_FIELDS.iter()
+                        .filter_map(|field| data.get(*field).map(|value| (field.to_string(), value.clone())))
+                        .collect()),
+                    None => (Map::new(), Map::new()),
+                };
+                for (field, value) in snapshotted {
+                    object.entry(field).or_insert(value);
+                }
+                if let Some(parameters) = object.entry("parameters").or_insert(Value::Object(Map::new())).as_object_mut() {
+                    parameters.extend(legacy);
+                }
+            }
+            let mut node: WorkflowNode = serde_json::from_value(object)
+                .map_err(|_| Error::custom("Invalid workflow node"))?;
+            node.fill_default_parameters();
+            nodes.push(node);
+        }
+        return Ok(nodes);
+    }
+
     let mut steps = Vec::<WorkflowNode>::new();
     for object in json_vector {
-        steps.push(WorkflowNode {
-            data: serde_json::from_value(object).map_err(|_| Error::custom(format!("Failed to serialize")))?,
-            prev: vec![],
-            next: vec![],
-        });
+        let mut object = match object {
+            Value::Object(object) => object,
+            _ => return Err(Error::custom("Invalid workflow step")),
+        };
+        let mut parameters = match object.remove("parameters") {
+            Some(Value::Object(parameters)) => parameters,
+            Some(_) => return Err(Error::custom("Invalid parameters")),
+            None => Map::new(),
+        };
+        parameters.extend(take_legacy_parameters(&mut object));
+        if let Some(count) = object.remove("machine_count") {
+            parameters.insert(MACHINE_COUNT_PARAM.to_string(), count);
+        }
+
+        let mut node = WorkflowNode::new(serde_json::from_value(Value::Object(object))
+            .map_err(|_| Error::custom(format!("Failed to serialize")))?);
+        for (name, value) in &parameters {
+            node.set_parameter(name, value).map_err(Error::custom)?;
+        }
+        steps.push(node);
     }
 
     // This will fill in the `prev`` and `next`` fields of the steps
     fill_edges(steps).map_err(|_| Error::custom("Failed to fill edges, likely an invalid workflow"))
 }
 
-/// Given a list of nodes with no edges, fill in the edges to create a graph
+/// Given a list of nodes with no edges, fill in the edges to create a graph.
+/// A step may come after another if either of them lists the other as valid,
+/// so a custom step only needs to list the steps it connects to
 pub fn fill_edges(steps: Vec<WorkflowNode>) -> Result<Vec<WorkflowNode>, ()> {
     // TODO: respect no_valid_prev and no_valid_next
     let mut new_steps = steps.clone();
     for (i, step) in steps.iter().enumerate() {
         for (j, other_step) in steps.iter().enumerate() {
             if i != j {
-                if step.data.valid_prev().contains(&other_step.data) {
+                if step.data.valid_prev().contains(&other_step.data.id())
+                    || other_step.data.valid_next().contains(&step.data.id()) {
                     new_steps[i].prev.push(j);
                 }
-                if step.data.valid_next().contains(&other_step.data) {
+                if step.data.valid_next().contains(&other_step.data.id())
+                    || other_step.data.valid_prev().contains(&step.data.id()) {
                     new_steps[i].next.push(j);
                 }
             }
         }
     }
     return Ok(new_steps);
-}
\ No newline at end of file
+}
+/// Checks that a workflow keeps all of its edges if the step of one of its nodes is replaced,
+/// i.e. that the replacement may come after and before the same nodes
+pub fn keeps_edges(workflow: &Workflow, index: usize, replacement: WFSVariant) -> bool {
+    let mut nodes: Vec<WorkflowNode> = workflow.Steps.iter()
+        .map(|node| WorkflowNode { prev: vec![], next: vec![], ..node.clone() })
+        .collect();
+    nodes[index].data = replacement;
+    return match fill_edges(nodes) {
+        Ok(nodes) => workflow.Steps[index].prev.iter().all(|i| nodes[index].prev.contains(i))
+            && workflow.Steps[index].next.iter().all(|i| nodes[index].next.contains(i)),
+        Err(_) => false,
+    };
+}
@@ -1,16 +1,17 @@
 use crate::database::*;
-use futures::future::try_join_all;
+use crate::workflow::{keeps_edges, WorkflowNode};
 use serde::{
     Serialize, 
     Deserialize,
     ser::{Serializer, SerializeStruct},
     de::{Deserializer, Error}
 };
-use serde_json::Value;
+use serde_json::{json, Value};
+use lazy_static::lazy_static;
 use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
 use strum::IntoEnumIterator;
-use strum_macros::EnumIter;
-use tokio::sync::OnceCell;
+use strum_macros::{AsRefStr, Display, EnumIter};
 
 /// Workflow step that will be returned to the user
 #[allow(non_snake_case)]
@@ -21,6 +22,30 @@ pub struct WorkflowStep {
     pub Title: String,
     pub SetupTime: u32,
     pub TimePerPage: u32,
+    /// The unit of work TimePerPage applies to
+    pub WorkUnit: WorkUnit,
+    /// IDs of the steps which may come directly before this one
+    #[serde(default)]
+    pub ValidPrev: Vec<DocID>,
+    /// IDs of the steps which may come directly after this one
+    #[serde(default)]
+    pub ValidNext: Vec<DocID>,
+    /// Can this step be the first step of a workflow?
+    #[serde(default)]
+    pub CanBeFirst: bool,
+    /// Can this step be the last step of a workflow?
+    #[serde(default)]
+    pub CanBeLast: bool,
+    /// JSON Schema of the parameters which can be set on each assigned step of this type
+    #[serde(default)]
+    pub ParameterSchema: Value,
+    /// Deprecated steps can't be added to new workflows
+    #[serde(default)]
+    pub Deprecated: bool,
+    #[serde(default)]
+    pub Stage: Option<Stage>,
+    #[serde(default)]
+    pub Category: Option<String>,
 }
 
 impl WorkflowStep {
@@ -31,6 +56,15 @@ impl WorkflowStep {
             Title: wfs.title(),
             SetupTime: wfs.setup_time(),
             TimePerPage: wfs.time_per_page(),
+            WorkUnit: wfs.work_unit(),
+            ValidPrev: wfs.valid_prev(),
+            ValidNext: wfs.valid_next(),
+            CanBeFirst: wfs.no_prev_valid(),
+            CanBeLast: wfs.no_next_valid(),
+            ParameterSchema: wfs.parameter_schema(),
+            Deprecated: wfs.deprecated(),
+            Stage: wfs.stage(),
+            Category: wfs.category(),
         });
     }
 }
@@ -41,8 +75,46 @@ impl WorkflowStep {
 pub struct AssignedWorkflowStepArgs {
     /// The ID of the workflow step to assign; [0-9]
     pub WorkflowStepID: u32, 
-    /// Number of RIPs, only applies to Rasterization step
+    /// Number of RIPs, shorthand for the Rasterization step's NumCores parameter
+    #[serde(default)]
     pub NumCores: Option<u32>,   
+    /// Number of machines or workers assigned to this step, defaults to 1
+    #[serde(default)]
+    pub MachineCount: Option<u32>,
+    /// Pages placed on each sheet, shorthand for the Impose step's NUp parameter
+    #[serde(default)]
+    pub NUp: Option<u32>,
+    /// Size of the press sheet, shorthand for the Impose step's SheetSize parameter
+    #[serde(default)]
+    pub SheetSize: Option<MediaSize>,
+    /// Values of the parameters declared by the step in the catalog, by name
+    #[serde(default)]
+    pub Parameters: HashMap<String, Value>,
+    /// Setup time of this step in this workflow, instead of the catalog's
+    #[serde(default)]
+    pub SetupTime: Option<u32>,
+    /// Time per unit of work of this step in this workflow, instead of the catalog's
+    #[serde(default)]
+    pub TimePerPage: Option<u32>,
+}
+
+impl AssignedWorkflowStepArgs {
+    /// All of the parameters given for this step by name, including the shorthand fields
+    pub fn all_parameters(&self) -> HashMap<String, Value> {
+        let mut parameters = self.Parameters.clone();
+        let shorthand = [
+            (MACHINE_COUNT_PARAM, self.MachineCount.map(Value::from)),
+            (NUM_CORES_PARAM, self.NumCores.map(Value::from)),
+            (N_UP_PARAM, self.NUp.map(Value::from)),
+            (SHEET_SIZE_PARAM, self.SheetSize.map(|size| Value::from(size.to_string()))),
+        ];
+        for (name, value) in shorthand {
+            if let Some(value) = value {
+                parameters.insert(name.to_string(), value);
+            }
+        }
+        return parameters;
+    }
 }
 
 /// The assigned workflow step in a workflow
@@ -53,7 +125,10 @@ pub struct AssignedWorkflowStep {
 	pub id: DocID,     
     /// Foreign key ID pertaining to what type of workflow step this is i.e. [0-9]        
     pub WorkflowStepID: DocID,
-    pub param_id: Option<DocID>,
+    /// Setup time overriding the catalog's for this assigned step
+    pub SetupTime: Option<u32>,
+    /// Time per unit of work overriding the catalog's for this assigned step
+    pub TimePerPage: Option<u32>,
     // TODO: are these still required?
     /// list of indices into a vec of AssignedWorkflowSteps, denoting which steps came last.
 	pub Prev: Vec<usize>,   
@@ -62,59 +137,244 @@ pub struct AssignedWorkflowStep {
 }
 
 /**
- * To add a new Workflow Step, add a new variant to the enum below,
- * define its attributes in the get_attributes() function, and add it 
- * to the EMPTY_WFS_VARIANT macro if it has no additional fields, 
- * otherwise add the variant to all relevant match statements
- * Compiler will give an error if neither is done
+ * Workflow Steps are defined in the step catalog, db/workflow_steps.json,
+ * which gives each step its ID, title, timings and which steps may come
+ * before and after it. Each catalog entry names the variant of the enum
+ * below which implements it, so timings and compatibility can be changed
+ * without recompiling, and the catalog can be reloaded at runtime
+ *
+ * A new step only needs a new variant in the enum below if the simulator
+ * treats it differently from other steps, as with Impose and Rasterization.
+ * Otherwise, a custom step can be created through the API instead
  *
- * Any variants with additional properties will require an additional
- * table in the database, see rasterization_params table for reference
+ * Each catalog entry also declares the step's parameters, with their types,
+ * defaults and ranges. Their values are stored for each assigned step in the
+ * assigned_workflow_step_param table, so adding a parameter only requires
+ * changing the catalog
  *
- * At runtime, any new enums will be automatically added to the
- * database and any removed enums will be removed from the database,
- * giving an error before starting the server if there are any
- * foreign key constraints
+ * Whenever the catalog is loaded, any new steps will be added to the
+ * database and any removed steps will be removed from the database,
+ * giving an error if any workflows or simulation reports still use them
  **/
 
 
 /// The different types of workflow steps
-#[derive(Clone, Copy, Debug, EnumIter, PartialEq)]
+#[derive(Clone, Copy, Debug, EnumIter, AsRefStr, PartialEq)]
 pub enum WFSVariant {
     DownloadFile,
     Preflight,
     Impose,
     Analyzer,
     ColorSetup,
-    Rasterization { num_cores: u32 },
+    Rasterization,
     Loader,
     Cutting,
     Laminating,
     Metrics,
+    /// A step defined by a user through the API, identified by its ID
+    Custom { id: DocID },
 }
 
-/// List variants which apply some default behavior in most match statements
-#[macro_export]
-macro_rules! EMPTY_WFS_VARIANT { () => {
-    WFSVariant::DownloadFile | WFSVariant::Preflight | WFSVariant::Impose | 
-    WFSVariant::Analyzer | WFSVariant::ColorSetup | WFSVariant::Loader | 
-    WFSVariant::Cutting | WFSVariant::Laminating | WFSVariant::Metrics
-}}
+/// How the work of a step is divided when more than one machine
+/// or worker is assigned to it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Parallelism {
+    /// Only a single machine can run this step
+    #[default]
+    None,
+    /// One setup is shared, then the pages are split between the workers
+    SplitPages,
+    /// Every machine is an independent lane with its own setup. Lanes are
+    /// set up in parallel, then split the pages between them
+    Lanes,
+}
+
+/// The unit of work a step is timed on. Impose turns pages into sheets,
+/// and sheets are gathered into stacks for finishing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum WorkUnit {
+    #[default]
+    Page,
+    Sheet,
+    Stack,
+}
+
+/// The part of the print process a step belongs to. A workflow moves through
+/// the stages in order, so a step can't lead back to an earlier stage
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display)]
+pub enum Stage {
+    Prepress,
+    Press,
+    Finishing,
+}
 
-/// Static properties of each Workflow Step
-struct WFSAttributes {
+/// The type of value a step parameter holds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ParameterKind {
+    Integer,
+    Number,
+    Boolean,
+    Text,
+    /// One of the parameter's options
+    Enum,
+}
+
+/// A parameter which can be set on each assigned step of a type of step
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParameterDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParameterKind,
+    /// Value used when an assigned step doesn't set this parameter
+    pub default: Value,
+    /// Smallest allowed value of an Integer or Number parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Largest allowed value of an Integer or Number parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Allowed values of an Enum parameter
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+impl ParameterDefinition {
+    /// Is the value of the type this parameter holds, and within its range or options?
+    pub fn accepts(&self, value: &Value) -> bool {
+        let in_range = |n: f64| self.min.is_none_or(|min| n >= min) && self.max.is_none_or(|max| n <= max);
+        return match self.kind {
+            ParameterKind::Integer => (value.is_i64() || value.is_u64()) && value.as_f64().is_some_and(in_range),
+            ParameterKind::Number => value.as_f64().is_some_and(in_range),
+            ParameterKind::Boolean => value.is_boolean(),
+            ParameterKind::Text => value.is_string(),
+            ParameterKind::Enum => value.as_str().is_some_and(|v| self.options.iter().any(|o| o == v)),
+        };
+    }
+
+    /// JSON Schema describing the values this parameter accepts
+    pub fn json_schema(&self) -> Value {
+        let bound = |n: f64| match self.kind {
+            ParameterKind::Integer => Value::from(n as i64),
+            _ => Value::from(n),
+        };
+        let mut schema = json!({
+            "type": match self.kind {
+                ParameterKind::Integer => "integer",
+                ParameterKind::Number => "number",
+                ParameterKind::Boolean => "boolean",
+                ParameterKind::Text | ParameterKind::Enum => "string",
+            },
+            "default": self.default,
+        });
+        if let Some(min) = self.min {
+            schema["minimum"] = bound(min);
+        }
+        if let Some(max) = self.max {
+            schema["maximum"] = bound(max);
+        }
+        if self.kind == ParameterKind::Enum {
+            schema["enum"] = json!(self.options);
+        }
+        return schema;
+    }
+}
+
+/// The arguments from the frontend to create a custom workflow step
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomWorkflowStepArgs {
+    pub Title: String,
+    pub SetupTime: u32,
+    pub TimePerPage: u32,
+    #[serde(default)]
+    pub Parallelism: Parallelism,
+    #[serde(default)]
+    pub WorkUnit: WorkUnit,
+    /// IDs of the steps which may come before this one
+    #[serde(default)]
+    pub ValidPrev: Vec<DocID>,
+    /// IDs of the steps which may come after this one
+    #[serde(default)]
+    pub ValidNext: Vec<DocID>,
+    #[serde(default)]
+    pub NoPrevValid: bool,
+    #[serde(default)]
+    pub NoNextValid: bool,
+    #[serde(default)]
+    pub Parameters: Vec<ParameterDefinition>,
+    /// Steps without a stage can be placed anywhere in a workflow
+    #[serde(default)]
+    pub Stage: Option<Stage>,
+    #[serde(default)]
+    pub Category: Option<String>,
+}
+
+/// Which workflow steps to list
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkflowStepFilter {
+    #[serde(default)]
+    pub Stage: Option<Stage>,
+    #[serde(default)]
+    pub Category: Option<String>,
+}
+
+/// The step to move the assigned steps of a deprecated step to
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateWorkflowStepArgs {
+    pub ReplacementID: DocID,
+}
+
+/// The outcome of moving the assigned steps of a deprecated step to its replacement
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowStepMigration {
+    pub id: DocID,
+    pub ReplacementID: DocID,
+    /// Number of assigned steps which were moved, across all workflows
+    pub Migrated: usize,
+    /// Number of parameters the replacement doesn't accept, which were removed
+    pub RemovedParameters: usize,
+}
+
+/// Properties of each Workflow Step, as defined in the step catalog
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WFSAttributes {
     id: DocID,
+    /// Name of the WFSVariant which implements this step
+    variant: String,
     title: String,
     setup_time: u32,
     time_per_page: u32,
-    /// List of valid previous steps for this type of step
-    valid_prev: Vec<WFSVariant>,
-    /// List of valid next steps for this type of step
-    valid_next: Vec<WFSVariant>,
+    /// How this step behaves when given more than one machine
+    parallelism: Parallelism,
+    /// What time_per_page is measured in
+    work_unit: WorkUnit,
+    /// List of IDs of valid previous steps for this type of step
+    #[serde(default)]
+    valid_prev: Vec<DocID>,
+    /// List of IDs of valid next steps for this type of step
+    #[serde(default)]
+    valid_next: Vec<DocID>,
     /// Can this type of step be the first step in a workflow?
+    #[serde(default)]
     no_prev_valid: bool,
     /// Can this type of step be the last step in a workflow?
+    #[serde(default)]
     no_next_valid: bool,
+    /// Parameters which can be set on each assigned step of this type
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<ParameterDefinition>,
+    /// Deprecated steps can still be loaded, but can't be added to new workflows
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deprecated: bool,
+    /// The part of the print process this step belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stage: Option<Stage>,
+    /// Finer grouping of steps within a stage, e.g. Inspection or Coating
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
 }
 
 impl WFSVariant {
@@ -131,10 +391,16 @@ impl WFSVariant {
     pub fn time_per_page(&self) -> u32 {
         self.get_wf_step_attributes().time_per_page
     }
-    pub fn valid_prev(&self) -> Vec<WFSVariant> {
+    pub fn parallelism(&self) -> Parallelism {
+        self.get_wf_step_attributes().parallelism
+    }
+    pub fn work_unit(&self) -> WorkUnit {
+        self.get_wf_step_attributes().work_unit
+    }
+    pub fn valid_prev(&self) -> Vec<DocID> {
         self.get_wf_step_attributes().valid_prev
     }
-    pub fn valid_next(&self) -> Vec<WFSVariant> {
+    pub fn valid_next(&self) -> Vec<DocID> {
         self.get_wf_step_attributes().valid_next
     }
     pub fn no_prev_valid(&self) -> bool {
@@ -143,159 +409,68 @@ impl WFSVariant {
     pub fn no_next_valid(&self) -> bool {
         self.get_wf_step_attributes().no_next_valid
     }
+    pub fn parameters(&self) -> Vec<ParameterDefinition> {
+        self.get_wf_step_attributes().parameters
+    }
+    pub fn deprecated(&self) -> bool {
+        self.get_wf_step_attributes().deprecated
+    }
+    pub fn stage(&self) -> Option<Stage> {
+        self.get_wf_step_attributes().stage
+    }
+    pub fn category(&self) -> Option<String> {
+        self.get_wf_step_attributes().category
+    }
 
-    /// This is where a Workflow Step's static attributes are defined.
-    /// Public functions call this one to retrieve specific attributes
-    fn get_wf_step_attributes(&self) -> WFSAttributes {
-        use WFSVariant::*;
-        return match self {
-            DownloadFile => WFSAttributes {
-                id: 0,
-                title: "Download File".to_string(),
-                setup_time: 0,
-                time_per_page: 1,
-                valid_prev: vec![],
-                valid_next: vec![Preflight],
-                no_prev_valid: true,
-                no_next_valid: false,
-            },
-
-            Preflight => WFSAttributes {
-                id: 1,
-                title: "Preflight".to_string(),
-                setup_time: 10,
-                time_per_page: 20,
-                valid_prev: vec![DownloadFile],
-                valid_next: vec![Impose],
-                no_prev_valid: false,
-                no_next_valid: false,
-            },
-
-            Impose => WFSAttributes {
-                id: 2,
-                title: "Impose".to_string(),
-                setup_time: 0,
-                time_per_page: 5,
-                valid_prev: vec![Preflight],
-                valid_next: vec![Analyzer],
-                no_prev_valid: false,
-                no_next_valid: false,
-            },
-
-            Analyzer => WFSAttributes {
-                id: 3,
-                title: "Analyzer".to_string(),
-                setup_time: 0,
-                time_per_page: 5,
-                valid_prev: vec![Impose],
-                valid_next: vec![ColorSetup],
-                no_prev_valid: false,
-                no_next_valid: false,
-            },
-
-            ColorSetup => WFSAttributes {
-                id: 4,
-                title: "Color Setup".to_string(),
-                setup_time: 2,
-                time_per_page: 1,
-                valid_prev: vec![Analyzer],
-                // Any rasterization num_cores valid [1,10]
-                // 🤨
-                valid_next: (0..=10)
-                    .map(|num_cores| Rasterization { num_cores })
-                    .collect(),
-                no_prev_valid: false,
-                no_next_valid: false,
-            },
-
-            Rasterization { .. } => WFSAttributes {
-                id: 5,
-                title: "Rasterization".to_string(),
-                setup_time: 50,
-                time_per_page: 15,
-                valid_prev: vec![ColorSetup],
-                valid_next: vec![Loader],
-                no_prev_valid: false,
-                no_next_valid: false,
-            },
-
-            Loader => WFSAttributes {
-                id: 6,
-                title: "Loader".to_string(),
-                setup_time: 100,
-                time_per_page: 1,
-                // Any rasterization num_cores valid [1,10]
-                valid_prev: (0..=10)
-                    .map(|num_cores| Rasterization { num_cores })
-                    .collect(),
-                valid_next: vec![Cutting, Laminating, Metrics],
-                no_prev_valid: false,
-                no_next_valid: true,
-            },
-
-            Cutting => WFSAttributes {
-                id: 7,
-                title: "Cutting".to_string(),
-                setup_time: 10,
-                time_per_page: 2,
-                valid_prev: vec![Loader, Metrics],
-                valid_next: vec![Laminating, Metrics],
-                no_prev_valid: false,
-                no_next_valid: true,
-            },
-
-            Laminating => WFSAttributes {
-                id: 8,
-                title: "Laminating".to_string(),
-                setup_time: 10,
-                time_per_page: 5,
-                valid_prev: vec![Loader, Cutting, Metrics],
-                valid_next: vec![Metrics],
-                no_prev_valid: false,
-                no_next_valid: true,
-            },
+    /// Whether the step works on every copy of a print job. Prepress work is
+    /// done once, and so is Rasterization, whose output is printed for every copy
+    pub fn prints_copies(&self) -> bool {
+        return *self != WFSVariant::Rasterization && self.stage().is_some_and(|stage| stage >= Stage::Press);
+    }
 
-            Metrics => WFSAttributes {
-                id: 9,
-                title: "Metrics".to_string(),
-                setup_time: 2,
-                time_per_page: 1,
-                valid_prev: vec![Loader, Cutting, Laminating],
-                valid_next: vec![Cutting, Laminating],
-                no_prev_valid: false,
-                no_next_valid: true,
+    /// Every step has a machine count, which can only be above one for
+    /// steps that can be run in parallel
+    pub fn machine_count_parameter(&self) -> ParameterDefinition {
+        return ParameterDefinition {
+            name: MACHINE_COUNT_PARAM.to_string(),
+            kind: ParameterKind::Integer,
+            default: Value::from(1),
+            min: Some(1.0),
+            max: match self.parallelism() {
+                Parallelism::None => Some(1.0),
+                _ => None,
             },
+            options: vec![],
         };
     }
 
-    // List the names of any tables with additional parameters
-    pub async fn get_wfs_param_table(&self) -> Option<String> {
-        use WFSVariant::*;
-        return match self {
-            Rasterization {..} => Some("rasterization_params".to_string()),
-            EMPTY_WFS_VARIANT!() => None
-        };
+    /// JSON Schema of the object of parameters which can be set on an assigned
+    /// step of this type, including its machine count
+    pub fn parameter_schema(&self) -> Value {
+        let properties: serde_json::Map<String, Value> = std::iter::once(self.machine_count_parameter())
+            .chain(self.parameters())
+            .map(|p| (p.name.clone(), p.json_schema()))
+            .collect();
+        return json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": self.title(),
+            "type": "object",
+            "properties": properties,
+            "additionalProperties": false,
+        });
     }
 
-    // For enum variants with fields, this function retrieves them from
-    // the database, otherwise it does nothing
-    // TODO: At some point, the entire workflow should be constructed 
-    // from the database before being handed off to other parts of 
-    // the program, meaning this should either be moved to database.rs 
-    // or be removed
-    async fn fill_properties(&mut self, prop_id: Option<DocID>) -> Result<(),CustomError> {
-        use WFSVariant::*;
-        match (self, prop_id) {
-            (Rasterization {num_cores}, Some(id)) => 
-                *num_cores = find_rasterization_params(id).await?,
-            (Rasterization {..}, None) => return Err(CustomError::OtherError(
-                "Rasterization requires prop_id".to_string(),)),
-            
-            (EMPTY_WFS_VARIANT!(), Some(_)) => return Err(CustomError::OtherError(
-                "Given WorkflowStep doesn't require prop_id".to_string(),)),
-            (EMPTY_WFS_VARIANT!(), None) => {}
-        }
-        return Ok(());
+    /// A Workflow Step's attributes are defined in the step catalog.
+    /// Public functions call this one to retrieve specific attributes
+    fn get_wf_step_attributes(&self) -> WFSAttributes {
+        let catalog = CATALOG.read().unwrap();
+        let id = match self {
+            WFSVariant::Custom { id } => Some(id),
+            _ => catalog.by_variant.get(self.as_ref()),
+        };
+        return id.and_then(|id| catalog.by_id.get(id))
+            .cloned()
+            .expect("Workflow step is missing from the catalog");
     }
 }
 
@@ -303,19 +478,12 @@ impl WFSVariant {
 impl Serialize for WFSVariant {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: Serializer {
-        use WFSVariant::*;
         let attr = self.get_wf_step_attributes();
         let mut state = serializer.serialize_struct("WFSAttributes", 4)?;
         state.serialize_field("id", &attr.id)?;
         state.serialize_field("title", &attr.title)?;
         state.serialize_field("setup_time", &attr.setup_time)?;
         state.serialize_field("time_per_page", &attr.time_per_page)?;
-
-        match self {
-            Rasterization { num_cores } => state.serialize_field("num_cores", num_cores)?,
-            EMPTY_WFS_VARIANT!() => {}
-        }
-
         return state.end();
     }
 }
@@ -325,18 +493,14 @@ impl<'de> Deserialize<'de> for WFSVariant {
     where D: Deserializer<'de> {
         let mut fields: serde_json::Map<String,Value> = Deserialize::deserialize(deserializer)?;
         
-        let mut output = get_variant_by_id(serde_json::from_value(fields.remove("id")
+        let output = get_variant_by_id(serde_json::from_value(fields.remove("id")
             .ok_or_else(|| Error::custom(format!("TODO")))?)
             .map_err(|_| Error::custom(format!("TODO")))?)
             .map_err(|_| Error::custom(format!("TODO")))?;
 
-        match output {
-            WFSVariant::Rasterization {ref mut num_cores} => {
-                *num_cores = serde_json::from_value(fields.remove("num_cores")
-                    .ok_or_else(|| Error::custom(format!("TODO")))?)
-                    .map_err(|_| Error::custom(format!("TODO")))?;
-            },
-            EMPTY_WFS_VARIANT!() => {}
+        // Informational fields written by `serialize`, which are looked up from the ID instead
+        for key in ["title", "setup_time", "time_per_page"] {
+            fields.remove(key);
         }
 
         if !fields.is_empty() {
@@ -348,31 +512,225 @@ impl<'de> Deserialize<'de> for WFSVariant {
 }
 
 
-static ID_TABLE: OnceCell<HashMap<DocID, WFSVariant>> = OnceCell::const_new();
-/// Run on program startup, after connecting to database to build a lookup
-/// table for getting a WFSVariant given its ID.
-/// Will insert any newly defined variants into the database and remove
-/// any that have been removed.
-/// Returns an error if any workflows rely on the removed workflow step
-pub async fn build_workflow_step_table() -> Result<(), CustomError> {
-    if let Some(_) = ID_TABLE.get() {
-        return Ok(());
-    }
-    let mut lookup_table = HashMap::<DocID, WFSVariant>::new();
-    let mut in_db = HashSet::<DocID>::from_iter(get_workflow_step_ids().await?);
-
-    for variant in WFSVariant::iter() {
-        let id = variant.id();
-        lookup_table.insert(id, variant);
-        if in_db.contains(&id) {
-            in_db.remove(&id);
-        } else {
-            insert_workflow_step(id).await?;
+/// Catalog entries for custom steps name this variant
+const CUSTOM_VARIANT: &str = "Custom";
+/// Custom steps are numbered from here, leaving room for new built-in steps
+const FIRST_CUSTOM_WORKFLOW_STEP_ID: DocID = 1000;
+
+/// Where the step catalog is read from on startup and saved to when replaced
+pub const WORKFLOW_STEP_CATALOG_LOCATION: &str = "./db/workflow_steps.json";
+
+/// The loaded step catalog, indexed by step ID and by variant name
+#[derive(Default)]
+struct Catalog {
+    by_id: HashMap<DocID, WFSAttributes>,
+    by_variant: HashMap<String, DocID>,
+}
+
+lazy_static! {
+    static ref CATALOG: RwLock<Catalog> = RwLock::new(Catalog::default());
+}
+
+impl Catalog {
+    /// Indexes the catalog entries, checking that IDs are unique, every entry is
+    /// implemented by exactly one known variant, every built-in variant implements
+    /// an entry and every edge leads to a step in the same or a later stage
+    fn new(entries: Vec<WFSAttributes>) -> Result<Catalog, CustomError> {
+        let mut catalog = Catalog::default();
+        for entry in entries {
+            if !WFSVariant::iter().any(|v| v.as_ref() == entry.variant) {
+                return Err(CustomError::InvalidCatalog(format!("Unknown variant {}", entry.variant)));
+            }
+            let is_custom = entry.variant == CUSTOM_VARIANT;
+            if !is_custom && catalog.by_variant.insert(entry.variant.clone(), entry.id).is_some() {
+                return Err(CustomError::InvalidCatalog(format!("Variant {} is defined twice", entry.variant)));
+            }
+            let mut names = HashSet::<&str>::new();
+            for parameter in &entry.parameters {
+                if !names.insert(&parameter.name) || parameter.name == MACHINE_COUNT_PARAM {
+                    return Err(CustomError::InvalidCatalog(format!("{} defines parameter {} more than once", entry.title, parameter.name)));
+                }
+                if parameter.kind == ParameterKind::Enum && parameter.options.is_empty() {
+                    return Err(CustomError::InvalidCatalog(format!("Parameter {} of {} has no options", parameter.name, entry.title)));
+                }
+                if !parameter.accepts(&parameter.default) {
+                    return Err(CustomError::InvalidCatalog(format!("Invalid default for parameter {} of {}", parameter.name, entry.title)));
+                }
+            }
+            if let Some(other) = catalog.by_id.insert(entry.id, entry) {
+                return Err(CustomError::InvalidCatalog(format!("ID {} is defined twice", other.id)));
+            }
+        }
+        // The simulator refers to built-in steps by variant, so none can be missing
+        if let Some(variant) = WFSVariant::iter()
+            .find(|v| v.as_ref() != CUSTOM_VARIANT && !catalog.by_variant.contains_key(v.as_ref())) {
+            return Err(CustomError::InvalidCatalog(format!("Variant {} has no entry", variant.as_ref())));
+        }
+        for entry in catalog.by_id.values() {
+            if let Some(id) = entry.valid_prev.iter().chain(&entry.valid_next).find(|id| !catalog.by_id.contains_key(id)) {
+                return Err(CustomError::InvalidCatalog(format!("{} refers to unknown step {id}", entry.title)));
+            }
+            let edges = entry.valid_prev.iter().map(|id| (&catalog.by_id[id], entry))
+                .chain(entry.valid_next.iter().map(|id| (entry, &catalog.by_id[id])));
+            for (before, after) in edges {
+                if let (Some(first), Some(second)) = (before.stage, after.stage) {
+                    if first > second {
+                        return Err(CustomError::InvalidCatalog(format!("{} ({first}) cannot come before {} ({second})",
+                            before.title, after.title)));
+                    }
+                }
+            }
         }
+        return Ok(catalog);
+    }
+
+    /// The catalog entries, ordered by ID
+    fn entries(&self) -> Vec<WFSAttributes> {
+        let mut entries: Vec<WFSAttributes> = self.by_id.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.id);
+        return entries;
+    }
+}
+
+/// Run on program startup, after connecting to database to load the step
+/// catalog from its file, along with the custom steps from the database, and
+/// build a lookup table for getting a WFSVariant given its ID.
+pub async fn build_workflow_step_table() -> Result<(), CustomError> {
+    return reload_workflow_step_catalog().await;
+}
+
+/// Loads the step catalog from its file again, replacing the one in use
+pub async fn reload_workflow_step_catalog() -> Result<(), CustomError> {
+    let text = std::fs::read_to_string(WORKFLOW_STEP_CATALOG_LOCATION)
+        .map_err(|e| CustomError::InvalidCatalog(e.to_string()))?;
+    let entries = serde_json::from_str(&text)
+        .map_err(|e| CustomError::InvalidCatalog(e.to_string()))?;
+    return load_workflow_step_catalog(entries).await;
+}
+
+/// Replaces the step catalog in use and saves it to the catalog file
+pub async fn replace_workflow_step_catalog(entries: Vec<WFSAttributes>) -> Result<(), CustomError> {
+    let text = serde_json::to_string_pretty(&entries)
+        .map_err(|e| CustomError::OtherError(e.to_string()))?;
+    load_workflow_step_catalog(entries).await?;
+    std::fs::write(WORKFLOW_STEP_CATALOG_LOCATION, text)
+        .map_err(|e| CustomError::OtherError(e.to_string()))?;
+    return Ok(());
+}
+
+/// Returns the step catalog in use, without the custom steps
+pub fn get_workflow_step_catalog() -> Vec<WFSAttributes> {
+    return CATALOG.read().unwrap().entries().into_iter()
+        .filter(|entry| entry.variant != CUSTOM_VARIANT)
+        .collect();
+}
+
+/// Creates a custom workflow step, stores it in the database and adds it to the catalog in use
+pub async fn create_custom_workflow_step(args: CustomWorkflowStepArgs) -> Result<WorkflowStep, CustomError> {
+    let mut entry = WFSAttributes {
+        id: FIRST_CUSTOM_WORKFLOW_STEP_ID,
+        variant: CUSTOM_VARIANT.to_string(),
+        title: args.Title,
+        setup_time: args.SetupTime,
+        time_per_page: args.TimePerPage,
+        parallelism: args.Parallelism,
+        work_unit: args.WorkUnit,
+        valid_prev: args.ValidPrev,
+        valid_next: args.ValidNext,
+        no_prev_valid: args.NoPrevValid,
+        no_next_valid: args.NoNextValid,
+        parameters: args.Parameters,
+        deprecated: false,
+        stage: args.Stage,
+        category: args.Category,
+    };
+    let entry = unit_of_work(|| async move {
+        // The ID is taken in the transaction the step is stored in, so concurrent requests get different IDs
+        entry.id = FIRST_CUSTOM_WORKFLOW_STEP_ID.max(find_next_workflow_step_id().await?);
+        let definition = serde_json::to_string(&entry)
+            .map_err(|e| CustomError::OtherError(e.to_string()))?;
+        insert_custom_workflow_step(entry.id, &entry.title, &definition).await?;
+
+        // An invalid step is rolled back
+        let mut entries = CATALOG.read().unwrap().entries();
+        entries.push(entry.clone());
+        Catalog::new(entries)?;
+        return Ok(entry);
+    }).await?;
+
+    // The step is only put in use once it's stored. The catalog is read and replaced
+    // under one lock, so concurrent requests keep each other's steps
+    let id = entry.id;
+    {
+        let mut catalog = CATALOG.write().unwrap();
+        let mut entries = catalog.entries();
+        entries.push(entry);
+        *catalog = Catalog::new(entries)?;
+    }
+    return WorkflowStep::get(id).await;
+}
+
+/// Validates the given catalog and puts it in use, inserting or updating its steps in
+/// the database and removing any steps it no longer has.
+///
+/// A removed step which workflows or simulation reports still rely on is retired
+/// instead: it is kept as a deprecated custom step, using the catalog entry it was
+/// last loaded with, until nothing uses it anymore. Returns an error if that entry
+/// was never stored
+async fn load_workflow_step_catalog(mut entries: Vec<WFSAttributes>) -> Result<(), CustomError> {
+    if let Some(entry) = entries.iter().find(|entry| entry.variant == CUSTOM_VARIANT) {
+        return Err(CustomError::InvalidCatalog(format!("Custom step {} must be created through the API", entry.title)));
     }
+    // The uses are counted in the transaction which removes the unused steps,
+    // so a step can't gain a use between being counted and being removed
+    let catalog = unit_of_work(|| async move {
+        // Retired steps are dropped once they are unused, or when the catalog defines them again
+        for step in find_custom_workflow_steps().await? {
+            if step.deprecated && (entries.iter().any(|entry| entry.id == step.id)
+                || count_workflow_step_uses(step.id).await? == 0) {
+                continue;
+            }
+            entries.push(step);
+        }
+
+        let mut removed = vec![];
+        for id in get_workflow_step_ids().await? {
+            if entries.iter().any(|entry| entry.id == id) {
+                continue;
+            }
+            if count_workflow_step_uses(id).await? == 0 {
+                removed.push(id);
+                continue;
+            }
+            let mut retired = find_workflow_step_definition(id).await?
+                .ok_or(CustomError::WorkflowStepInUse(id))?;
+            retired.variant = CUSTOM_VARIANT.to_string();
+            retired.deprecated = true;
+            entries.push(retired);
+        }
 
-    try_join_all(in_db.into_iter().map(|id| remove_workflow_step(id))).await?;
-    ID_TABLE.set(lookup_table)?;
+        // Retired steps may refer to steps which have since been removed
+        let ids: HashSet<DocID> = entries.iter().map(|entry| entry.id).collect();
+        for entry in entries.iter_mut().filter(|entry| entry.deprecated && entry.variant == CUSTOM_VARIANT) {
+            entry.valid_prev.retain(|id| ids.contains(id));
+            entry.valid_next.retain(|id| ids.contains(id));
+        }
+        let catalog = Catalog::new(entries)?;
+
+        for entry in catalog.by_id.values() {
+            let definition = serde_json::to_string(entry)
+                .map_err(|e| CustomError::OtherError(e.to_string()))?;
+            insert_workflow_step(entry.id, &entry.title, &definition).await?;
+        }
+        // The steps share the unit of work's connection, so they are removed one at a time
+        for id in removed {
+            remove_workflow_step(id).await?;
+        }
+        return Ok(catalog);
+    }).await?;
+    *CATALOG.write().unwrap() = catalog;
+    // Workflow steps are assembled from the catalog
+    invalidate_cached_workflows();
     return Ok(());
 }
 
@@ -381,28 +739,74 @@ pub async fn build_workflow_step_table() -> Result<(), CustomError> {
 /// have default values, so this should primarily be used for pattern 
 /// matching and get_workflow_step_by_id() should be used otherwise
 pub fn get_variant_by_id(id: DocID) -> Result<WFSVariant, CustomError> {
-    return ID_TABLE
-        .get()
-        .and_then(|table| table.get(&id).copied())
+    let variant = CATALOG.read().unwrap().by_id.get(&id)
+        .map(|entry| entry.variant.clone())
+        .ok_or_else(|| CustomError::OtherError("WorkflowStep not found".to_string()))?;
+    if variant == CUSTOM_VARIANT {
+        return Ok(WFSVariant::Custom { id });
+    }
+    return WFSVariant::iter()
+        .find(|v| v.as_ref() == variant)
         .ok_or_else(|| CustomError::OtherError("WorkflowStep not found".to_string()));
 }
 
-/// Gets a Workflow Step by its ID and fills its properties, if applicable
-/// 
-/// ### Arguments
-/// * wfs_id - The ID of the Workflow Step to retrieve
-/// * prop_id - The ID of the step's property table if applicable (e.g. Rasterization)
-pub async fn get_workflow_step_by_id(wfs_id: DocID, prop_id: Option<DocID>,)
--> Result<WFSVariant, CustomError> {
-    let mut output = get_variant_by_id(wfs_id)?;
-    output.fill_properties(prop_id).await?;
-    return Ok(output);
+/// Returns every workflow step which can be added to a workflow
+pub async fn get_all_workflow_steps() -> Result<Vec<WorkflowStep>, CustomError> {
+    return get_filtered_workflow_steps(&WorkflowStepFilter::default()).await;
 }
 
-pub async fn get_all_workflow_steps() -> Vec<WorkflowStep> {
+/// Returns the workflow steps which can be added to a workflow, and are in the
+/// given stage and category if any
+pub async fn get_filtered_workflow_steps(filter: &WorkflowStepFilter) -> Result<Vec<WorkflowStep>, CustomError> {
     let mut output = Vec::<WorkflowStep>::new();
-    for variant in WFSVariant::iter() {
-        output.push(WorkflowStep::get(variant.id()).await.expect(""));
+    let entries = CATALOG.read().unwrap().entries();
+    for entry in entries.into_iter().filter(|entry| !entry.deprecated
+        && filter.Stage.is_none_or(|stage| entry.stage == Some(stage))
+        && filter.Category.as_ref().is_none_or(|category| entry.category.as_ref() == Some(category))) {
+        output.push(WorkflowStep::get(entry.id).await?);
+    }
+    return Ok(output);
+}
+/// Moves the assigned steps of a deprecated workflow step to its replacement, in every
+/// workflow. Parameters which the replacement doesn't accept are removed from them
+pub async fn migrate_workflow_step(id: DocID, replacement_id: DocID) -> Result<WorkflowStepMigration, CustomError> {
+    let step = get_variant_by_id(id)
+        .map_err(|_| CustomError::InvalidMigration(format!("WorkflowStep {id} not found")))?;
+    let replacement = get_variant_by_id(replacement_id)
+        .map_err(|_| CustomError::InvalidMigration(format!("WorkflowStep {replacement_id} not found")))?;
+    if !step.deprecated() {
+        return Err(CustomError::InvalidMigration(format!("{} is not deprecated", step.title())));
+    }
+    if replacement.deprecated() {
+        return Err(CustomError::InvalidMigration(format!("{} is deprecated", replacement.title())));
     }
-    return output;
-}
\ No newline at end of file
+
+    let template = WorkflowNode::new(replacement);
+    let (migrated, removed_parameters) = unit_of_work(|| async move {
+        // Every workflow using the step has to stay valid with the replacement in its place
+        let invalid: Vec<String> = query_workflows().await?.iter()
+            .filter(|workflow| workflow.Steps.iter().enumerate()
+                .any(|(i, node)| node.data.id() == id && !keeps_edges(workflow, i, replacement)))
+            .map(|workflow| format!("{} ({})", workflow.Title, workflow.id.unwrap_or_default()))
+            .collect();
+        if !invalid.is_empty() {
+            return Err(CustomError::InvalidMigration(format!(
+                "{} can't take the place of {} in workflows {}", replacement.title(), step.title(), invalid.join(", "))));
+        }
+        let mut removed_parameters = 0;
+        for (assigned_id, name, text) in find_workflow_step_params(id).await? {
+            if template.clone().set_parameter(&name, &stored_param_value(text)).is_err() {
+                remove_assigned_workflow_step_param(assigned_id, &name).await?;
+                removed_parameters += 1;
+            }
+        }
+        let migrated = update_assigned_workflow_step_type(id, replacement_id).await?;
+        return Ok((migrated, removed_parameters));
+    }).await?;
+    return Ok(WorkflowStepMigration {
+        id,
+        ReplacementID: replacement_id,
+        Migrated: migrated,
+        RemovedParameters: removed_parameters,
+    });
+}
@@ -1,16 +1,24 @@
 use axum::http::StatusCode;
+use backend::api::ADMIN_TOKEN_ENV;
 use backend::database::*;
+use backend::migrations::*;
+use backend::sqlite::SqliteRepository;
 use reqwest;
 use serde_json::{from_str, json};
 use serial_test::serial;
+use std::sync::Arc;
 
 const HOST: &str = "localhost";
 const PORT: &str = "5040";
+/// Token sent to the admin routes by the tests which enable them
+const ADMIN_TOKEN: &str = "test-admin-token";
 
 #[tokio::test]
 #[serial]
 async fn test_get_print_jobs() {
-    // Start test server
+    // Start test server on a new database
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -30,7 +38,9 @@ async fn test_get_print_jobs() {
 #[tokio::test]
 #[serial]
 async fn test_get_workflows() {
-    // Start test server
+    // Start test server on a new database
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -50,7 +60,9 @@ async fn test_get_workflows() {
 #[tokio::test]
 #[serial]
 async fn test_get_workflow_steps() {
-    // Start test server
+    // Start test server on a new database
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -64,13 +76,311 @@ async fn test_get_workflow_steps() {
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let steps: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(steps[0]["CanBeFirst"], true);
+    assert_eq!(steps[0]["ValidNext"], json!([1]));
+    assert_eq!(steps[0]["ParameterSchema"]["properties"]["MachineCount"]["maximum"], 1);
+
+    // Rasterization declares its number of cores, and can run on many machines
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep/5", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let step: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let schema = &step["ParameterSchema"];
+    assert_eq!(schema["type"], "object");
+    assert_eq!(schema["properties"]["NumCores"], json!({ "type": "integer", "default": 1, "minimum": 1, "maximum": 10 }));
+    assert!(schema["properties"]["MachineCount"].get("maximum").is_none());
+    assert_eq!(step["CanBeFirst"], false);
+
+    // Enum parameters list their options
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep/2", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let step: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let sheet_size = &step["ParameterSchema"]["properties"]["SheetSize"];
+    assert_eq!(sheet_size["type"], "string");
+    assert!(sheet_size["enum"].as_array().unwrap().contains(&json!("B2")));
+
+    // Steps can be listed by stage and category
+    let ids = |steps: serde_json::Value| steps.as_array().unwrap().iter()
+        .map(|s| s["id"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep?Stage=Finishing", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ids(from_str(&response.text().await.unwrap()).unwrap()), vec![7, 8, 9]);
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep?Stage=Prepress&Category=Inspection", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ids(from_str(&response.text().await.unwrap()).unwrap()), vec![1, 3]);
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workflow_step_stages(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // Finishing can't lead back to press
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .json(&json!({
+            "Title": "Reprint",
+            "SetupTime": 5,
+            "TimePerPage": 1,
+            "ValidPrev": [7],
+            "ValidNext": [6],
+            "Stage": "Finishing"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/WorkflowStep/Time", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let step_times: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Stage/Time", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let stage_times: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    // Download File through Color Setup are prepress, Rasterization and Loader are press
+    let sum = |ids: &[&str]| ids.iter().map(|id| step_times[*id].as_u64().unwrap()).sum::<u64>();
+    assert_eq!(stage_times["Prepress"], sum(&["0", "1", "2", "3", "4"]));
+    assert_eq!(stage_times["Press"], sum(&["5", "6"]));
+    assert!(stage_times.get("Finishing").is_none());
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Stage/Time", HOST, PORT, 999999))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_schema_migrations() {
+    let repository = Arc::new(SqliteRepository::open(&DatabaseLocation::InMemory).unwrap());
+    set_repository(repository.clone());
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let status = migration_status().await.unwrap();
+    assert_eq!(status.CurrentVersion, status.LatestVersion);
+    assert!(status.Pending.is_empty());
+
+    // A database migrated by a newer server is refused
+    let future_version = status.LatestVersion + 1;
+    repository.connection().unwrap()
+        .execute("INSERT INTO schema_version (version, name, applied_at) VALUES (?1, 'future', 0)", [future_version])
+        .unwrap();
+    let newer_status = migration_status().await;
+    let newer_migrate = migrate_database().await;
+    repository.connection().unwrap()
+        .execute("DELETE FROM schema_version WHERE version=(?)", [future_version])
+        .unwrap();
+    assert!(matches!(newer_status, Err(CustomError::SchemaTooNew(..))));
+    assert!(matches!(newer_migrate, Err(CustomError::SchemaTooNew(..))));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_upgrade_baseline_database() {
+    // A database created by hand from the original SQL files, before migrations were tracked
+    let path = temp_database_path("baseline");
+    let baseline = SqliteRepository::open(&DatabaseLocation::File(path.clone())).unwrap();
+    baseline.connection().unwrap().execute_batch(&format!("{}{}
+        INSERT INTO printjob (id, title, creation_time, page_count, rasterization_profile_id) VALUES (1, 'Baseline PrintJob', 0, 10, 1);
+        INSERT INTO workflow (id, title) VALUES (1, 'Baseline Workflow');
+        INSERT INTO workflow_step (id) VALUES (0);
+        INSERT INTO workflow_step (id) VALUES (5);
+        INSERT INTO assigned_workflow_step (id, workflow_id, workflow_step_id) VALUES (1, 1, 0);
+        INSERT INTO assigned_workflow_step (id, workflow_id, workflow_step_id) VALUES (2, 1, 5);
+        INSERT INTO rasterization_params (id, assigned_workflow_step_id, num_of_RIPs) VALUES (NULL, 2, 2);
+        INSERT INTO rasterization_params (id, assigned_workflow_step_id, num_of_RIPs) VALUES (NULL, 2, 4);
+        INSERT INTO simulation_report (id, title, creation_time, total_time_taken, printjobID, workflowID) VALUES (1, 'Baseline Report', 0, 42, 1, 1);
+        INSERT INTO simulation_report_step_time (simulation_report_id, workflow_step_id, step_time) VALUES (1, 0, 42);
+    ", MIGRATIONS[0].sql, SEED)).unwrap();
+    drop(baseline);
+
+    // Every later migration is applied to it, keeping its data
+    open_database(DatabaseLocation::File(path.clone())).unwrap();
+    setup_database().await.unwrap();
+    assert_eq!(migration_status().await.unwrap().CurrentVersion, latest_version());
+    // It already has the sample data, so seeding it again adds nothing
+    assert!(!seed_database().await.unwrap());
+    assert_eq!(query_rasterization_profiles().await.unwrap().len(), 5);
+    let print_job = find_print_job(1).await.unwrap();
+    assert_eq!(print_job.PageSize, MediaSize::Letter);
+    assert_eq!(print_job.Copies, 1);
+    let report = find_simulation_report(1).await.unwrap();
+    assert_eq!(report.TotalTimeTaken, 42);
+    assert_eq!(report.StepTimes.get(&0), Some(&42));
+    assert!(report.Overrides.is_empty());
+
+    // The rasterization step keeps its last number of RIPs as its NumCores parameter
+    let workflow = find_workflow(1).await.unwrap();
+    assert_eq!(workflow.Steps.len(), 2);
+    let rasterization = workflow.Steps.iter().find(|node| node.data.id() == 5).unwrap();
+    assert_eq!(rasterization.parameter::<u32>(NUM_CORES_PARAM), Some(4));
+
+    // Reports no longer refer to their print job and workflow by foreign key
+    let upgraded = SqliteRepository::open(&DatabaseLocation::File(path.clone())).unwrap();
+    let foreign_keys: u32 = upgraded.connection().unwrap()
+        .query_row("SELECT COUNT(*) FROM pragma_foreign_key_list('simulation_report')", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(foreign_keys, 0);
+    let violations: u32 = upgraded.connection().unwrap()
+        .query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(violations, 0);
+    drop(upgraded);
+
+    open_database(DatabaseLocation::InMemory).unwrap();
+    remove_temp_database(&path);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_database_pool() {
+    let path = temp_database_path("pool");
+    open_database(DatabaseLocation::File(path.clone())).unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // Pooled connections use WAL mode
+    let journal_mode: String = SqliteRepository::open(&DatabaseLocation::File(path.clone())).unwrap()
+        .connection().unwrap()
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+
+    // Concurrent requests are each served. Workflows are cached, so print jobs are listed instead
+    let client = reqwest::Client::new();
+    let requests = (0..20).map(|_| client
+        .get(&format!("http://{}:{}/PrintJob", HOST, PORT))
+        .send());
+    for response in futures::future::join_all(requests).await {
+        assert_eq!(response.unwrap().status(), StatusCode::OK.as_u16());
+    }
+
+    let response = client
+        .get(&format!("http://{}:{}/Metrics/Database", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let metrics: PoolMetrics = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(metrics.MaxConnections, 8);
+    assert!(metrics.Connections <= metrics.MaxConnections);
+    assert_eq!(metrics.ActiveConnections, metrics.Connections - metrics.IdleConnections);
+    assert!(metrics.Checkouts >= 20);
+    assert_eq!(metrics.Timeouts, 0);
+
+    server.abort();
+    open_database(DatabaseLocation::InMemory).unwrap();
+    remove_temp_database(&path);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_database_location() {
+    let path = temp_database_path("location");
+    open_database(DatabaseLocation::File(path.clone())).unwrap();
+    migrate_database().await.unwrap();
+    assert!(seed_database().await.unwrap());
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // A new database file is created with the schema, and then seeded with the sample data
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
     server.abort();
+
+    // An in-memory database is migrated when it's opened, and doesn't see the file's data
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    assert_eq!(migration_status().await.unwrap().CurrentVersion, latest_version());
+    assert!(find_print_job(print_job_id).await.is_err());
+    assert!(find_rasterization_profile(rasterization_profile_id).await.is_ok());
+
+    // Nor does another in-memory database see the first one's
+    let memory_print_job_id = insert_print_job(PrintJob {
+        id: None,
+        DateCreated: None,
+        Title: "In-memory PrintJob".to_string(),
+        PageCount: 1,
+        RasterizationProfileID: rasterization_profile_id,
+        PageSize: MediaSize::Letter,
+        Duplex: false,
+        Copies: 1,
+    }).await.unwrap();
+    assert!(find_print_job(memory_print_job_id).await.is_ok());
+    open_database(DatabaseLocation::InMemory).unwrap();
+    assert!(find_print_job(memory_print_job_id).await.is_err());
+
+    // The file keeps its data when reopened
+    open_database(DatabaseLocation::parse(&path)).unwrap();
+    assert_eq!(find_print_job(print_job_id).await.unwrap().Title, "Test Print Job");
+
+    open_database(DatabaseLocation::InMemory).unwrap();
+    remove_temp_database(&path);
+}
+
+/// A path for a database file which only this test uses
+fn temp_database_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("printos-test-{}-{name}.db3", std::process::id()));
+    return path.to_string_lossy().to_string();
+}
+
+fn remove_temp_database(path: &str) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{path}{suffix}"));
+    }
 }
 
 #[tokio::test]
 #[serial]
 async fn test_get_simulation_reports() {
-    // Start test server
+    // Start test server on a new database
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -90,6 +400,8 @@ async fn test_get_simulation_reports() {
 #[tokio::test]
 #[serial]
 async fn test_simulation_report_create(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -108,6 +420,8 @@ async fn test_simulation_report_create(){
 #[tokio::test]
 #[serial]
 async fn test_printjob_post_get_delete() {
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -124,6 +438,8 @@ async fn test_printjob_post_get_delete() {
 #[tokio::test]
 #[serial]
 async fn test_workflow_post_get_delete(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
     let server = tokio::spawn(async {
         backend::run_server(HOST, PORT).await;
     });
@@ -136,6 +452,1603 @@ async fn test_workflow_post_get_delete(){
     server.abort();
 }
 
+#[tokio::test]
+#[serial]
+async fn test_workflow_machine_count(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let mut payload = json!({
+        "Title": "Test Workflow Machine Count",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0 },
+            { "WorkflowStepID": 1, "MachineCount": 3 },
+            { "WorkflowStepID": 2 },
+            { "WorkflowStepID": 3 },
+            { "WorkflowStepID": 4 },
+            { "WorkflowStepID": 5, "NumCores": 2 },
+            { "WorkflowStepID": 6, "MachineCount": 2 }
+        ]
+    });
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][1]["machine_count"], 3);
+    assert_eq!(workflow["Steps"][6]["machine_count"], 2);
+    assert_eq!(workflow["Steps"][0]["machine_count"], 1);
+
+    // Loader's lanes are set up in parallel, so another machine never makes it slower
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let mut total_times = vec![];
+    for machine_count in 1..=4 {
+        let response = client
+            .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+            .json(&json!({
+                "PrintJobID": print_job_id,
+                "WorkflowID": workflow_id,
+                "Overrides": [{ "WorkflowStepID": 6, "Parameters": { "MachineCount": machine_count } }]
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+        let report_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+        let response = client
+            .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+            .send()
+            .await
+            .unwrap();
+        let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+        total_times.push(report["TotalTimeTaken"].as_u64().unwrap());
+    }
+    assert!(total_times.windows(2).all(|pair| pair[1] <= pair[0]), "{total_times:?}");
+    assert!(total_times[1] < total_times[0]);
+
+    // Download File can't be run on more than one machine
+    payload["WorkflowSteps"][0]["MachineCount"] = json!(2);
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    // Machine counts must be whole numbers which fit a machine count
+    payload["WorkflowSteps"][0]["MachineCount"] = json!(1);
+    for machine_count in [json!(u64::from(u32::MAX) + 1), json!(2.5)] {
+        payload["WorkflowSteps"][6]["MachineCount"] = machine_count;
+        let response = client
+            .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+            .json(&payload)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workflow_timing_overrides(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&json!({
+            "Title": "Test Fast Preflight",
+            "WorkflowSteps": [
+                { "WorkflowStepID": 0 },
+                { "WorkflowStepID": 1, "TimePerPage": 10 },
+                { "WorkflowStepID": 2 },
+                { "WorkflowStepID": 3 },
+                { "WorkflowStepID": 4 },
+                { "WorkflowStepID": 5 },
+                { "WorkflowStepID": 6 }
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][1]["time_per_page"], 10);
+    assert!(workflow["Steps"][1].get("setup_time").is_none());
+    assert!(workflow["Steps"][0].get("time_per_page").is_none());
+
+    // The workflow's timing is used instead of the catalog's
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(report["TotalTimeTaken"], 542);
+
+    // And a report's overrides are used instead of the workflow's
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({
+            "PrintJobID": print_job_id,
+            "WorkflowID": workflow_id,
+            "Overrides": [{ "NodeIndex": 1, "TimePerPage": 20 }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let report_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(report["TotalTimeTaken"], 642);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_imposition(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let response = client
+        .post(&format!("http://{}:{}/PrintJob", HOST, PORT))
+        .json(&json!({
+            "Title": "Test Imposed Print Job",
+            "PageCount": 100,
+            "Copies": 5,
+            "Duplex": true,
+            "PageSize": "Letter",
+            "RasterizationProfileID": rasterization_profile_id
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let print_job_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/PrintJob/{}", HOST, PORT, print_job_id))
+        .send()
+        .await
+        .unwrap();
+    let print_job: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(print_job["Copies"], 5);
+    assert_eq!(print_job["Duplex"], true);
+
+    let mut payload = json!({
+        "Title": "Test Imposed Workflow",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0 },
+            { "WorkflowStepID": 1 },
+            { "WorkflowStepID": 2, "NUp": 4, "SheetSize": "B2" },
+            { "WorkflowStepID": 3 },
+            { "WorkflowStepID": 4 },
+            { "WorkflowStepID": 5, "NumCores": 1 },
+            { "WorkflowStepID": 6 },
+            { "WorkflowStepID": 7 }
+        ]
+    });
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][2]["parameters"]["NUp"], 4);
+    assert_eq!(workflow["Steps"][2]["parameters"]["SheetSize"], "B2");
+
+    let sim_report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/WorkflowStep/Time", HOST, PORT, sim_report_id))
+        .send()
+        .await
+        .unwrap();
+    let step_times: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    // Prepress works on a single copy of 100 pages, 4-up duplex onto 13 sheets,
+    // which are printed for each of the 5 copies and gathered into one stack
+    assert_eq!(step_times["1"], 10 + 100 * 20);
+    assert_eq!(step_times["5"], 50 + 13 * 15);
+    assert_eq!(step_times["6"], 100 + 13 * 5);
+    assert_eq!(step_times["7"], 10 + 2);
+
+    // Four Letter pages don't fit on an SRA3 sheet
+    payload["WorkflowSteps"][2]["SheetSize"] = json!("SRA3");
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({ "PrintJobID": print_job_id, "WorkflowID": workflow_id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_finishing_parameters(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let response = client
+        .post(&format!("http://{}:{}/PrintJob", HOST, PORT))
+        .json(&json!({
+            "Title": "Test Finished Print Job",
+            "PageCount": 100,
+            "Copies": 20,
+            "RasterizationProfileID": rasterization_profile_id
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let print_job_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&json!({
+            "Title": "Test Finished Workflow",
+            "WorkflowSteps": [
+                { "WorkflowStepID": 0 },
+                { "WorkflowStepID": 1 },
+                { "WorkflowStepID": 2 },
+                { "WorkflowStepID": 3 },
+                { "WorkflowStepID": 4 },
+                { "WorkflowStepID": 5 },
+                { "WorkflowStepID": 6 },
+                { "WorkflowStepID": 7 },
+                { "WorkflowStepID": 8 }
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    // 2000 sheets are gathered into 4 stacks. Each blade cuts a stack of its own,
+    // and soft touch film takes twice as long to apply as gloss
+    let overrides = json!([
+        { "WorkflowStepID": 7, "Parameters": { "BladeCount": 4 } },
+        { "WorkflowStepID": 8, "Parameters": { "FilmType": "SoftTouch" } }
+    ]);
+    let mut step_times = vec![];
+    for overrides in [json!([]), overrides] {
+        let response = client
+            .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+            .json(&json!({ "PrintJobID": print_job_id, "WorkflowID": workflow_id, "Overrides": overrides }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+        let sim_report_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+        let response = client
+            .get(&format!("http://{}:{}/SimulationReport/{}/WorkflowStep/Time", HOST, PORT, sim_report_id))
+            .send()
+            .await
+            .unwrap();
+        step_times.push(from_str::<serde_json::Value>(&response.text().await.unwrap()).unwrap());
+    }
+    assert_eq!(step_times[0]["7"], 10 + 4 * 2);
+    assert_eq!(step_times[0]["8"], 10 + 2000 * 5);
+    assert_eq!(step_times[1]["7"], 10 + 2);
+    assert_eq!(step_times[1]["8"], 10 + 2000 * 10);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_report_overrides(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let overrides = json!([
+        { "WorkflowStepID": 1, "TimePerPage": 10 },
+        { "NodeIndex": 6, "SetupTime": 50, "Parameters": { "MachineCount": 2 } }
+    ]);
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({ "PrintJobID": print_job_id, "WorkflowID": workflow_id, "Overrides": overrides }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let sim_report_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/WorkflowStep/Time", HOST, PORT, sim_report_id))
+        .send()
+        .await
+        .unwrap();
+    let step_times: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(step_times["1"], 10 + 10 * 10);
+    assert_eq!(step_times["6"], 50 + 5);
+
+    // The report records the overrides it was simulated with
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, sim_report_id))
+        .send()
+        .await
+        .unwrap();
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(report["Overrides"], overrides);
+
+    // Download File has no NumCores parameter
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({
+            "PrintJobID": print_job_id,
+            "WorkflowID": workflow_id,
+            "Overrides": [{ "NodeIndex": 0, "Parameters": { "NumCores": 4 } }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    // Times which no longer fit are rejected, while more workers than fit a u32 are fine
+    for (overrides, status) in [
+        (json!([{ "WorkflowStepID": 1, "TimePerPage": u32::MAX / 20 }]), StatusCode::CREATED),
+        (json!([{ "WorkflowStepID": 1, "TimePerPage": u32::MAX / 10 + 1 }]), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!([{ "WorkflowStepID": 1, "SetupTime": u32::MAX }]), StatusCode::UNPROCESSABLE_ENTITY),
+        (json!([{ "NodeIndex": 6, "Parameters": { "MachineCount": u32::MAX } }]), StatusCode::CREATED),
+    ] {
+        let response = client
+            .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+            .json(&json!({ "PrintJobID": print_job_id, "WorkflowID": workflow_id, "Overrides": overrides }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status.as_u16(), "{overrides}");
+    }
+
+    // So is work which no longer fits once every copy is printed
+    let response = client
+        .post(&format!("http://{}:{}/PrintJob", HOST, PORT))
+        .json(&json!({
+            "Title": "Too Many Copies",
+            "PageCount": 10,
+            "Copies": u32::MAX,
+            "RasterizationProfileID": rasterization_profile_id
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let copies_print_job_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({ "PrintJobID": copies_print_job_id, "WorkflowID": workflow_id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_report_analysis(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let sim_report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Analysis", HOST, PORT, sim_report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let analysis: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    // Every step of a linear workflow is on the critical path, and Preflight takes the longest
+    assert_eq!(analysis["Bottleneck"], 1);
+    assert!(analysis["Steps"].as_array().unwrap().iter().all(|step| step["OnCriticalPath"] == true));
+
+    // Splitting Preflight's 10 pages between two machines saves the most time
+    let best = &analysis["Recommendations"][0];
+    assert_eq!(best["Kind"], "AddMachine");
+    assert_eq!(best["NodeIndex"], 1);
+    assert_eq!(best["EstimatedTimeSaved"], 100);
+
+    // A second Loader lane is set up alongside the first, so it only saves half of the sheets' time
+    let loader = analysis["Recommendations"].as_array().unwrap().iter().find(|r| r["NodeIndex"] == 6).unwrap();
+    assert_eq!(loader["Kind"], "AddMachine");
+    assert_eq!(loader["EstimatedTimeSaved"], 5);
+
+    // Doubling 8 cores would go past Rasterization's maximum, so that change is left out
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&json!({
+            "Title": "Test Eight Core Workflow",
+            "WorkflowSteps": [
+                { "WorkflowStepID": 0 },
+                { "WorkflowStepID": 1 },
+                { "WorkflowStepID": 2 },
+                { "WorkflowStepID": 3 },
+                { "WorkflowStepID": 4 },
+                { "WorkflowStepID": 5, "NumCores": 8 },
+                { "WorkflowStepID": 6 }
+            ]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+    let sim_report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Analysis", HOST, PORT, sim_report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let analysis: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert!(!analysis["Recommendations"].as_array().unwrap().iter().any(|r| r["Kind"] == "AddRasterizationCores"));
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Analysis", HOST, PORT, 999999))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_report_compare(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let report_a = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({
+            "PrintJobID": print_job_id,
+            "WorkflowID": workflow_id,
+            "Overrides": [{ "WorkflowStepID": 1, "TimePerPage": 10 }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    let report_b = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Compare/{}", HOST, PORT, report_a, report_b))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let comparison: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    assert_eq!(comparison["SamePrintJob"], true);
+    assert_eq!(comparison["SameWorkflow"], true);
+    assert_eq!(comparison["SameRasterizationProfile"], true);
+    assert_eq!(comparison["SameOverrides"], false);
+    assert_eq!(comparison["TotalTime"]["Delta"], -100);
+
+    // Preflight went from 210 to 110, everything else stayed the same
+    let preflight = comparison["Steps"].as_array().unwrap().iter()
+        .find(|step| step["WorkflowStepID"] == 1).unwrap();
+    assert_eq!(preflight["Time"]["A"], 210);
+    assert_eq!(preflight["Time"]["B"], 110);
+    assert!((preflight["Time"]["PercentDelta"].as_f64().unwrap() + 47.6).abs() < 0.1);
+    assert_eq!(comparison["Nodes"].as_array().unwrap().len(), 7);
+    assert_eq!(comparison["Nodes"][2]["Time"]["Delta"], 0);
+
+    // Print jobs and workflows are compared as they were simulated: another print job
+    // just like the first is the same, while the workflow changes with its steps
+    let url = format!("http://{}:{}/WorkflowStep/Catalog", HOST, PORT);
+    let response = client.get(&url).send().await.unwrap();
+    let original: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let mut faster = original.clone();
+    faster[1]["time_per_page"] = json!(10);
+    std::env::set_var(ADMIN_TOKEN_ENV, ADMIN_TOKEN);
+    let faster_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&faster).send().await.unwrap().status();
+    let other_print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let report_c = test_post_simulation_report(other_print_job_id, workflow_id).await;
+    let restore_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&original).send().await.unwrap().status();
+    assert_eq!(faster_status, StatusCode::OK.as_u16());
+    assert_eq!(restore_status, StatusCode::OK.as_u16());
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Compare/{}", HOST, PORT, report_a, report_c))
+        .send()
+        .await
+        .unwrap();
+    let comparison: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(comparison["SamePrintJob"], true);
+    assert_eq!(comparison["SameWorkflow"], false);
+    assert_eq!(comparison["SameOverrides"], true);
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Compare/{}", HOST, PORT, report_a, 999999))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_report_snapshot(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let other_report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+
+    // The report outlives the print job and workflow it simulated
+    test_delete_print_job(print_job_id).await;
+    test_delete_workflow(workflow_id).await;
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(report["TotalTimeTaken"], 642);
+    assert_eq!(report["PrintJobSnapshot"]["Title"], "Test Print Job");
+    assert_eq!(report["PrintJobSnapshot"]["PageCount"], 10);
+    assert_eq!(report["WorkflowSnapshot"]["Title"], "Test Workflow");
+    assert_eq!(report["WorkflowSnapshot"]["Steps"].as_array().unwrap().len(), 7);
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let reports: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let listed = reports.as_array().unwrap().iter().find(|r| r["id"] == report_id).unwrap();
+    assert_eq!(listed["PrintJobTitle"], "Test Print Job");
+    assert_eq!(listed["WorkflowTitle"], "Test Workflow");
+
+    // Analysis and comparison work from the snapshots
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Analysis", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let analysis: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(analysis["TotalTimeTaken"], 642);
+
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Compare/{}", HOST, PORT, report_id, other_report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let comparison: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(comparison["SameRasterizationProfile"], true);
+    assert_eq!(comparison["TotalTime"]["Delta"], 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_simulation_report_rerun(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+
+    // Re-run against the current print job and workflow, without a body
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["RerunOf"], report_id);
+    assert_eq!(summary["UsedSnapshot"], false);
+    assert_eq!(summary["Comparison"]["TotalTime"]["B"], 642);
+    assert_eq!(summary["Comparison"]["TotalTime"]["Delta"], 0);
+
+    let rerun_id = summary["id"].as_u64().unwrap() as DocID;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, rerun_id))
+        .send()
+        .await
+        .unwrap();
+    let rerun: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(rerun["RerunOf"], report_id);
+
+    // Once the sources are gone the snapshot is re-run instead
+    test_delete_print_job(print_job_id).await;
+    test_delete_workflow(workflow_id).await;
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, report_id))
+        .json(&json!({ "UseSnapshot": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["UsedSnapshot"], true);
+    assert_eq!(summary["Comparison"]["TotalTime"]["Delta"], 0);
+
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, report_id))
+        .json(&json!({ "UseSnapshot": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["UsedSnapshot"], true);
+    assert_eq!(summary["Comparison"]["SameWorkflow"], true);
+    assert_eq!(summary["Comparison"]["TotalTime"]["Delta"], 0);
+
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, 999999))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workflow_step_catalog(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{}:{}/WorkflowStep/Catalog", HOST, PORT);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let original: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(original.as_array().unwrap().len(), 10);
+
+    // Changing the catalog is an admin route
+    std::env::set_var(ADMIN_TOKEN_ENV, ADMIN_TOKEN);
+    let response = client.put(&url).json(&original).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16());
+    let response = client.post(&format!("{}/Reload", url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16());
+
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+
+    // Speed up Preflight without recompiling
+    let mut faster = original.clone();
+    faster[1]["title"] = json!("Fast Preflight");
+    faster[1]["time_per_page"] = json!(10);
+    let faster_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&faster).send().await.unwrap().status();
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    // Loader is used by the workflow, so giving it a new ID retires the old one instead
+    let mut renumbered_loader = original.clone();
+    for entry in renumbered_loader.as_array_mut().unwrap() {
+        if entry["id"] == 6 {
+            entry["id"] = json!(16);
+        }
+        for key in ["valid_prev", "valid_next"] {
+            for id in entry[key].as_array_mut().unwrap() {
+                if *id == 6 {
+                    *id = json!(16);
+                }
+            }
+        }
+    }
+    let in_use_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&renumbered_loader).send().await.unwrap().status();
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep/6", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let retired: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let workflow_status = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap()
+        .status();
+
+    let mut unknown_variant = original.clone();
+    unknown_variant[0]["variant"] = json!("Teleport");
+    let invalid_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&unknown_variant).send().await.unwrap().status();
+
+    // Every built-in variant must have an entry
+    let without_loader: Vec<serde_json::Value> = original.as_array().unwrap().iter()
+        .filter(|entry| entry["variant"] != "Loader")
+        .cloned()
+        .collect();
+    let missing_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&without_loader).send().await.unwrap().status();
+
+    // Restore the original catalog before checking the results
+    let restore_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&original).send().await.unwrap().status();
+    let reload_status = client
+        .post(&format!("{}/Reload", url))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .status();
+
+    assert_eq!(faster_status, StatusCode::OK.as_u16());
+    assert_eq!(report["TotalTimeTaken"], 542);
+    assert_eq!(in_use_status, StatusCode::OK.as_u16());
+    assert_eq!(retired["Deprecated"], true);
+    assert_eq!(workflow_status, StatusCode::OK.as_u16());
+    assert_eq!(invalid_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert_eq!(missing_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert_eq!(restore_status, StatusCode::OK.as_u16());
+    assert_eq!(reload_status, StatusCode::OK.as_u16());
+
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep/1", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let preflight: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(preflight["TimePerPage"], 20);
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep/6", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let loader: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(loader["Deprecated"], false);
+
+    // The report's snapshot keeps the step as it was when the report was made
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}/Analysis", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let analysis: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(analysis["Steps"][1]["Title"], "Fast Preflight");
+    assert_eq!(analysis["TotalTimeTaken"], 542);
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, report_id))
+        .json(&json!({ "UseSnapshot": true }))
+        .send()
+        .await
+        .unwrap();
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["Comparison"]["TotalTime"]["Delta"], 0);
+    assert_eq!(summary["Comparison"]["Nodes"][1]["Title"], "Fast Preflight");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workflow_step_deprecation(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let url = format!("http://{}:{}/WorkflowStep/Catalog", HOST, PORT);
+    std::env::set_var(ADMIN_TOKEN_ENV, ADMIN_TOKEN);
+    let response = client.get(&url).send().await.unwrap();
+    let original: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    let payload = json!({
+        "Title": "Test Laminated Workflow",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0 },
+            { "WorkflowStepID": 1 },
+            { "WorkflowStepID": 2 },
+            { "WorkflowStepID": 3 },
+            { "WorkflowStepID": 4 },
+            { "WorkflowStepID": 5 },
+            { "WorkflowStepID": 6 },
+            { "WorkflowStepID": 8, "Parameters": { "FilmType": "Matte" } }
+        ]
+    });
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    // Laminating is retired in favour of Cutting
+    let mut deprecated = original.clone();
+    deprecated[8]["deprecated"] = json!(true);
+    let deprecate_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&deprecated).send().await.unwrap().status();
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let steps: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let new_workflow_status = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap()
+        .status();
+
+    // Migrating rewrites every workflow using the step, so it's an admin route
+    let unauthorized_status = client
+        .post(&format!("http://{}:{}/WorkflowStep/8/Migrate", HOST, PORT))
+        .json(&json!({ "ReplacementID": 7 }))
+        .send()
+        .await
+        .unwrap()
+        .status();
+    let migrate = |id: u32, replacement_id: u32| client
+        .post(&format!("http://{}:{}/WorkflowStep/{}/Migrate", HOST, PORT, id))
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&json!({ "ReplacementID": replacement_id }))
+        .send();
+    let not_deprecated_status = migrate(7, 6).await.unwrap().status();
+    let to_deprecated_status = migrate(7, 8).await.unwrap().status();
+    // Preflight can't come after Loader, so the workflow would no longer be valid
+    let response = migrate(8, 1).await.unwrap();
+    let invalid_workflow_status = response.status();
+    let invalid_workflow_message = response.text().await.unwrap();
+    let response = migrate(8, 7).await.unwrap();
+    let migrate_status = response.status();
+    let migration: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+
+    let restore_status = client.put(&url).bearer_auth(ADMIN_TOKEN).json(&original).send().await.unwrap().status();
+
+    assert_eq!(deprecate_status, StatusCode::OK.as_u16());
+    assert!(!steps.as_array().unwrap().iter().any(|s| s["id"] == 8));
+    assert_eq!(new_workflow_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert_eq!(unauthorized_status, StatusCode::UNAUTHORIZED.as_u16());
+    assert_eq!(not_deprecated_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert_eq!(to_deprecated_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert_eq!(invalid_workflow_status, StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    assert!(invalid_workflow_message.contains(&format!("Test Laminated Workflow ({workflow_id})")));
+    assert_eq!(migrate_status, StatusCode::OK.as_u16());
+    assert_eq!(migration["Migrated"], 1);
+    assert_eq!(migration["RemovedParameters"], 1);
+    assert_eq!(restore_status, StatusCode::OK.as_u16());
+
+    // Cutting doesn't have a film type, so it starts from its own defaults
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][7]["data"]["id"], 7);
+    assert_eq!(workflow["Steps"][7]["parameters"], json!({ "BladeCount": 1 }));
+
+    test_delete_workflow(workflow_id).await;
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_custom_workflow_step(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .json(&json!({
+            "Title": "Binding",
+            "SetupTime": 20,
+            "TimePerPage": 3,
+            "Parallelism": "Lanes",
+            "WorkUnit": "Sheet",
+            "ValidPrev": [6],
+            "NoNextValid": true,
+            "Parameters": [{ "name": "Glue", "type": "Text", "default": "PUR" }]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let step: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(step["Title"], "Binding");
+    let binding_id = step["id"].as_u64().unwrap();
+    assert!(binding_id >= 1000);
+
+    // Steps must connect to existing steps
+    let response = client
+        .post(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .json(&json!({ "Title": "Folding", "SetupTime": 5, "TimePerPage": 1, "ValidPrev": [12345] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let steps: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert!(steps.as_array().unwrap().iter().any(|s| s["id"] == binding_id));
+
+    // Steps created concurrently each get their own ID and stay in the catalog
+    let requests = (0..10).map(|i| client
+        .post(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .json(&json!({ "Title": format!("Stamping {i}"), "SetupTime": 1, "TimePerPage": 1, "ValidPrev": [6] }))
+        .send());
+    let mut stamping_ids = vec![];
+    for response in futures::future::join_all(requests).await {
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+        let step: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+        stamping_ids.push(step["id"].as_u64().unwrap());
+    }
+    stamping_ids.sort();
+    stamping_ids.dedup();
+    assert_eq!(stamping_ids.len(), 10);
+    let response = client
+        .get(&format!("http://{}:{}/WorkflowStep", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let steps: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert!(stamping_ids.iter().all(|id| steps.as_array().unwrap().iter().any(|s| s["id"] == *id)));
+
+    // Parameters must be declared by the step and of the declared type
+    let mut workflow_steps = vec![
+        json!({ "WorkflowStepID": 0 }),
+        json!({ "WorkflowStepID": 1 }),
+        json!({ "WorkflowStepID": 2 }),
+        json!({ "WorkflowStepID": 3 }),
+        json!({ "WorkflowStepID": 4 }),
+        json!({ "WorkflowStepID": 5, "NumCores": 1 }),
+        json!({ "WorkflowStepID": 6 }),
+        json!({ "WorkflowStepID": binding_id, "Parameters": { "Glue": 5 } }),
+    ];
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&json!({ "Title": "Bound Workflow", "WorkflowSteps": workflow_steps }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    workflow_steps[7] = json!({ "WorkflowStepID": binding_id, "Parameters": { "Glue": "EVA" } });
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&json!({ "Title": "Bound Workflow", "WorkflowSteps": workflow_steps }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][7]["parameters"]["Glue"], "EVA");
+    assert_eq!(workflow["Steps"][6]["next"], json!([7]));
+
+    // Binding 10 sheets after the Loader takes 20 + 10 * 3
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let report: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(report["TotalTimeTaken"], 642 + 50);
+    assert_eq!(report["StepTimes"][binding_id.to_string()], 50);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_step_parameters(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let mut payload = json!({
+        "Title": "Laminated Workflow",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0 },
+            { "WorkflowStepID": 1 },
+            { "WorkflowStepID": 2, "NUp": 0 },
+            { "WorkflowStepID": 3 },
+            { "WorkflowStepID": 4 },
+            { "WorkflowStepID": 5 },
+            { "WorkflowStepID": 6 },
+            { "WorkflowStepID": 8, "Parameters": { "FilmType": "Velvet" } }
+        ]
+    });
+
+    // Impose needs at least one page per sheet
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    // Enum parameters only take one of their options
+    payload["WorkflowSteps"][2] = json!({ "WorkflowStepID": 2 });
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+
+    payload["WorkflowSteps"][7]["Parameters"]["FilmType"] = json!("Matte");
+    let response = client
+        .post(&format!("http://{}:{}/Workflow", HOST, PORT))
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED.as_u16());
+    let workflow_id = response.text().await.unwrap().parse::<DocID>().unwrap();
+
+    // Parameters which weren't given take their defaults
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    let workflow: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(workflow["Steps"][7]["parameters"]["FilmType"], "Matte");
+    assert_eq!(workflow["Steps"][2]["parameters"]["NUp"], 1);
+    assert_eq!(workflow["Steps"][2]["parameters"]["SheetSize"], "Letter");
+    assert_eq!(workflow["Steps"][5]["parameters"]["NumCores"], 1);
+
+    test_delete_workflow(workflow_id).await;
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_analytics(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+    test_post_simulation_report(print_job_id, workflow_id).await;
+    client
+        .post(&format!("http://{}:{}/SimulationReport", HOST, PORT))
+        .json(&json!({
+            "PrintJobID": print_job_id,
+            "WorkflowID": workflow_id,
+            "Overrides": [{ "WorkflowStepID": 1, "TimePerPage": 10 }]
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .get(&format!("http://{}:{}/Analytics/Workflow", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let stats: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let stats = stats.as_array().unwrap().iter().find(|s| s["id"] == workflow_id).unwrap();
+    assert_eq!(stats["Count"], 2);
+    assert_eq!(stats["Min"], 542);
+    assert_eq!(stats["Max"], 642);
+    assert_eq!(stats["Average"], 592.0);
+    assert_eq!(stats["P50"], 542);
+    assert_eq!(stats["P90"], 642);
+
+    // Nothing was simulated before 1970
+    let response = client
+        .get(&format!("http://{}:{}/Analytics/WorkflowStep?From=0&To=1", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "[]");
+
+    let response = client
+        .get(&format!("http://{}:{}/Analytics/TimePerPage?Interval=3600", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let trend: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert!(!trend.as_array().unwrap().is_empty());
+
+    let response = client
+        .get(&format!("http://{}:{}/Analytics/Usage?Limit=1000", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    let usage: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert!(usage["Workflows"].as_array().unwrap().iter().any(|w| w["id"] == workflow_id && w["Count"] == 2));
+    assert!(usage["RasterizationProfiles"].as_array().unwrap().iter().any(|p| p["id"] == rasterization_profile_id));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_delete_dependents(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+
+    // A profile which is still used by a print job can't be deleted on its own
+    let response = client
+        .delete(&format!("http://{}:{}/RasterizationProfile/{}", HOST, PORT, rasterization_profile_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT.as_u16());
+    let conflict: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(conflict["Dependents"], json!([{ "Type": "PrintJob", "id": print_job_id }]));
+
+    // A preview lists the dependents first, without deleting anything
+    let response = client
+        .delete(&format!("http://{}:{}/RasterizationProfile/{}?cascade=true&preview=true", HOST, PORT, rasterization_profile_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let removed: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(removed, json!([
+        { "Type": "PrintJob", "id": print_job_id },
+        { "Type": "RasterizationProfile", "id": rasterization_profile_id },
+    ]));
+    test_get_print_job_by_id(print_job_id).await;
+
+    let response = client
+        .delete(&format!("http://{}:{}/RasterizationProfile/{}?cascade=true", HOST, PORT, rasterization_profile_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT.as_u16());
+    let response = client
+        .get(&format!("http://{}:{}/PrintJob/{}", HOST, PORT, print_job_id))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.status().is_success());
+
+    // Deleting it again finds nothing
+    let response = client
+        .delete(&format!("http://{}:{}/RasterizationProfile/{}", HOST, PORT, rasterization_profile_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    // A report is needed by its re-runs
+    let print_job_id = test_post_print_job(query_rasterization_profiles().await.unwrap()[0].id).await;
+    let workflow_id = test_post_workflow().await;
+    let report_id = test_post_simulation_report(print_job_id, workflow_id).await;
+    let response = client
+        .post(&format!("http://{}:{}/SimulationReport/{}/Rerun", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    let rerun_id = summary["id"].as_u64().unwrap() as DocID;
+
+    let response = client
+        .delete(&format!("http://{}:{}/SimulationReport/{}", HOST, PORT, report_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT.as_u16());
+    test_delete_simulation_report(rerun_id).await;
+    test_delete_simulation_report(report_id).await;
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_trash(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let rasterization_profile_id = test_get_rasterization_profile().await;
+    let print_job_id = test_post_print_job(rasterization_profile_id).await;
+    let workflow_id = test_post_workflow().await;
+
+    // Deleted items are hidden, and listed in the trash
+    test_delete_workflow(workflow_id).await;
+    let response = client
+        .get(&format!("http://{}:{}/Workflow/{}", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    assert!(!response.status().is_success());
+    let response = client
+        .get(&format!("http://{}:{}/Trash", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let trash: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(trash[0]["Type"], "Workflow");
+    assert_eq!(trash[0]["id"], workflow_id);
+    assert_eq!(trash[0]["Title"], "Test Workflow");
+
+    let response = client
+        .post(&format!("http://{}:{}/Workflow/{}/Restore", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    test_get_workflow_by_id(workflow_id).await;
+
+    // Only items in the trash can be restored
+    let response = client
+        .post(&format!("http://{}:{}/Workflow/{}/Restore", HOST, PORT, workflow_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND.as_u16());
+
+    // A print job is restored along with its profile
+    let response = client
+        .delete(&format!("http://{}:{}/RasterizationProfile/{}?cascade=true", HOST, PORT, rasterization_profile_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT.as_u16());
+    // A profile in the trash can't be given to a new print job
+    let response = client
+        .post(&format!("http://{}:{}/PrintJob", HOST, PORT))
+        .json(&json!({ "Title": "Trashed Profile", "PageCount": 10, "RasterizationProfileID": rasterization_profile_id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    let response = client
+        .post(&format!("http://{}:{}/PrintJob/{}/Restore", HOST, PORT, print_job_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let restored: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(restored, json!([
+        { "Type": "RasterizationProfile", "id": rasterization_profile_id },
+        { "Type": "PrintJob", "id": print_job_id },
+    ]));
+    test_get_print_job_by_id(print_job_id).await;
+    test_get_rasterization_profile().await;
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_audit_log(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    let client = reqwest::Client::new();
+    let workflow_id = test_post_workflow().await;
+    test_delete_workflow(workflow_id).await;
+
+    // The log holds every item and user email, so it's an admin route
+    std::env::set_var(ADMIN_TOKEN_ENV, ADMIN_TOKEN);
+    let response = client
+        .get(&format!("http://{}:{}/Audit", HOST, PORT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16());
+
+    let response = client
+        .get(&format!("http://{}:{}/Audit?Entity=Workflow&EntityID={}", HOST, PORT, workflow_id))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let log: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(log.as_array().unwrap().len(), 2);
+    assert_eq!(log[0]["Action"], "Delete");
+    assert_eq!(log[0]["Before"]["Title"], "Test Workflow");
+    assert_eq!(log[0]["After"], serde_json::Value::Null);
+    assert_eq!(log[1]["Action"], "Insert");
+    assert_eq!(log[1]["After"]["Steps"].as_array().unwrap().len(), 7);
+
+    let response = client
+        .get(&format!("http://{}:{}/Audit?Action=Delete&Limit=1", HOST, PORT))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    let log: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(log.as_array().unwrap().len(), 1);
+    assert_eq!(log[0]["EntityID"], workflow_id);
+
+    let response = client
+        .get(&format!("http://{}:{}/Audit?Entity=Unknown", HOST, PORT))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST.as_u16());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_backup_restore(){
+    open_database(DatabaseLocation::InMemory).unwrap();
+    seed_database().await.unwrap();
+    let server = tokio::spawn(async {
+        backend::run_server(HOST, PORT).await;
+    });
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+
+    // Admin routes are disabled until a token is configured, then require it
+    let client = reqwest::Client::new();
+    let backup_url = format!("http://{}:{}/Admin/Backup", HOST, PORT);
+    let restore_url = format!("http://{}:{}/Admin/Restore", HOST, PORT);
+    std::env::remove_var(ADMIN_TOKEN_ENV);
+    let response = client.get(&backup_url).bearer_auth(ADMIN_TOKEN).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN.as_u16());
+    std::env::set_var(ADMIN_TOKEN_ENV, ADMIN_TOKEN);
+    let response = client.get(&backup_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16());
+    let response = client.post(&restore_url).bearer_auth("wrong").json(&json!({})).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED.as_u16());
+
+    let kept_id = test_post_print_job(test_get_rasterization_profile().await).await;
+    let response = client.get(&backup_url).bearer_auth(ADMIN_TOKEN).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let backup: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(backup["SchemaVersion"], latest_version());
+    assert_eq!(backup["Tables"]["rasterization_profile"].as_array().unwrap().len(), 5);
+
+    // Replacing the data removes what was added since the backup
+    let added_id = test_post_print_job(test_get_rasterization_profile().await).await;
+    let response = client
+        .post(&format!("{restore_url}?Mode=Replace"))
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&backup)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["Mode"], "Replace");
+    assert_eq!(summary["Rows"]["printjob"], 1);
+    let print_jobs = query_print_jobs().await.unwrap();
+    assert!(print_jobs.iter().any(|job| job.id == Some(kept_id)));
+    assert!(!print_jobs.iter().any(|job| job.id == Some(added_id)));
+
+    // Merging keeps what's there
+    let response = client
+        .post(&format!("{restore_url}?Mode=Merge"))
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&backup)
+        .send()
+        .await
+        .unwrap();
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["Rows"]["printjob"], 0);
+
+    // Archives of another format or schema, or missing tables, are rejected
+    let mut newer = backup.clone();
+    newer["SchemaVersion"] = json!(latest_version() + 1);
+    let mut empty = backup.clone();
+    empty["Tables"] = json!({});
+    for archive in [newer, empty, json!({ "Tables": {} })] {
+        let response = client
+            .post(&restore_url)
+            .bearer_auth(ADMIN_TOKEN)
+            .json(&archive)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY.as_u16());
+    }
+    assert_eq!(query_print_jobs().await.unwrap().len(), 1);
+
+    // Archives larger than axum's default limit of 2 MB can be restored
+    let mut large = backup.clone();
+    let audit_log = large["Tables"]["audit_log"].as_array_mut().unwrap();
+    let template = audit_log[0].clone();
+    let first_id = audit_log.iter().filter_map(|row| row["id"].as_u64()).max().unwrap() + 1;
+    for id in first_id..first_id + 3000 {
+        let mut row = template.clone();
+        row["id"] = json!(id);
+        row["after_json"] = json!("x".repeat(1000));
+        audit_log.push(row);
+    }
+    let body = large.to_string();
+    assert!(body.len() > 2 * 1024 * 1024);
+    let response = client
+        .post(&restore_url)
+        .bearer_auth(ADMIN_TOKEN)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK.as_u16());
+    let summary: serde_json::Value = from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(summary["Rows"]["audit_log"], large["Tables"]["audit_log"].as_array().unwrap().len());
+
+    std::env::remove_var(ADMIN_TOKEN_ENV);
+    server.abort();
+}
+
 async fn test_get_rasterization_profile() -> DocID {
     let client = reqwest::Client::new();
     let response = client
@@ -147,7 +2060,7 @@ async fn test_get_rasterization_profile() -> DocID {
 
     assert!(
         list.len() == 5,
-        "Expected exactly five rasterization profiles from dummy-data.sql"
+        "Expected exactly five rasterization profiles from the sample data"
     );
 
     return list[0].id;
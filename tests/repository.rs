@@ -0,0 +1,391 @@
+use backend::database::*;
+use backend::analysis::rerun_simulation_report;
+use backend::backup::*;
+use backend::migrations::*;
+use backend::repository::{open_repository, Repository};
+use backend::workflow::{Workflow, WorkflowArgs};
+use serde_json::json;
+use serial_test::serial;
+use std::sync::Arc;
+
+/// Environment variable holding the URL of a PostgreSQL database the conformance
+/// tests may wipe. The PostgreSQL tests are skipped when it isn't set
+const TEST_POSTGRES_ENV: &str = "PRINTOS_TEST_POSTGRES";
+
+fn print_job(title: &str) -> PrintJob {
+    return PrintJob {
+        id: None,
+        DateCreated: Some(1700000000),
+        Title: title.to_string(),
+        PageCount: 12,
+        RasterizationProfileID: 1,
+        PageSize: MediaSize::A4,
+        Duplex: true,
+        Copies: 3,
+    };
+}
+
+/// Checks that a repository stores and loads every kind of data the same way.
+/// Every backend must pass this with a freshly created database
+async fn check_repository(repository: Arc<dyn Repository>) {
+    set_repository(repository.clone());
+    setup_database().await.unwrap();
+
+    // Migrations
+    let status = repository.migration_status().await.unwrap();
+    assert_eq!(status.CurrentVersion, latest_version());
+    assert!(status.Pending.is_empty());
+    repository.migrate().await.unwrap();
+
+    // The migrations only build the schema, rasterization profiles come from the sample data
+    assert!(query_rasterization_profiles().await.unwrap().is_empty());
+    assert!(seed_database().await.unwrap());
+    assert!(!seed_database().await.unwrap());
+    let profiles = query_rasterization_profiles().await.unwrap();
+    assert_eq!(profiles.len(), 5);
+    assert_eq!(find_rasterization_profile(profiles[0].id).await.unwrap().title, "BW");
+
+    // Print jobs
+    let print_job_id = insert_print_job(print_job("Conformance")).await.unwrap();
+    let found = find_print_job(print_job_id).await.unwrap();
+    assert_eq!(found.id, Some(print_job_id));
+    assert_eq!(found.Title, "Conformance");
+    assert_eq!(found.DateCreated, Some(1700000000));
+    assert_eq!(found.PageCount, 12);
+    assert_eq!(found.PageSize, MediaSize::A4);
+    assert!(found.Duplex);
+    assert_eq!(found.Copies, 3);
+    assert!(query_print_jobs().await.unwrap().iter().any(|job| job.id == Some(print_job_id)));
+
+    let removed_id = insert_print_job(print_job("Removed")).await.unwrap();
+    assert_eq!(remove_print_job(removed_id).await.unwrap(), 1);
+    assert!(matches!(find_print_job(removed_id).await, Err(CustomError::NotFound)));
+    // Missing items are reported the same way by every backend
+    assert!(matches!(find_workflow(removed_id).await, Err(CustomError::NotFound)));
+    assert!(matches!(find_simulation_report(removed_id).await, Err(CustomError::NotFound)));
+    assert!(matches!(delete_item(StoredItem::new(ItemType::PrintJob, removed_id), DeleteOptions::default()).await,
+        Err(CustomError::NotFound)));
+
+    // Workflows
+    let args: WorkflowArgs = serde_json::from_value(json!({
+        "Title": "Conformance",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0, "SetupTime": 5 },
+            { "WorkflowStepID": 1, "MachineCount": 2 },
+        ],
+    })).unwrap();
+    let workflow_id = insert_workflow(args).await.unwrap();
+    let workflow = find_workflow(workflow_id).await.unwrap();
+    assert_eq!(workflow.Title, "Conformance");
+    assert_eq!(workflow.Steps.len(), 2);
+    assert_eq!(workflow.Steps[0].setup_time, Some(5));
+    assert_eq!(workflow.Steps[0].next, vec![1]);
+    assert_eq!(workflow.Steps[1].prev, vec![0]);
+    assert_eq!(workflow.Steps[1].machine_count, 2);
+    assert!(query_workflows().await.unwrap().iter().any(|workflow| workflow.id == Some(workflow_id)));
+    // Listed workflows are assembled just like a single one
+    let listed = repository.query_workflows().await.unwrap();
+    assert_eq!(json!(listed.iter().find(|workflow| workflow.id == Some(workflow_id)).unwrap()), json!(workflow));
+
+    // Workflow steps are copied from the catalog
+    assert!(get_workflow_step_ids().await.unwrap().contains(&0));
+    assert!(find_workflow_step_definition(0).await.unwrap().is_some());
+    assert!(find_next_workflow_step_id().await.unwrap() > 0);
+    assert!(find_workflow_step_params(1).await.unwrap().iter().any(|(_, name, _)| name == "MachineCount"));
+
+    // Simulation reports
+    let report_id = insert_simulation_report(print_job_id, workflow_id, vec![]).await.unwrap();
+    let report = find_simulation_report(report_id).await.unwrap();
+    assert_eq!(report.PrintJobID, print_job_id);
+    assert_eq!(report.WorkflowID, workflow_id);
+    assert!(report.TotalTimeTaken > 0);
+    assert_eq!(report.StepTimes.len(), 2);
+    assert_eq!(report.NodeTimes.len(), 2);
+    assert_eq!(report.PrintJobSnapshot.unwrap().Title, "Conformance");
+    let detailed = query_simulation_reports().await.unwrap();
+    let detailed = detailed.iter().find(|report| report.id == Some(report_id)).unwrap();
+    assert_eq!(detailed.PrintJobTitle, "Conformance");
+    assert_eq!(detailed.WorkflowTitle, "Conformance");
+    assert_eq!(detailed.RasterizationProfile, "BW");
+    assert_eq!(count_workflow_step_uses(0).await.unwrap(), 3);
+
+    // Dependents
+    let rerun_id = rerun_simulation_report(report_id, false).await.unwrap().id;
+    assert_eq!(repository.find_simulation_report_reruns(report_id, false).await.unwrap(), vec![rerun_id]);
+    assert!(repository.find_rasterization_profile_print_jobs(profiles[0].id, false).await.unwrap().contains(&print_job_id));
+    assert!(matches!(delete_item(StoredItem::new(ItemType::SimulationReport, report_id), DeleteOptions::default()).await,
+        Err(CustomError::HasDependents(_, dependents)) if dependents.len() == 1));
+    assert_eq!(remove_simulation_report(rerun_id).await.unwrap(), 1);
+
+    // Analytics
+    let filter = AnalyticsFilter::default();
+    let statistics = query_workflow_time_statistics(&filter).await.unwrap();
+    let statistics = statistics.iter().find(|statistics| statistics.id == workflow_id).unwrap();
+    assert_eq!(statistics.Title, "Conformance");
+    assert_eq!(statistics.Count, 1);
+    assert_eq!(statistics.Min, report.TotalTimeTaken);
+    assert_eq!(statistics.P95, report.TotalTimeTaken);
+    assert_eq!(statistics.Average, report.TotalTimeTaken as f64);
+    assert_eq!(query_workflow_step_time_statistics(&filter).await.unwrap().len(), 2);
+    let trend = query_time_per_page_trend(&filter).await.unwrap();
+    assert_eq!(trend.len(), 1);
+    assert_eq!(trend[0].AverageTimePerPage, report.TotalTimeTaken as f64 / 36.0);
+    let usage = query_usage(&filter).await.unwrap();
+    assert_eq!(usage.Workflows[0].id, workflow_id);
+    assert_eq!(usage.Workflows[0].Count, 1);
+    assert_eq!(usage.RasterizationProfiles[0].Title, "BW");
+    // Reports whose print job was removed are still counted through their snapshot
+    let removed_job_id = insert_print_job(print_job("Removed")).await.unwrap();
+    let removed_report_id = insert_simulation_report(removed_job_id, workflow_id, vec![]).await.unwrap();
+    assert_eq!(remove_print_job(removed_job_id).await.unwrap(), 1);
+    let trend = query_time_per_page_trend(&filter).await.unwrap();
+    assert_eq!(trend[0].Count, 2);
+    assert_eq!(trend[0].AverageTimePerPage, report.TotalTimeTaken as f64 / 36.0);
+    let usage = query_usage(&filter).await.unwrap();
+    assert_eq!(usage.RasterizationProfiles[0].Count, 2);
+    assert_eq!(usage.RasterizationProfiles[0].Title, "BW");
+    assert_eq!(remove_simulation_report(removed_report_id).await.unwrap(), 1);
+
+    // Reports outlive what they simulated, and are removed along with their times
+    assert_eq!(remove_workflow(workflow_id).await.unwrap(), 1);
+    assert!(find_workflow(workflow_id).await.is_err());
+    assert!(!query_workflows().await.unwrap().iter().any(|workflow| workflow.id == Some(workflow_id)));
+    assert_eq!(find_simulation_report(report_id).await.unwrap().WorkflowSnapshot.unwrap().Title, "Conformance");
+    assert_eq!(remove_simulation_report(report_id).await.unwrap(), 1);
+    assert!(find_simulation_report(report_id).await.is_err());
+    assert_eq!(count_workflow_step_uses(0).await.unwrap(), 0);
+
+    // Error log
+    let error_id = insert_error_detailed(ErrorDetailed {
+        id: None,
+        date: 1700000000,
+        status: 404,
+        domain: "localhost".to_string(),
+        request: "/Workflow/0".to_string(),
+        method: "GET".to_string(),
+        response: "Not found".to_string(),
+    }).await.unwrap();
+    let errors = query_errors_detailed().await.unwrap();
+    let error = errors.iter().find(|error| error.id == Some(error_id)).unwrap();
+    assert_eq!(error.status, 404);
+    assert_eq!(error.request, "/Workflow/0");
+
+    // Users have unique emails
+    insert_user("conformance@example.com".to_string(), "password".to_string()).await.unwrap();
+    assert!(insert_user("conformance@example.com".to_string(), "password".to_string()).await.is_err());
+
+    // A write of several rows leaves nothing behind when it fails partway
+    let partial: WorkflowArgs = serde_json::from_value(json!({
+        "Title": "Partial",
+        "WorkflowSteps": [{ "WorkflowStepID": 999999 }],
+    })).unwrap();
+    let workflow = Workflow { id: None, Title: "Partial".to_string(), Steps: vec![] };
+    assert!(repository.insert_workflow(partial, workflow).await.is_err());
+    assert!(!query_workflows().await.unwrap().iter().any(|workflow| workflow.Title == "Partial"));
+
+    // Units of work are committed when they succeed, and rolled back when they fail
+    let (first, second) = unit_of_work(|| async {
+        return Ok((insert_print_job(print_job("First")).await?, insert_print_job(print_job("Second")).await?));
+    }).await.unwrap();
+    assert_eq!(find_print_job(first).await.unwrap().Title, "First");
+    assert_eq!(find_print_job(second).await.unwrap().Title, "Second");
+
+    let failed: Result<(), CustomError> = unit_of_work(|| async {
+        insert_print_job(print_job("Rolled back")).await?;
+        // A nested unit of work is part of the outer one
+        unit_of_work(|| async { insert_print_job(print_job("Rolled back")).await }).await?;
+        return Err(CustomError::OtherError("failed".to_string()));
+    }).await;
+    assert!(failed.is_err());
+    assert!(!query_print_jobs().await.unwrap().iter().any(|job| job.Title == "Rolled back"));
+
+    // Deleted items are moved to the trash, and hidden until they're restored
+    let trashed_id = insert_print_job(print_job("Trashed")).await.unwrap();
+    let trashed = StoredItem::new(ItemType::PrintJob, trashed_id);
+    assert_eq!(delete_item(trashed.clone(), DeleteOptions::default()).await.unwrap(), vec![trashed.clone()]);
+    assert!(find_print_job(trashed_id).await.is_err());
+    assert!(!query_print_jobs().await.unwrap().iter().any(|job| job.id == Some(trashed_id)));
+    let trash = query_trash().await.unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].Type, ItemType::PrintJob);
+    assert_eq!(trash[0].Title, "Trashed");
+    assert_eq!(repository.find_dependencies(trashed.clone()).await.unwrap(),
+        vec![StoredItem::new(ItemType::RasterizationProfile, profiles[0].id)]);
+    assert_eq!(restore_item(trashed.clone()).await.unwrap(), vec![trashed.clone()]);
+    assert_eq!(find_print_job(trashed_id).await.unwrap().Title, "Trashed");
+    assert!(restore_item(trashed.clone()).await.is_err());
+
+    // Restoring an item restores what it needs, and purging removes what depends on it first
+    let profile = StoredItem::new(ItemType::RasterizationProfile, profiles[4].id);
+    let mut dependent = print_job("Purged");
+    dependent.RasterizationProfileID = profile.id;
+    let dependent = StoredItem::new(ItemType::PrintJob, insert_print_job(dependent).await.unwrap());
+    let cascade = DeleteOptions { cascade: true, preview: false };
+    assert_eq!(delete_item(profile.clone(), cascade).await.unwrap(), vec![dependent.clone(), profile.clone()]);
+    assert!(find_rasterization_profile(profile.id).await.is_err());
+    assert_eq!(restore_item(dependent.clone()).await.unwrap(), vec![profile.clone(), dependent.clone()]);
+    delete_item(profile.clone(), cascade).await.unwrap();
+    assert!(purge_trash(0).await.unwrap().is_empty());
+    assert_eq!(purge_trash(u32::MAX).await.unwrap(), vec![dependent.clone(), profile.clone()]);
+    assert!(query_trash().await.unwrap().is_empty());
+    assert!(restore_item(profile).await.is_err());
+
+    // Every change is recorded in the audit log, newest first
+    let log = query_audit_log(&AuditFilter {
+        Entity: Some(AuditEntity::PrintJob),
+        EntityID: Some(trashed_id),
+        ..Default::default()
+    }).await.unwrap();
+    let actions: Vec<AuditAction> = log.iter().map(|entry| entry.Action).collect();
+    assert_eq!(actions, vec![AuditAction::Restore, AuditAction::Delete, AuditAction::Insert]);
+    assert!(log[2].Before.is_none());
+    assert_eq!(log[2].After.as_ref().unwrap()["Title"], "Trashed");
+    assert_eq!(log[1].Before.as_ref().unwrap()["Title"], "Trashed");
+    assert!(log[1].After.is_none());
+    assert!(log.iter().all(|entry| entry.Actor.is_none() && entry.Time > 0));
+    let purged = query_audit_log(&AuditFilter { Action: Some(AuditAction::Purge), Limit: Some(1), ..Default::default() }).await.unwrap();
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].Entity, AuditEntity::RasterizationProfile);
+    assert_eq!(purged[0].Before.as_ref().unwrap()["Type"], "RasterizationProfile");
+    assert!(query_audit_log(&AuditFilter { From: Some(u32::MAX), ..Default::default() }).await.unwrap().is_empty());
+
+    // Changes which were rolled back aren't recorded
+    let print_jobs = AuditFilter { Entity: Some(AuditEntity::PrintJob), Limit: Some(1000), ..Default::default() };
+    assert!(!query_audit_log(&print_jobs).await.unwrap().iter()
+        .any(|entry| entry.After.as_ref().is_some_and(|after| after["Title"] == "Rolled back")));
+
+    // The workflow step catalog is only recorded when it changes
+    let steps = AuditFilter { Entity: Some(AuditEntity::WorkflowStep), Limit: Some(1000), ..Default::default() };
+    let recorded = query_audit_log(&steps).await.unwrap().len();
+    assert!(recorded > 0);
+    setup_database().await.unwrap();
+    assert_eq!(query_audit_log(&steps).await.unwrap().len(), recorded);
+
+    // Backups restore every row with its ID, and new IDs continue after the restored ones
+    let backed_up: WorkflowArgs = serde_json::from_value(json!({
+        "Title": "Backed up",
+        "WorkflowSteps": [
+            { "WorkflowStepID": 0 },
+            { "WorkflowStepID": 1, "MachineCount": 2 },
+        ],
+    })).unwrap();
+    let backed_up_id = insert_workflow(backed_up).await.unwrap();
+    let backup = create_backup().await.unwrap();
+    assert_eq!(backup.FormatVersion, BACKUP_FORMAT_VERSION);
+    assert_eq!(backup.SchemaVersion, latest_version());
+    let after_backup = insert_print_job(print_job("After backup")).await.unwrap();
+    let summary = restore_backup(backup.clone(), RestoreMode::Replace).await.unwrap();
+    assert_eq!(summary.Rows["printjob"], backup.Tables["printjob"].len());
+    assert_eq!(summary.Rows["workflow_step"], backup.Tables["workflow_step"].len());
+    assert!(find_print_job(after_backup).await.is_err());
+    let restored = find_print_job(print_job_id).await.unwrap();
+    assert_eq!(restored.Title, "Conformance");
+    assert!(restored.Duplex);
+    let workflow = find_workflow(backed_up_id).await.unwrap();
+    assert_eq!(workflow.Steps[1].prev, vec![0]);
+    assert_eq!(workflow.Steps[1].machine_count, 2);
+    let last_id = backup.Tables["printjob"].iter().filter_map(|row| row["id"].as_u64()).max().unwrap();
+    let after_restore = insert_print_job(print_job("After restore")).await.unwrap();
+    assert_eq!(after_restore as u64, last_id + 1);
+
+    // Merging only adds the rows which are missing
+    let summary = restore_backup(backup.clone(), RestoreMode::Merge).await.unwrap();
+    assert!(summary.Rows.values().all(|&rows| rows == 0));
+    assert_eq!(find_print_job(after_restore).await.unwrap().Title, "After restore");
+
+    // A merge whose rows would refer to another row under the same ID is rejected,
+    // while rows under new IDs are added
+    let mut overlapping = backup.clone();
+    let workflows = overlapping.Tables.get_mut("workflow").unwrap();
+    let row = workflows.iter_mut().find(|row| row["id"] == json!(backed_up_id)).unwrap();
+    row.insert("title".to_string(), json!("Another workflow"));
+    assert!(matches!(restore_backup(overlapping, RestoreMode::Merge).await, Err(CustomError::InvalidBackup(_))));
+    assert_eq!(find_workflow(backed_up_id).await.unwrap().Title, "Backed up");
+    let mut added = backup.clone();
+    let print_jobs = added.Tables.get_mut("printjob").unwrap();
+    let mut row = print_jobs[0].clone();
+    row.insert("id".to_string(), json!(after_restore + 1));
+    print_jobs.push(row);
+    let summary = restore_backup(added, RestoreMode::Merge).await.unwrap();
+    assert_eq!(summary.Rows["printjob"], 1);
+    assert_eq!(find_print_job(after_restore + 1).await.unwrap().Title, restored.Title);
+
+    // Archives which don't match the database are rejected without changing anything
+    let mut newer = backup.clone();
+    newer.SchemaVersion += 1;
+    assert!(matches!(restore_backup(newer, RestoreMode::Replace).await, Err(CustomError::InvalidBackup(_))));
+    let mut unknown_column = backup.clone();
+    unknown_column.Tables.get_mut("printjob").unwrap()[0].insert("unknown".to_string(), json!(1));
+    assert!(matches!(restore_backup(unknown_column, RestoreMode::Replace).await, Err(CustomError::InvalidBackup(_))));
+    let mut missing_table = backup.clone();
+    missing_table.Tables.remove("audit_log");
+    assert!(matches!(restore_backup(missing_table, RestoreMode::Replace).await, Err(CustomError::InvalidBackup(_))));
+    let mut missing_column = backup.clone();
+    missing_column.Tables.get_mut("printjob").unwrap()[0].remove("copies");
+    assert!(matches!(restore_backup(missing_column, RestoreMode::Replace).await, Err(CustomError::InvalidBackup(_))));
+    assert_eq!(find_print_job(after_restore).await.unwrap().Title, "After restore");
+
+    // Cached workflows are dropped when a workflow is deleted or restored
+    let listed = |id| async move { query_workflows().await.unwrap().iter().any(|workflow| workflow.id == Some(id)) };
+    assert!(listed(backed_up_id).await);
+    let trashed_workflow = StoredItem::new(ItemType::Workflow, backed_up_id);
+    delete_item(trashed_workflow.clone(), DeleteOptions::default()).await.unwrap();
+    assert!(!listed(backed_up_id).await);
+    assert!(find_workflow(backed_up_id).await.is_err());
+    restore_item(trashed_workflow).await.unwrap();
+    assert!(listed(backed_up_id).await);
+    assert_eq!(find_workflow(backed_up_id).await.unwrap().Steps[1].machine_count, 2);
+
+    assert!(repository.pool_metrics().Checkouts > 0);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_sqlite_repository() {
+    check_repository(open_repository(&DatabaseLocation::InMemory).unwrap()).await;
+    open_database(DatabaseLocation::InMemory).unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_postgres_repository() {
+    let url = match std::env::var(TEST_POSTGRES_ENV) {
+        Ok(url) => url,
+        Err(_) => {
+            println!("Skipping the PostgreSQL conformance tests, since {TEST_POSTGRES_ENV} isn't set");
+            return;
+        },
+    };
+
+    // Start from an empty database
+    let reset_url = url.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut client = postgres::Client::connect(&reset_url, postgres::NoTls).unwrap();
+        client.batch_execute("DROP SCHEMA public CASCADE; CREATE SCHEMA public;").unwrap();
+    }).await.unwrap();
+
+    let repository = open_repository(&DatabaseLocation::Postgres(url)).unwrap();
+    check_repository(repository.clone()).await;
+
+    // Backups of a SQLite database can be restored into PostgreSQL
+    open_database(DatabaseLocation::InMemory).unwrap();
+    setup_database().await.unwrap();
+    seed_database().await.unwrap();
+    insert_print_job(print_job("From SQLite")).await.unwrap();
+    let backup = create_backup().await.unwrap();
+    set_repository(repository);
+    restore_backup(backup, RestoreMode::Replace).await.unwrap();
+    let print_jobs = query_print_jobs().await.unwrap();
+    assert_eq!(print_jobs.len(), 1);
+    assert!(print_jobs[0].Duplex);
+    open_database(DatabaseLocation::InMemory).unwrap();
+}
+
+#[test]
+fn test_postgres_migrations_match() {
+    assert_eq!(MIGRATIONS.len(), POSTGRES_MIGRATIONS.len());
+    for (sqlite, postgres) in MIGRATIONS.iter().zip(POSTGRES_MIGRATIONS) {
+        assert_eq!(sqlite.version, postgres.version);
+        assert_eq!(sqlite.name, postgres.name);
+    }
+}